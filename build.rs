@@ -1,3 +1,5 @@
 fn main() {
-    println!("cargo:rustc-link-lib=framework=IOKit");
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("macos") {
+        println!("cargo:rustc-link-lib=framework=IOKit");
+    }
 }
@@ -2,16 +2,194 @@
 
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+use crate::state::Policy;
+
+const DEFAULT_CONNECT_RETRIES: u32 = 10;
+const DEFAULT_RETRY_DELAY_SECS: u64 = 2;
+const DEFAULT_SETTLE_MS: u64 = 1000;
+const DEFAULT_GRACE_MS: u64 = 5000;
+const DEFAULT_RECONCILE_SECS: u64 = 30;
+const DEFAULT_HOOK_TIMEOUT_SECS: u64 = 30;
+
 /// Runtime configuration loaded from a JSON file.
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// A config either describes a single dock through the top-level `dock_uid` / `ipad_name`
+/// fields, or several docks through a `profiles` list.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Config {
     /// Thunderbolt dock UID as a hex string, e.g. `"0x003DA86E85A8CB00"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dock_uid: Option<String>,
+    /// Optional iPad name to target. If `None`, the first available Sidecar device is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipad_name: Option<String>,
+    /// Options for the single-dock form. Ignored when `profiles` is used.
+    #[serde(flatten)]
+    pub options: ProfileOptions,
+    /// One entry per dock, each with its own iPad and options.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<Profile>,
+}
+
+/// A dock paired with the iPad it should extend to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    /// Name used in log output. Defaults to the dock UID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Thunderbolt dock UID as a hex string.
     pub dock_uid: String,
     /// Optional iPad name to target. If `None`, the first available Sidecar device is used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ipad_name: Option<String>,
+    #[serde(flatten)]
+    pub options: ProfileOptions,
+}
+
+/// Per-profile behaviour.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileOptions {
+    /// Number of times to look for the iPad before giving up on a connect.
+    pub connect_retries: u32,
+    /// Delay between connect attempts, in seconds.
+    pub retry_delay_secs: u64,
+    /// How long the dock must stay attached before Sidecar is connected, in milliseconds.
+    pub settle_ms: u64,
+    /// How long the dock may be absent before Sidecar is disconnected, in milliseconds.
+    /// Re-plugging within this window keeps the existing session.
+    pub grace_ms: u64,
+    /// Whether to disconnect Sidecar before the Mac sleeps. It reconnects on wake either
+    /// way if the dock is still attached.
+    pub disconnect_on_sleep: bool,
+    /// How often to check that Sidecar is still connected while the dock is attached, in
+    /// seconds. A dropped session is reconnected. `0` turns the checks off.
+    pub reconcile_secs: u64,
+    /// How long to leave Sidecar disconnected after someone disconnects it by hand, in
+    /// seconds. When unset, it stays disconnected until the dock is next attached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manual_cooldown_secs: Option<u64>,
+    /// Whether to disconnect Sidecar when the daemon stops, e.g. on `launchctl stop`.
+    /// Sessions the daemon did not start are left alone either way.
+    pub disconnect_on_shutdown: bool,
+    #[serde(flatten)]
+    pub hooks: Hooks,
+}
+
+/// Shell commands run when the dock or Sidecar changes state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hooks {
+    /// Run when the dock is attached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_connect: Option<Hook>,
+    /// Run when the dock is removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_disconnect: Option<Hook>,
+    /// Run once Sidecar has connected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_sidecar_connected: Option<Hook>,
+    /// Run once every Sidecar connect attempt has failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_sidecar_failed: Option<Hook>,
+}
+
+/// A shell command, given either as a plain string or as an object with options.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "HookSpec")]
+pub struct Hook {
+    /// Command line passed to `/bin/sh -c`.
+    pub command: String,
+    /// How long the command may run before it is killed, in seconds.
+    pub timeout_secs: u64,
+    /// What to do when the command fails or times out.
+    pub on_failure: FailurePolicy,
+}
+
+/// How to handle a failed hook.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Log a warning.
+    #[default]
+    Warn,
+    /// Log at debug level only.
+    Ignore,
+    /// Run the command up to this many more times, then log a warning.
+    Retry(u32),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HookSpec {
+    Command(String),
+    Full {
+        command: String,
+        #[serde(default = "default_hook_timeout")]
+        timeout_secs: u64,
+        #[serde(default)]
+        on_failure: FailurePolicy,
+    },
+}
+
+fn default_hook_timeout() -> u64 {
+    DEFAULT_HOOK_TIMEOUT_SECS
+}
+
+impl From<HookSpec> for Hook {
+    fn from(spec: HookSpec) -> Self {
+        match spec {
+            HookSpec::Command(command) => Hook {
+                command,
+                timeout_secs: DEFAULT_HOOK_TIMEOUT_SECS,
+                on_failure: FailurePolicy::default(),
+            },
+            HookSpec::Full {
+                command,
+                timeout_secs,
+                on_failure,
+            } => Hook {
+                command,
+                timeout_secs,
+                on_failure,
+            },
+        }
+    }
+}
+
+impl Default for ProfileOptions {
+    fn default() -> Self {
+        Self {
+            connect_retries: DEFAULT_CONNECT_RETRIES,
+            retry_delay_secs: DEFAULT_RETRY_DELAY_SECS,
+            settle_ms: DEFAULT_SETTLE_MS,
+            grace_ms: DEFAULT_GRACE_MS,
+            disconnect_on_sleep: false,
+            reconcile_secs: DEFAULT_RECONCILE_SECS,
+            manual_cooldown_secs: None,
+            disconnect_on_shutdown: false,
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+impl ProfileOptions {
+    /// Delays and retry limits for the state machine.
+    pub fn policy(&self) -> Policy {
+        Policy {
+            settle: Duration::from_millis(self.settle_ms),
+            grace: Duration::from_millis(self.grace_ms),
+            max_attempts: self.connect_retries.max(1),
+            retry_delay: Duration::from_secs(self.retry_delay_secs),
+            disconnect_on_sleep: self.disconnect_on_sleep,
+            reconcile: Duration::from_secs(self.reconcile_secs),
+            manual_cooldown: self.manual_cooldown_secs.map(Duration::from_secs),
+            disconnect_on_shutdown: self.disconnect_on_shutdown,
+        }
+    }
 }
 
 impl Config {
@@ -33,7 +211,6 @@ impl Config {
     }
 
     /// Save configuration to a JSON file (pretty-printed).
-    #[allow(dead_code)]
     pub fn save(&self, path: &Path) -> Result<(), String> {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)
@@ -45,13 +222,53 @@ impl Config {
             .map_err(|e| format!("Failed to write config to {}: {e}", path.display()))
     }
 
+    /// Parse the top-level `dock_uid` from its hex string representation to a `u64`.
+    pub fn dock_uid_u64(&self) -> Result<u64, String> {
+        match &self.dock_uid {
+            Some(uid) => parse_uid(uid),
+            None => Err("No top-level dock_uid configured".into()),
+        }
+    }
+
+    /// The profiles to monitor, with the single-dock form expanded into one profile.
+    ///
+    /// Every returned profile has a valid `dock_uid`.
+    pub fn profiles(&self) -> Result<Vec<Profile>, String> {
+        let profiles = match (&self.dock_uid, self.profiles.is_empty()) {
+            (Some(_), false) => {
+                return Err("Config sets both dock_uid and profiles; use one or the other".into());
+            }
+            (None, true) => return Err("Config must set either dock_uid or profiles".into()),
+            (Some(uid), true) => vec![Profile {
+                name: None,
+                dock_uid: uid.clone(),
+                ipad_name: self.ipad_name.clone(),
+                options: self.options.clone(),
+            }],
+            (None, false) => self.profiles.clone(),
+        };
+
+        for profile in &profiles {
+            profile.dock_uid_u64()?;
+        }
+        Ok(profiles)
+    }
+}
+
+impl Profile {
     /// Parse `dock_uid` from its hex string representation to a `u64`.
     pub fn dock_uid_u64(&self) -> Result<u64, String> {
-        let s = self
-            .dock_uid
-            .trim()
-            .trim_start_matches("0x")
-            .trim_start_matches("0X");
-        u64::from_str_radix(s, 16).map_err(|e| format!("Invalid dock_uid '{}': {e}", self.dock_uid))
+        parse_uid(&self.dock_uid)
+    }
+
+    /// Name used to identify this profile in log output.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.dock_uid)
     }
 }
+
+/// Parse a dock UID from its hex string representation, with or without a `0x` prefix.
+pub(crate) fn parse_uid(uid: &str) -> Result<u64, String> {
+    let s = uid.trim().trim_start_matches("0x").trim_start_matches("0X");
+    u64::from_str_radix(s, 16).map_err(|e| format!("Invalid dock_uid '{uid}': {e}"))
+}
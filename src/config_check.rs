@@ -0,0 +1,525 @@
+//! Thorough checking of a config file, for `config validate`.
+//!
+//! [`Config::load`] stops at the first problem and ignores fields it does not know.
+//! [`check`] instead reports everything it finds, each with a line and column, and also
+//! flags things that load fine but probably do not do what was meant.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::config::{Config, FailurePolicy, parse_uid};
+use crate::sidecar::normalise_quotes;
+
+const OPTIONS: &[&str] = &[
+    "connect_retries",
+    "retry_delay_secs",
+    "settle_ms",
+    "grace_ms",
+    "disconnect_on_sleep",
+    "reconcile_secs",
+    "manual_cooldown_secs",
+    "disconnect_on_shutdown",
+];
+const HOOKS: &[&str] = &[
+    "on_connect",
+    "on_disconnect",
+    "on_sidecar_connected",
+    "on_sidecar_failed",
+];
+const HOOK_FIELDS: &[&str] = &["command", "timeout_secs", "on_failure"];
+
+/// How bad a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The daemon will refuse to start with this config.
+    Error,
+    /// The config loads, but likely not as intended.
+    Warning,
+}
+
+/// A problem found in a config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// 1-based line of the offending text.
+    pub line: usize,
+    /// 1-based column, in characters.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}: {severity}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Every problem in the config `text`, in the order they appear.
+///
+/// When `devices` lists the Sidecar devices currently visible, configured iPad names are
+/// checked against it.
+pub fn check(text: &str, devices: Option<&[String]>) -> Vec<Diagnostic> {
+    if let Err(e) = serde_json::from_str::<Value>(text) {
+        return vec![Diagnostic {
+            severity: Severity::Error,
+            line: e.line(),
+            column: e.column(),
+            message: format!("Invalid JSON: {}", strip_position(&e)),
+        }];
+    }
+    let Some(root) = Parser::new(text).document() else {
+        return Vec::new();
+    };
+
+    let mut checker = Checker {
+        text,
+        devices,
+        found: Vec::new(),
+    };
+    checker.root(&root);
+
+    // Anything the checks above missed still surfaces, so a clean report always means
+    // the daemon will start.
+    if !checker.found.iter().any(|d| d.severity == Severity::Error) {
+        match serde_json::from_str::<Config>(text) {
+            Err(e) => checker.error_at_line(e.line(), e.column(), strip_position(&e)),
+            Ok(cfg) => {
+                if let Err(e) = cfg.profiles() {
+                    checker.error(root.at, e);
+                }
+            }
+        }
+    }
+
+    let mut found = checker.found;
+    found.sort_by_key(|d| (d.line, d.column));
+    found
+}
+
+/// The closest of `candidates` to a mistyped `name`, if any is close enough. A name that
+/// is the start of a candidate, like `grace` for `grace_ms`, counts as close.
+pub fn suggest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    candidates
+        .iter()
+        .filter_map(|c| {
+            let candidate = c.to_lowercase();
+            let distance = edit_distance(&name, &candidate);
+            let close = distance <= (c.len() / 3).max(1)
+                || (name.len() >= 4 && candidate.starts_with(&name));
+            close.then_some((distance, *c))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, c)| c)
+}
+
+/// serde_json appends " at line L column C" to its messages; it is reported separately.
+fn strip_position(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    match message.rfind(" at line ") {
+        Some(i) => message[..i].to_string(),
+        None => message,
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+struct Checker<'a> {
+    text: &'a str,
+    devices: Option<&'a [String]>,
+    found: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn root(&mut self, root: &Node) {
+        let Kind::Object(members) = &root.kind else {
+            self.error(root.at, "The config must be a JSON object".into());
+            return;
+        };
+
+        let mut known = vec!["dock_uid", "ipad_name", "profiles"];
+        known.extend(OPTIONS);
+        known.extend(HOOKS);
+        self.fields(members, &known);
+
+        let profiles = members.iter().find(|m| m.key == "profiles");
+        let uses_profiles =
+            profiles.is_some_and(|m| !matches!(&m.value.kind, Kind::Array(a) if a.is_empty()));
+        let dock_uid = members.iter().find(|m| m.key == "dock_uid");
+        match (dock_uid, uses_profiles) {
+            (Some(uid), true) => self.error(
+                uid.at,
+                "Both dock_uid and profiles are set; use one or the other".into(),
+            ),
+            (None, false) => self.error(
+                root.at,
+                "Set either dock_uid or a non-empty profiles list".into(),
+            ),
+            (None, true) => {
+                for member in members.iter().filter(|m| m.key != "profiles") {
+                    if known.contains(&member.key.as_str()) {
+                        self.warning(
+                            member.at,
+                            format!(
+                                "'{}' is ignored when profiles are used; set it in each profile",
+                                member.key
+                            ),
+                        );
+                    }
+                }
+            }
+            (Some(_), false) => {}
+        }
+
+        if let Some(profiles) = profiles {
+            self.profiles(&profiles.value);
+        }
+    }
+
+    fn profiles(&mut self, node: &Node) {
+        let Kind::Array(items) = &node.kind else {
+            self.error(node.at, "profiles must be a list".into());
+            return;
+        };
+        let mut known = vec!["name", "dock_uid", "ipad_name"];
+        known.extend(OPTIONS);
+        known.extend(HOOKS);
+
+        let mut labels = HashSet::new();
+        for item in items {
+            let Kind::Object(members) = &item.kind else {
+                self.error(item.at, "Each profile must be an object".into());
+                continue;
+            };
+            self.fields(members, &known);
+
+            let field = |key| members.iter().find(|m| m.key == key);
+            let Some(uid) = field("dock_uid") else {
+                self.error(item.at, "Profile is missing dock_uid".into());
+                continue;
+            };
+            let label = field("name").unwrap_or(uid);
+            if let Kind::Scalar(Value::String(label_text)) = &label.value.kind
+                && !labels.insert(label_text.clone())
+            {
+                self.warning(
+                    label.value.at,
+                    format!(
+                        "Another profile is also called '{label_text}'; \
+                         commands naming it will be ambiguous"
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Report duplicate and unknown fields among `members`, then check the known ones.
+    fn fields(&mut self, members: &[Member], known: &[&str]) {
+        for member in self.known_fields(members, known) {
+            let key = member.key.as_str();
+            let value = &member.value;
+            let json = value.to_value();
+            match key {
+                "dock_uid" => match json.as_str() {
+                    Some(uid) => {
+                        if let Err(e) = parse_uid(uid) {
+                            self.error(value.at, e);
+                        }
+                    }
+                    None => self.error(value.at, "dock_uid must be a hex string".into()),
+                },
+                "ipad_name" => match json.as_str() {
+                    Some(name) => self.ipad_name(value.at, name),
+                    None => self.error(value.at, "ipad_name must be a string".into()),
+                },
+                "name" if !json.is_string() => {
+                    self.error(value.at, "name must be a string".into());
+                }
+                "connect_retries" => match json.as_u64().filter(|n| u32::try_from(*n).is_ok()) {
+                    Some(0) => self.warning(
+                        value.at,
+                        "connect_retries of 0 still makes one attempt".into(),
+                    ),
+                    Some(_) => {}
+                    None => self.not_a_whole_number(key, value.at, &json),
+                },
+                "disconnect_on_sleep" | "disconnect_on_shutdown" => {
+                    if !json.is_boolean() {
+                        self.error(value.at, format!("{key} must be true or false"));
+                    }
+                }
+                _ if OPTIONS.contains(&key) && json.as_u64().is_none() => {
+                    self.not_a_whole_number(key, value.at, &json);
+                }
+                _ if HOOKS.contains(&key) => self.hook(key, value),
+                _ => {}
+            }
+        }
+    }
+
+    fn hook(&mut self, key: &str, node: &Node) {
+        let members = match &node.kind {
+            Kind::Scalar(Value::String(_)) => return,
+            Kind::Object(members) => members,
+            _ => {
+                self.error(
+                    node.at,
+                    format!("{key} must be a command string or an object with a command"),
+                );
+                return;
+            }
+        };
+        for member in self.known_fields(members, HOOK_FIELDS) {
+            let at = member.value.at;
+            let json = member.value.to_value();
+            match member.key.as_str() {
+                "command" if !json.is_string() => {
+                    self.error(at, "command must be a string".into());
+                }
+                "timeout_secs" if json.as_u64().is_none() => {
+                    self.not_a_whole_number("timeout_secs", at, &json);
+                }
+                "on_failure" => {
+                    if serde_json::from_value::<FailurePolicy>(json).is_err() {
+                        self.error(
+                            at,
+                            r#"on_failure must be "warn", "ignore" or {"retry": N}"#.into(),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !members.iter().any(|m| m.key == "command") {
+            self.error(node.at, format!("{key} is missing its command"));
+        }
+    }
+
+    /// The members whose key is one of `known`, after reporting the rest and any key
+    /// given twice.
+    fn known_fields<'m>(&mut self, members: &'m [Member], known: &[&str]) -> Vec<&'m Member> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for member in members {
+            let key = member.key.as_str();
+            if !seen.insert(key) {
+                self.error(member.at, format!("'{key}' is set more than once"));
+            }
+            if known.contains(&key) {
+                found.push(member);
+                continue;
+            }
+            let message = match suggest(key, known) {
+                Some(close) => format!("Unknown field '{key}'; did you mean '{close}'?"),
+                None => format!("Unknown field '{key}'; it is ignored"),
+            };
+            self.warning(member.at, message);
+        }
+        found
+    }
+
+    fn ipad_name(&mut self, at: usize, name: &str) {
+        let Some(devices) = self.devices else {
+            return;
+        };
+        let wanted = normalise_quotes(name);
+        if devices.iter().any(|d| normalise_quotes(d) == wanted) {
+            return;
+        }
+        let visible = if devices.is_empty() {
+            "none are visible".to_string()
+        } else {
+            format!("visible: {}", devices.join(", "))
+        };
+        let names: Vec<&str> = devices.iter().map(String::as_str).collect();
+        let message = match suggest(name, &names) {
+            Some(close) => format!("No Sidecar device is named '{name}'; did you mean '{close}'?"),
+            None => format!("No Sidecar device is named '{name}' ({visible})"),
+        };
+        self.warning(at, message);
+    }
+
+    fn not_a_whole_number(&mut self, key: &str, at: usize, value: &Value) {
+        self.error(at, format!("{key} must be a whole number, not {value}"));
+    }
+
+    fn error(&mut self, at: usize, message: String) {
+        self.push(Severity::Error, at, message);
+    }
+
+    fn warning(&mut self, at: usize, message: String) {
+        self.push(Severity::Warning, at, message);
+    }
+
+    fn error_at_line(&mut self, line: usize, column: usize, message: String) {
+        self.found.push(Diagnostic {
+            severity: Severity::Error,
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn push(&mut self, severity: Severity, at: usize, message: String) {
+        let before = &self.text[..at];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        self.found.push(Diagnostic {
+            severity,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        });
+    }
+}
+
+/// A JSON value and the byte offset it starts at.
+struct Node {
+    at: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Object(Vec<Member>),
+    Array(Vec<Node>),
+    Scalar(Value),
+}
+
+/// An object member, with the offset of its key.
+struct Member {
+    key: String,
+    at: usize,
+    value: Node,
+}
+
+impl Node {
+    fn to_value(&self) -> Value {
+        match &self.kind {
+            Kind::Object(members) => Value::Object(
+                members
+                    .iter()
+                    .map(|m| (m.key.clone(), m.value.to_value()))
+                    .collect(),
+            ),
+            Kind::Array(items) => Value::Array(items.iter().map(Node::to_value).collect()),
+            Kind::Scalar(value) => value.clone(),
+        }
+    }
+}
+
+/// Reads JSON that serde_json has already accepted, keeping where each value and key is.
+/// Unlike [`Value`], duplicate keys are kept.
+struct Parser<'a> {
+    text: &'a str,
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, at: 0 }
+    }
+
+    fn document(&mut self) -> Option<Node> {
+        self.value()
+    }
+
+    fn value(&mut self) -> Option<Node> {
+        self.skip_whitespace();
+        let at = self.at;
+        let kind = match self.peek()? {
+            b'{' => Kind::Object(self.object()?),
+            b'[' => Kind::Array(self.array()?),
+            b'"' => Kind::Scalar(Value::String(self.string()?)),
+            _ => {
+                let rest = &self.text[at..];
+                let len = rest
+                    .find(|c: char| matches!(c, ',' | ']' | '}') || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                self.at += len;
+                Kind::Scalar(serde_json::from_str(&rest[..len]).ok()?)
+            }
+        };
+        Some(Node { at, kind })
+    }
+
+    fn object(&mut self) -> Option<Vec<Member>> {
+        self.at += 1;
+        let mut members = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek()? {
+                b'}' => {
+                    self.at += 1;
+                    return Some(members);
+                }
+                b',' => self.at += 1,
+                _ => {
+                    let at = self.at;
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.at += 1; // ':'
+                    let value = self.value()?;
+                    members.push(Member { key, at, value });
+                }
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Vec<Node>> {
+        self.at += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek()? {
+                b']' => {
+                    self.at += 1;
+                    return Some(items);
+                }
+                b',' => self.at += 1,
+                _ => items.push(self.value()?),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let bytes = self.text.as_bytes();
+        let start = self.at;
+        let mut end = start + 1;
+        while *bytes.get(end)? != b'"' {
+            end += if bytes[end] == b'\\' { 2 } else { 1 };
+        }
+        self.at = end + 1;
+        serde_json::from_str(&self.text[start..self.at]).ok()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.at..];
+        self.at += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.at).copied()
+    }
+}
@@ -0,0 +1,293 @@
+//! Notice edits to the config file.
+//!
+//! Uses inotify on Linux and kqueue on macOS, watching the file's directory so that
+//! editors which save by replacing the file are noticed too. Elsewhere, or when the
+//! native watcher cannot be set up, the file is polled. Bursts of events are coalesced,
+//! and only changes to the file's contents are reported.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+/// How often the file is checked when polling.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long events must stop arriving before the file is read again.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Signal the returned channel whenever the contents of `path` change.
+pub fn watch(path: &Path) -> Result<Receiver<()>, String> {
+    match native::watch(path) {
+        Ok(events) => changes(path, events, DEBOUNCE),
+        Err(e) => {
+            log::warn!("{e}; checking {} for changes instead", path.display());
+            poll(path, POLL_INTERVAL)
+        }
+    }
+}
+
+/// Like [`watch`], but reading the file every `interval` instead of relying on the OS.
+pub fn poll(path: &Path, interval: Duration) -> Result<Receiver<()>, String> {
+    changes(path, ticks(interval)?, Duration::ZERO)
+}
+
+/// Report content changes to `path`, re-reading it once `events` have been quiet for
+/// `debounce`.
+fn changes(path: &Path, events: Receiver<()>, debounce: Duration) -> Result<Receiver<()>, String> {
+    let (tx, rx) = mpsc::channel();
+    let path = path.to_path_buf();
+    let mut last = fs::read(&path).ok();
+    thread::Builder::new()
+        .name("config-watch".into())
+        .spawn(move || {
+            while events.recv().is_ok() {
+                while !debounce.is_zero() && events.recv_timeout(debounce).is_ok() {}
+                // A missing file is usually an editor halfway through replacing it.
+                let Ok(current) = fs::read(&path) else {
+                    continue;
+                };
+                if last.as_ref() != Some(&current) {
+                    last = Some(current);
+                    if tx.send(()).is_err() {
+                        return;
+                    }
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start config watch thread: {e}"))?;
+    Ok(rx)
+}
+
+/// An event every `interval`.
+fn ticks(interval: Duration) -> Result<Receiver<()>, String> {
+    let (tx, rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("config-poll".into())
+        .spawn(move || {
+            loop {
+                thread::sleep(interval);
+                if tx.send(()).is_err() {
+                    return;
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start config poll thread: {e}"))?;
+    Ok(rx)
+}
+
+/// The directory holding `path`.
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Send `()` each time `read_event` reports an event, on a thread named `name`.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn spawn_reader(
+    name: &str,
+    tx: Sender<()>,
+    mut read_event: impl FnMut() -> std::io::Result<()> + Send + 'static,
+) -> Result<(), String> {
+    thread::Builder::new()
+        .name(name.into())
+        .spawn(move || {
+            loop {
+                match read_event() {
+                    Ok(()) => {
+                        if tx.send(()).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        log::error!("Watching the config file failed: {e}");
+                        return;
+                    }
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start {name} thread: {e}"))?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod native {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::mpsc::{self, Receiver};
+
+    /// Directory changes that can mean the config file was written or replaced.
+    const MASK: u32 = libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_TO
+        | libc::IN_MOVED_FROM
+        | libc::IN_CREATE
+        | libc::IN_DELETE;
+
+    /// An event whenever an entry in the directory holding `path` changes.
+    pub fn watch(path: &Path) -> Result<Receiver<()>, String> {
+        let dir = super::parent_dir(path);
+        let c_dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|_| format!("Invalid path {}", dir.display()))?;
+
+        let inotify = unsafe {
+            let fd = libc::inotify_init1(libc::IN_CLOEXEC);
+            if fd < 0 {
+                return Err(format!(
+                    "Failed to start inotify: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), c_dir.as_ptr(), MASK) } < 0 {
+            return Err(format!(
+                "Failed to watch {}: {}",
+                dir.display(),
+                io::Error::last_os_error()
+            ));
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut buf = vec![0u8; 4096];
+        super::spawn_reader("config-inotify", tx, move || {
+            let n = unsafe { libc::read(inotify.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })?;
+        Ok(rx)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod native {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::ptr;
+    use std::sync::mpsc::{self, Receiver};
+
+    /// File changes worth re-reading for; deletes and renames also mean the descriptor
+    /// must be reopened.
+    const FILE_NOTES: u32 = libc::NOTE_WRITE
+        | libc::NOTE_EXTEND
+        | libc::NOTE_ATTRIB
+        | libc::NOTE_DELETE
+        | libc::NOTE_RENAME;
+
+    /// An event whenever the file at `path` is written, or an entry in its directory
+    /// changes.
+    pub fn watch(path: &Path) -> Result<Receiver<()>, String> {
+        let dir = super::parent_dir(path);
+        let kqueue = unsafe {
+            let fd = libc::kqueue();
+            if fd < 0 {
+                return Err(format!(
+                    "Failed to start kqueue: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        let dir_fd = open(&dir).map_err(|e| format!("Failed to watch {}: {e}", dir.display()))?;
+        register(&kqueue, &dir_fd, libc::NOTE_WRITE)
+            .map_err(|e| format!("Failed to watch {}: {e}", dir.display()))?;
+
+        let path = path.to_path_buf();
+        // Closing a descriptor removes its registration, so a replaced file is dropped
+        // and the new one registered in its place.
+        let mut file_fd = open(&path).ok();
+        if let Some(fd) = &file_fd {
+            register(&kqueue, fd, FILE_NOTES)
+                .map_err(|e| format!("Failed to watch config: {e}"))?;
+        }
+
+        let (tx, rx) = mpsc::channel();
+        super::spawn_reader("config-kqueue", tx, move || {
+            let _keep_dir_open = &dir_fd;
+            let mut event: libc::kevent = unsafe { std::mem::zeroed() };
+            let n = unsafe {
+                libc::kevent(
+                    kqueue.as_raw_fd(),
+                    ptr::null(),
+                    0,
+                    &mut event,
+                    1,
+                    ptr::null(),
+                )
+            };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let replaced = match &file_fd {
+                Some(fd) => {
+                    event.ident == fd.as_raw_fd() as libc::uintptr_t
+                        && event.fflags & (libc::NOTE_DELETE | libc::NOTE_RENAME) != 0
+                }
+                None => true,
+            };
+            if replaced {
+                file_fd = open(&path).ok();
+                if let Some(fd) = &file_fd {
+                    register(&kqueue, fd, FILE_NOTES)?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(rx)
+    }
+
+    /// Open `path` for event notifications only.
+    fn open(path: &Path) -> io::Result<OwnedFd> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_EVTONLY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    fn register(kqueue: &OwnedFd, fd: &OwnedFd, notes: u32) -> io::Result<()> {
+        let mut change: libc::kevent = unsafe { std::mem::zeroed() };
+        change.ident = fd.as_raw_fd() as libc::uintptr_t;
+        change.filter = libc::EVFILT_VNODE;
+        change.flags = libc::EV_ADD | libc::EV_ENABLE | libc::EV_CLEAR;
+        change.fflags = notes;
+        let rc = unsafe {
+            libc::kevent(
+                kqueue.as_raw_fd(),
+                &change,
+                1,
+                ptr::null_mut(),
+                0,
+                ptr::null(),
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod native {
+    use std::path::Path;
+    use std::sync::mpsc::Receiver;
+
+    pub fn watch(_path: &Path) -> Result<Receiver<()>, String> {
+        Err("No file change notifications on this platform".into())
+    }
+}
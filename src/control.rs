@@ -0,0 +1,468 @@
+//! Local control socket for a running daemon.
+//!
+//! The daemon listens on a Unix domain socket (see [`default_socket_path`]) that only its
+//! user can reach. The protocol is JSON lines: every line sent is a [`Request`] such as
+//! `{"command": "connect", "profile": "desk"}` and is answered by one [`Response`] line.
+//! After `subscribe`, the connection instead receives a status snapshot followed by a
+//! [`Response::Changed`] for every state change, and a [`Response::Reloaded`] for every
+//! config reload, until it is closed.
+
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dock_event::Closer;
+use crate::sidecar::SidecarError;
+use crate::state::Notice;
+
+/// How long a client waits for an answer. Connect and disconnect requests are answered
+/// once Sidecar has responded, which can take up to 30s.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Something a client asks the daemon to do.
+///
+/// Requests that take a `profile` match it against profile names (or the dock UID of
+/// unnamed profiles). Without one, `connect` and `disconnect` require a single configured
+/// profile, while `pause` and `resume` apply to every profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// Report the state of every profile.
+    Status,
+    /// Connect Sidecar now. Without the dock attached, or while paused, the session is
+    /// not tracked and the next dock removal leaves it alone.
+    Connect {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Disconnect Sidecar now. It stays disconnected until the dock is next attached.
+    Disconnect {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Stop acting on dock changes.
+    Pause {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Start acting on dock changes again.
+    Resume {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+    },
+    /// Re-read the config file. The current config is kept if the file is invalid.
+    Reload,
+    /// Receive a status snapshot, then every state change.
+    Subscribe,
+    /// Report what the daemon found wrong with Sidecar sessions and put right, oldest
+    /// first.
+    Corrections,
+    /// Stop the daemon, as SIGTERM does. Answered once it is ready to exit.
+    Shutdown,
+}
+
+/// The daemon's answer to a [`Request`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    /// The request was carried out.
+    Ok,
+    /// The request failed.
+    Error {
+        message: String,
+        /// Set when a client may want to tell this failure apart.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<ErrorKind>,
+    },
+    /// The state of every profile.
+    Status { profiles: Vec<ProfileStatus> },
+    /// A profile changed state. Only sent to subscribers.
+    Changed {
+        profile: ProfileStatus,
+        notices: Vec<Notice>,
+    },
+    /// Recent corrections, oldest first.
+    Corrections { corrections: Vec<Correction> },
+    /// The config was reloaded; the state of every profile under it. Only sent to
+    /// subscribers.
+    Reloaded { profiles: Vec<ProfileStatus> },
+}
+
+/// Failures that [`Response::Error`] identifies, so that clients can react to them as if
+/// they had called Sidecar themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Sidecar could not be reached.
+    Unavailable,
+    /// Sidecar does not offer the profile's iPad.
+    NotFound,
+}
+
+impl Response {
+    /// A [`Response::Error`] of no particular kind.
+    pub fn error(message: impl Into<String>) -> Self {
+        Response::Error {
+            message: message.into(),
+            kind: None,
+        }
+    }
+
+    /// A [`Response::Error`] for a failed Sidecar request.
+    pub fn sidecar_error(error: &SidecarError) -> Self {
+        let kind = match error {
+            SidecarError::Unavailable(_) => Some(ErrorKind::Unavailable),
+            SidecarError::DeviceNotFound { .. } => Some(ErrorKind::NotFound),
+            _ => None,
+        };
+        Response::Error {
+            message: error.to_string(),
+            kind,
+        }
+    }
+}
+
+/// Where one profile currently stands.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileStatus {
+    /// Profile name, or its dock UID when unnamed.
+    pub name: String,
+    /// Dock UID, formatted as `0x` and 16 hex digits.
+    pub dock_uid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipad_name: Option<String>,
+    /// State machine state, e.g. `"idle"`, `"connected"` or `"paused"`.
+    pub state: String,
+    pub dock_present: bool,
+    /// Whether the profile's iPad has a Sidecar session, including one started by hand.
+    /// Only `status` asks Sidecar; other messages report what the daemon last saw.
+    pub sidecar_connected: bool,
+}
+
+/// A Sidecar session the daemon found in the wrong state and set about repairing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Correction {
+    /// When it was found, in seconds since the Unix epoch.
+    pub at: u64,
+    /// Profile name, or its dock UID when unnamed.
+    pub profile: String,
+    /// What was wrong, e.g. `"Sidecar session dropped"`.
+    pub problem: String,
+    /// How long the daemon waited before reconnecting, in seconds.
+    pub retry_in_secs: u64,
+}
+
+/// A request read from the socket and where to send its response(s).
+#[derive(Debug)]
+pub struct Incoming {
+    pub request: Request,
+    pub reply: Sender<Response>,
+}
+
+/// Default socket path: `$XDG_RUNTIME_DIR/sidecar-on-dock/control.sock`, or a per-user
+/// directory in the temporary directory when `XDG_RUNTIME_DIR` is unset (as on macOS).
+pub fn default_socket_path() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        Some(runtime) => PathBuf::from(runtime).join("sidecar-on-dock"),
+        None => std::env::temp_dir().join(format!("sidecar-on-dock-{}", current_uid())),
+    };
+    dir.join("control.sock")
+}
+
+fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// A bound control socket.
+#[derive(Debug)]
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Server {
+    /// Bind a socket at `path` that only the current user can connect to.
+    ///
+    /// A missing parent directory is created with mode 0700; an existing one must belong
+    /// to the current user. A socket left behind by a previous run is replaced, but one
+    /// that is still accepting connections is an error.
+    pub fn bind(path: &Path) -> Result<Self, String> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            prepare_dir(dir)?;
+        }
+        if fs::symlink_metadata(path).is_ok() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!(
+                    "Another daemon is already listening on {}",
+                    path.display()
+                ));
+            }
+            fs::remove_file(path)
+                .map_err(|e| format!("Failed to remove stale socket {}: {e}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Failed to listen on {}: {e}", path.display()))?;
+        fs::set_permissions(path, Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict {}: {e}", path.display()))?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accept connections on a background thread, passing every request to `requests`.
+    /// Each connection is served on its own thread.
+    pub fn serve(self, requests: Sender<Incoming>) -> Result<(), String> {
+        thread::Builder::new()
+            .name("control".into())
+            .spawn(move || {
+                for stream in self.listener.incoming() {
+                    let stream = match stream {
+                        Ok(s) => s,
+                        Err(e) => {
+                            log::warn!("Control connection failed: {e}");
+                            continue;
+                        }
+                    };
+                    let requests = requests.clone();
+                    let spawned =
+                        thread::Builder::new()
+                            .name("control-client".into())
+                            .spawn(move || {
+                                if let Err(e) = serve_client(stream, &requests) {
+                                    log::debug!("Control client went away: {e}");
+                                }
+                            });
+                    if let Err(e) = spawned {
+                        log::warn!("Failed to start control client thread: {e}");
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to start control thread: {e}"))?;
+        Ok(())
+    }
+}
+
+/// Create `dir` for the socket, or check that an existing one is ours.
+fn prepare_dir(dir: &Path) -> Result<(), String> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    let metadata =
+        fs::metadata(dir).map_err(|e| format!("Failed to inspect {}: {e}", dir.display()))?;
+    if metadata.uid() != current_uid() {
+        return Err(format!(
+            "Refusing to use {}: it belongs to another user",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Answer requests from one client until it disconnects.
+fn serve_client(stream: UnixStream, requests: &Sender<Incoming>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str::<Request>(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                let message = format!("Invalid request: {e}");
+                write_line(&mut writer, &Response::error(message))?;
+                continue;
+            }
+        };
+
+        let subscribe = request == Request::Subscribe;
+        let (reply, responses) = mpsc::channel();
+        if requests.send(Incoming { request, reply }).is_err() {
+            let message = "The daemon is shutting down".to_string();
+            return write_line(&mut writer, &Response::error(message));
+        }
+        if subscribe {
+            for response in responses {
+                write_line(&mut writer, &response)?;
+            }
+            return Ok(());
+        }
+        let response = responses
+            .recv()
+            .unwrap_or_else(|_| Response::error("The daemon did not answer"));
+        write_line(&mut writer, &response)?;
+    }
+    Ok(())
+}
+
+fn write_line<T: Serialize>(writer: &mut UnixStream, message: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message).map_err(io::Error::other)?;
+    line.push(b'\n');
+    writer.write_all(&line)
+}
+
+/// A connection to a running daemon's control socket.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connect to the socket at `path`.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let stream = UnixStream::connect(path)
+            .map_err(|e| format!("Cannot reach the daemon at {}: {e}", path.display()))?;
+        stream
+            .set_read_timeout(Some(REPLY_TIMEOUT))
+            .map_err(|e| format!("Failed to configure control socket: {e}"))?;
+        let writer = stream
+            .try_clone()
+            .map_err(|e| format!("Failed to configure control socket: {e}"))?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    /// Send `request` and wait for the answer.
+    pub fn request(&mut self, request: &Request) -> Result<Response, String> {
+        write_line(&mut self.writer, request)
+            .map_err(|e| format!("Failed to send request: {e}"))?;
+        self.read()?
+            .ok_or_else(|| "The daemon closed the connection".into())
+    }
+
+    /// The state of every profile.
+    pub fn status(&mut self) -> Result<Vec<ProfileStatus>, String> {
+        match self.request(&Request::Status)? {
+            Response::Status { profiles } => Ok(profiles),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Connect Sidecar for `profile`, waiting for the outcome.
+    pub fn connect(&mut self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(&Request::Connect { profile })
+    }
+
+    /// Disconnect Sidecar for `profile`, waiting for the outcome.
+    pub fn disconnect(&mut self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(&Request::Disconnect { profile })
+    }
+
+    /// Pause `profile`, or every profile.
+    pub fn pause(&mut self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(&Request::Pause { profile })
+    }
+
+    /// Resume `profile`, or every profile.
+    pub fn resume(&mut self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(&Request::Resume { profile })
+    }
+
+    /// What the daemon has put right recently, oldest first.
+    pub fn corrections(&mut self) -> Result<Vec<Correction>, String> {
+        match self.request(&Request::Corrections)? {
+            Response::Corrections { corrections } => Ok(corrections),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Make the daemon re-read its config file.
+    pub fn reload(&mut self) -> Result<(), String> {
+        self.expect_ok(&Request::Reload)
+    }
+
+    /// Stop the daemon, waiting until it is ready to exit.
+    pub fn shutdown(&mut self) -> Result<(), String> {
+        self.expect_ok(&Request::Shutdown)
+    }
+
+    /// Follow state changes. The first item is a [`Response::Status`] snapshot.
+    pub fn subscribe(mut self) -> Result<Subscription, String> {
+        write_line(&mut self.writer, &Request::Subscribe)
+            .map_err(|e| format!("Failed to send request: {e}"))?;
+        self.reader
+            .get_ref()
+            .set_read_timeout(None)
+            .map_err(|e| format!("Failed to configure control socket: {e}"))?;
+        Ok(Subscription { client: self })
+    }
+
+    fn expect_ok(&mut self, request: &Request) -> Result<(), String> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// The next response, or `None` once the daemon has closed the connection.
+    fn read(&mut self) -> Result<Option<Response>, String> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => serde_json::from_str(&line)
+                .map(Some)
+                .map_err(|e| format!("Invalid response: {e}")),
+            Err(e) => Err(format!("Failed to read response: {e}")),
+        }
+    }
+}
+
+/// An error response's message, or a description of a response of the wrong kind.
+pub(crate) fn unexpected(response: Response) -> String {
+    match response {
+        Response::Error { message, .. } => message,
+        other => format!("Unexpected response: {other:?}"),
+    }
+}
+
+/// Responses streamed after [`Client::subscribe`]. Ends when the daemon goes away.
+#[derive(Debug)]
+pub struct Subscription {
+    client: Client,
+}
+
+impl Subscription {
+    /// Something that ends the subscription from another thread, after which the iterator
+    /// returns `None`.
+    pub fn closer(&self) -> Result<Closer, String> {
+        let stream = self
+            .client
+            .writer
+            .try_clone()
+            .map_err(|e| format!("Failed to configure control socket: {e}"))?;
+        Ok(Box::new(move || {
+            let _ = stream.shutdown(Shutdown::Both);
+        }))
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Response, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.client.read().transpose()
+    }
+}
@@ -0,0 +1,317 @@
+//! The dock monitor as a library: configure a [`Daemon`], run it on a thread of your
+//! choosing and drive it from elsewhere through a [`Handle`].
+//!
+//! ```no_run
+//! use sidecar_on_dock::config::Config;
+//! use sidecar_on_dock::daemon::Daemon;
+//!
+//! let config = Config::load(&Config::default_path())?;
+//! let builder = Daemon::builder()
+//!     .config(config)
+//!     .on_event(|profile, notices| println!("{}: {} {notices:?}", profile.name, profile.state));
+//! let handle = builder.handle();
+//! let daemon = builder.build()?;
+//! let running = std::thread::spawn(move || daemon.run());
+//!
+//! println!("{:?}", handle.status()?);
+//! handle.stop();
+//! running.join().unwrap()?;
+//! # Ok::<(), String>(())
+//! ```
+//!
+//! On macOS, Sidecar answers on the main dispatch queue, so the main thread must be
+//! running its run loop, as it does in any app, while the daemon runs elsewhere.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::config::{Config, Profile};
+use crate::control::{self, Correction, Incoming, ProfileStatus, Request, Response, Server};
+use crate::dock_event::DockEventSource;
+use crate::dock_monitor::{self, Reload};
+use crate::sidecar::SidecarBackend;
+use crate::state::Notice;
+
+/// Called with a profile's new status, and what changed, on every state change.
+pub type OnEvent = Box<dyn FnMut(&ProfileStatus, &[Notice]) + Send>;
+
+/// A configured dock monitor, ready to [`run`](Daemon::run).
+pub struct Daemon {
+    profiles: Vec<Profile>,
+    source: Box<dyn DockEventSource>,
+    backend: Box<dyn SidecarBackend>,
+    on_event: Option<OnEvent>,
+    reload: Option<Reload>,
+    subscribers: Vec<Sender<Response>>,
+    server: Option<Server>,
+    handle: Handle,
+    requests: Receiver<Incoming>,
+}
+
+/// Configures a [`Daemon`]. Only the config is required; the dock event source and the
+/// Sidecar backend default to the platform's own.
+pub struct DaemonBuilder {
+    config: Option<Config>,
+    source: Option<Box<dyn DockEventSource>>,
+    backend: Option<Box<dyn SidecarBackend>>,
+    on_event: Option<OnEvent>,
+    reload: Option<Reload>,
+    subscribers: Vec<Sender<Response>>,
+    control_socket: Option<PathBuf>,
+    handle: Handle,
+    requests: Receiver<Incoming>,
+}
+
+/// Talks to a [`Daemon`] from any thread. Requests made before the daemon runs are
+/// answered once it does.
+#[derive(Debug, Clone)]
+pub struct Handle {
+    requests: Sender<Incoming>,
+}
+
+impl Daemon {
+    /// Start configuring a daemon.
+    pub fn builder() -> DaemonBuilder {
+        let (requests_tx, requests) = mpsc::channel();
+        DaemonBuilder {
+            config: None,
+            source: None,
+            backend: None,
+            on_event: None,
+            reload: None,
+            subscribers: Vec::new(),
+            control_socket: None,
+            handle: Handle {
+                requests: requests_tx,
+            },
+            requests,
+        }
+    }
+
+    /// A handle for stopping the daemon or sending it requests.
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    /// Monitor the docks until [`Handle::stop`] is called, or the dock event source ends
+    /// and nothing is left to do.
+    pub fn run(self) -> Result<(), String> {
+        if let Some(server) = self.server {
+            server.serve(self.handle.requests.clone())?;
+        }
+
+        let mut subscribers = self.subscribers;
+        let events = match self.on_event {
+            Some(mut on_event) => {
+                let (changes_tx, changes) = mpsc::channel();
+                subscribers.push(changes_tx);
+                let thread = thread::Builder::new()
+                    .name("daemon-events".into())
+                    .spawn(move || {
+                        for change in changes {
+                            if let Response::Changed { profile, notices } = change {
+                                on_event(&profile, &notices);
+                            }
+                        }
+                    })
+                    .map_err(|e| format!("Failed to start event thread: {e}"))?;
+                Some(thread)
+            }
+            None => None,
+        };
+
+        let result = dock_monitor::monitor(
+            self.profiles,
+            self.source,
+            self.backend,
+            Some(self.requests),
+            self.reload,
+            subscribers,
+        );
+        // The monitor has dropped its subscribers, so the thread ends once it has caught up.
+        if let Some(events) = events {
+            let _ = events.join();
+        }
+        result
+    }
+}
+
+impl DaemonBuilder {
+    /// The profiles to monitor.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Where dock events come from, instead of the platform's hotplug notifications.
+    pub fn source(mut self, source: impl DockEventSource + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// What drives Sidecar, instead of the platform's own.
+    pub fn backend(mut self, backend: impl SidecarBackend + 'static) -> Self {
+        self.backend = Some(Box::new(backend));
+        self
+    }
+
+    /// Call `on_event` on every state change, from a thread of the daemon's.
+    pub fn on_event(
+        mut self,
+        on_event: impl FnMut(&ProfileStatus, &[Notice]) + Send + 'static,
+    ) -> Self {
+        self.on_event = Some(Box::new(on_event));
+        self
+    }
+
+    /// How to load fresh profiles on a `reload` request. Without this, reloading fails.
+    pub fn reload(
+        mut self,
+        reload: impl FnMut() -> Result<Vec<Profile>, String> + Send + 'static,
+    ) -> Self {
+        self.reload = Some(Box::new(reload));
+        self
+    }
+
+    /// Also take requests from a control socket at `path`, as the `run` command does.
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Also send every [`Response::Changed`] and [`Response::Reloaded`] to `subscriber`.
+    #[cfg_attr(not(feature = "stream"), allow(dead_code))]
+    pub(crate) fn subscriber(mut self, subscriber: Sender<Response>) -> Self {
+        self.subscribers.push(subscriber);
+        self
+    }
+
+    /// A handle to the daemon this builder will build, e.g. for a signal handler that must
+    /// be in place before the daemon starts any thread.
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    /// Check the config and set up the source, backend and control socket.
+    pub fn build(self) -> Result<Daemon, String> {
+        let config = self.config.ok_or("No config given")?;
+        let profiles = config.profiles()?;
+        let server = match self.control_socket {
+            Some(path) => {
+                let server = Server::bind(&path)?;
+                log::info!("Control socket: {}", server.path().display());
+                Some(server)
+            }
+            None => None,
+        };
+        let backend = match self.backend {
+            Some(backend) => backend,
+            None => crate::manual::platform_backend().map_err(|e| e.to_string())?,
+        };
+        let source = match self.source {
+            Some(source) => source,
+            None => platform_source()?,
+        };
+        Ok(Daemon {
+            profiles,
+            source,
+            backend,
+            on_event: self.on_event,
+            reload: self.reload,
+            subscribers: self.subscribers,
+            server,
+            handle: self.handle,
+            requests: self.requests,
+        })
+    }
+}
+
+/// Hotplug notifications for this platform.
+fn platform_source() -> Result<Box<dyn DockEventSource>, String> {
+    #[cfg(target_os = "macos")]
+    {
+        Ok(Box::new(crate::iokit_source::IOKitSource::start()?))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Box::new(crate::sysfs_source::SysfsSource::start()?))
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Err("Dock monitoring is not supported on this platform".into())
+    }
+}
+
+impl Handle {
+    /// Send `request` and wait for the answer. [`Request::Subscribe`] only gets the
+    /// snapshot; use [`DaemonBuilder::on_event`] to follow changes.
+    pub fn request(&self, request: Request) -> Result<Response, String> {
+        let (reply, answer) = mpsc::channel();
+        self.requests
+            .send(Incoming { request, reply })
+            .map_err(|_| "The daemon is not running".to_string())?;
+        answer
+            .recv()
+            .map_err(|_| "The daemon stopped before answering".into())
+    }
+
+    /// The state of every profile.
+    pub fn status(&self) -> Result<Vec<ProfileStatus>, String> {
+        match self.request(Request::Status)? {
+            Response::Status { profiles } => Ok(profiles),
+            other => Err(control::unexpected(other)),
+        }
+    }
+
+    /// Connect Sidecar for `profile`, waiting for the outcome.
+    pub fn connect(&self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(Request::Connect { profile })
+    }
+
+    /// Disconnect Sidecar for `profile`, waiting for the outcome.
+    pub fn disconnect(&self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(Request::Disconnect { profile })
+    }
+
+    /// Pause `profile`, or every profile.
+    pub fn pause(&self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(Request::Pause { profile })
+    }
+
+    /// Resume `profile`, or every profile.
+    pub fn resume(&self, profile: Option<&str>) -> Result<(), String> {
+        let profile = profile.map(Into::into);
+        self.expect_ok(Request::Resume { profile })
+    }
+
+    /// Load fresh profiles with the builder's [`reload`](DaemonBuilder::reload).
+    pub fn reload(&self) -> Result<(), String> {
+        self.expect_ok(Request::Reload)
+    }
+
+    /// What the daemon has put right recently, oldest first.
+    pub fn corrections(&self) -> Result<Vec<Correction>, String> {
+        match self.request(Request::Corrections)? {
+            Response::Corrections { corrections } => Ok(corrections),
+            other => Err(control::unexpected(other)),
+        }
+    }
+
+    /// Ask the daemon to stop, without waiting. [`Daemon::run`] returns once it has.
+    pub fn stop(&self) {
+        let (reply, _) = mpsc::channel();
+        let request = Request::Shutdown;
+        let _ = self.requests.send(Incoming { request, reply });
+    }
+
+    fn expect_ok(&self, request: Request) -> Result<(), String> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            other => Err(control::unexpected(other)),
+        }
+    }
+}
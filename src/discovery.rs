@@ -1,27 +1,416 @@
 //! Enumerate connected Thunderbolt devices and available Sidecar (iPad) devices
 //! so the user can populate their config file.
+//!
+//! Thunderbolt switches are read from the IORegistry, falling back to `system_profiler`
+//! when that fails. Results are gathered into a [`Report`], shown as a table for people
+//! or serialised as JSON or YAML for scripts. Looking for devices needs macOS; the report
+//! and reading saved dumps do not.
 
-use std::process::Command;
+use std::fmt;
 
-use crate::sidecar_ffi;
+use serde::Serialize;
 
-/// A Thunderbolt peripheral discovered via `system_profiler`.
-#[derive(Debug)]
+/// Version of the serialised [`Report`] layout. Raised whenever a field is renamed or
+/// removed, or its meaning changes; new fields may appear without a bump.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A Thunderbolt switch: the Mac's own controller, a dock, or a device behind one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ThunderboltDevice {
+    /// Model name.
     pub name: String,
     pub uid: String,
     pub vendor: String,
+    /// Ports taken from the Mac to reach the switch, one byte per hop with the first hop
+    /// lowest (e.g. `301` is port 1, then port 3).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_string: Option<String>,
+    /// Hops from the Mac: 1 for a device plugged straight in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<u32>,
+    /// Port on the upstream switch that this one is plugged into.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u32>,
+    /// Speed of the link to the upstream switch, e.g. `Up to 40 Gb/s`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_speed: Option<String>,
+    /// Lanes in the link to the upstream switch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_width: Option<u32>,
+    /// Firmware version of the switch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub firmware: Option<String>,
+    /// Whether this is one of the Mac's own controllers rather than a peripheral.
+    pub apple_internal: bool,
+    /// Switches plugged into this one. Only filled in by the tree view.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<ThunderboltDevice>,
+}
+
+/// How `discover` lays out Thunderbolt devices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// Nest each switch under the one it is plugged into.
+    pub tree: bool,
+    /// Keep the Mac's own controllers.
+    pub all: bool,
+}
+
+/// Lay out a topology `tree` as `layout` asks: flattened upstream first, or nested.
+/// Without [`Layout::all`] the Mac's own controllers are dropped, and in a tree the
+/// devices plugged into them take their place.
+pub fn arrange(tree: Vec<ThunderboltDevice>, layout: Layout) -> Vec<ThunderboltDevice> {
+    let mut out = Vec::new();
+    for device in tree {
+        place(device, layout, &mut out);
+    }
+    out
+}
+
+fn place(mut device: ThunderboltDevice, layout: Layout, out: &mut Vec<ThunderboltDevice>) {
+    let children = std::mem::take(&mut device.children);
+    let shown = layout.all || !device.apple_internal;
+    if layout.tree && shown {
+        for child in children {
+            place(child, layout, &mut device.children);
+        }
+        out.push(device);
+        return;
+    }
+    if shown {
+        out.push(device);
+    }
+    for child in children {
+        place(child, layout, out);
+    }
+}
+
+/// The port a switch at `depth` hangs off, from its hex `route` string.
+fn upstream_port(route: &str, depth: u32) -> Option<u32> {
+    let route = u64::from_str_radix(route, 16).ok()?;
+    let shift = depth.checked_sub(1)?.checked_mul(8)?;
+    route.checked_shr(shift).map(|hops| (hops & 0xff) as u32)
 }
 
 /// An iPad reachable for Sidecar display extension.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct SidecarDevice {
     pub name: String,
 }
 
-/// Discover non-Apple Thunderbolt devices by parsing `system_profiler SPThunderboltDataType -xml`.
+/// What one section of the report found, or why it could not look.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Listing<T> {
+    pub devices: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl<T> From<Result<Vec<T>, String>> for Listing<T> {
+    fn from(result: Result<Vec<T>, String>) -> Self {
+        match result {
+            Ok(devices) => Self {
+                devices,
+                error: None,
+            },
+            Err(e) => Self {
+                devices: Vec::new(),
+                error: Some(e),
+            },
+        }
+    }
+}
+
+/// Everything discovery found. Sections that were not asked for are `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thunderbolt: Option<Listing<ThunderboltDevice>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sidecar: Option<Listing<SidecarDevice>>,
+}
+
+impl Report {
+    pub fn new(
+        thunderbolt: Option<Result<Vec<ThunderboltDevice>, String>>,
+        sidecar: Option<Result<Vec<SidecarDevice>, String>>,
+    ) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            thunderbolt: thunderbolt.map(Into::into),
+            sidecar: sidecar.map(Into::into),
+        }
+    }
+
+    /// Whether any section that was asked for could not be listed.
+    pub fn failed(&self) -> bool {
+        let thunderbolt = self.thunderbolt.as_ref().and_then(|l| l.error.as_ref());
+        let sidecar = self.sidecar.as_ref().and_then(|l| l.error.as_ref());
+        thunderbolt.or(sidecar).is_some()
+    }
+}
+
+/// The human-readable layout printed by `discover`.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(listing) = &self.thunderbolt {
+            writeln!(f, "=== Thunderbolt Devices ===\n")?;
+            if let Some(e) = &listing.error {
+                writeln!(f, "  (failed: {e})\n")?;
+            } else if listing.devices.is_empty() {
+                writeln!(f, "  (no external Thunderbolt devices found)\n")?;
+            }
+            for d in &listing.devices {
+                write_device(f, d, 1)?;
+            }
+        }
+
+        if let Some(listing) = &self.sidecar {
+            writeln!(f, "=== Sidecar Devices (iPads) ===\n")?;
+            if let Some(e) = &listing.error {
+                writeln!(f, "  (failed: {e})\n")?;
+            } else if listing.devices.is_empty() {
+                writeln!(f, "  (no Sidecar-capable devices found)\n")?;
+            } else {
+                for d in &listing.devices {
+                    writeln!(f, "  Name: {}", d.name)?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        if self
+            .thunderbolt
+            .as_ref()
+            .is_some_and(|l| !l.devices.is_empty())
+        {
+            writeln!(
+                f,
+                "Hint: run `sidecar-on-dock init` to write the config, or copy the UID of \
+                 your dock into it."
+            )?;
+            writeln!(
+                f,
+                "Default config path: {}",
+                crate::config::Config::default_path().display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// One device block, indented by `level`, followed by the devices plugged into it.
+fn write_device(f: &mut fmt::Formatter<'_>, d: &ThunderboltDevice, level: usize) -> fmt::Result {
+    let indent = "    ".repeat(level - 1);
+    let name = if d.apple_internal {
+        format!("{} (this Mac)", d.name)
+    } else {
+        d.name.clone()
+    };
+    writeln!(f, "{indent}  Name:     {name}")?;
+    writeln!(f, "{indent}  Vendor:   {}", d.vendor)?;
+    writeln!(f, "{indent}  UID:      {}", d.uid)?;
+    if let (Some(route), Some(depth)) = (&d.route_string, d.depth) {
+        match d.port {
+            Some(port) => writeln!(
+                f,
+                "{indent}  Route:    {route} (depth {depth}, port {port})"
+            )?,
+            None => writeln!(f, "{indent}  Route:    {route} (depth {depth})")?,
+        }
+    }
+    match (&d.link_speed, d.link_width) {
+        (Some(speed), Some(width)) => writeln!(f, "{indent}  Link:     {speed}, x{width}")?,
+        (Some(speed), None) => writeln!(f, "{indent}  Link:     {speed}")?,
+        (None, Some(width)) => writeln!(f, "{indent}  Link:     x{width}")?,
+        (None, None) => {}
+    }
+    if let Some(firmware) = &d.firmware {
+        writeln!(f, "{indent}  Firmware: {firmware}")?;
+    }
+    writeln!(f)?;
+    for child in &d.children {
+        write_device(f, child, level + 1)?;
+    }
+    Ok(())
+}
+
+/// List the devices in the sections asked for, laying out Thunderbolt devices as
+/// `layout` asks.
+#[cfg(target_os = "macos")]
+pub fn discover(thunderbolt: bool, sidecar: bool, layout: Layout) -> Report {
+    Report::new(
+        thunderbolt.then(|| discover_thunderbolt_tree().map(|tree| arrange(tree, layout))),
+        sidecar.then(discover_sidecar_devices),
+    )
+}
+
+/// Discover non-Apple Thunderbolt devices, upstream first.
+#[cfg(target_os = "macos")]
 pub fn discover_thunderbolt_devices() -> Result<Vec<ThunderboltDevice>, String> {
-    let output = Command::new("system_profiler")
+    discover_thunderbolt_tree().map(|tree| arrange(tree, Layout::default()))
+}
+
+/// The whole Thunderbolt topology, the Mac's own controllers included. Reads the
+/// IORegistry, and only runs `system_profiler` if that fails.
+#[cfg(target_os = "macos")]
+pub fn discover_thunderbolt_tree() -> Result<Vec<ThunderboltDevice>, String> {
+    iokit_thunderbolt_tree().or_else(|e| {
+        log::warn!("{e}; asking system_profiler instead");
+        system_profiler_thunderbolt_tree()
+    })
+}
+
+/// The `IOThunderboltSwitch` services in the IORegistry, each nested under the switch it
+/// is plugged into, with the link it is plugged in by.
+#[cfg(target_os = "macos")]
+pub fn iokit_thunderbolt_tree() -> Result<Vec<ThunderboltDevice>, String> {
+    use crate::iokit_ffi::*;
+    use crate::iokit_source::{
+        for_each_switch, number_property, registry_properties, string_property,
+    };
+
+    let mut found = Vec::new();
+    for_each_switch(|service| {
+        if let Some(props) = registry_properties(service) {
+            let port = number_property(&props, "Upstream Port Number")
+                .and_then(|number| switch_port(service, number))
+                .and_then(|port| {
+                    let props = registry_properties(port);
+                    unsafe { IOObjectRelease(port) };
+                    props
+                });
+            let device = switch_device(
+                |key| string_property(&props, key),
+                |key| number_property(&props, key),
+                |key| number_property(port.as_ref()?, key),
+            );
+            let parent = upstream_switch(service).and_then(|upstream| {
+                let props = registry_properties(upstream);
+                unsafe { IOObjectRelease(upstream) };
+                number_property(&props?, "UID").map(format_uid)
+            });
+            found.push((device, parent));
+        }
+    })?;
+
+    // Registry order is not guaranteed to follow the topology.
+    found.sort_by_key(|(d, _)| d.port);
+    Ok(nest(found))
+}
+
+/// The nearest `IOThunderboltSwitch` above `service` in the service plane, which the
+/// caller must release.
+#[cfg(target_os = "macos")]
+fn upstream_switch(
+    service: crate::iokit_ffi::io_service_t,
+) -> Option<crate::iokit_ffi::io_service_t> {
+    use std::ffi::c_char;
+
+    use crate::iokit_ffi::*;
+    use crate::iokit_source::TB_SWITCH_CLASS;
+
+    let mut entry = service;
+    loop {
+        let mut parent = 0;
+        let kr = unsafe {
+            IORegistryEntryGetParentEntry(
+                entry,
+                kIOServicePlane.as_ptr() as *const c_char,
+                &mut parent,
+            )
+        };
+        if entry != service {
+            unsafe { IOObjectRelease(entry) };
+        }
+        if kr != KERN_SUCCESS || parent == 0 {
+            return None;
+        }
+        if unsafe { IOObjectConformsTo(parent, TB_SWITCH_CLASS.as_ptr() as *const c_char) } != 0 {
+            return Some(parent);
+        }
+        entry = parent;
+    }
+}
+
+/// The `IOThunderboltPort` numbered `number` on the switch `service`, which the caller
+/// must release.
+#[cfg(target_os = "macos")]
+fn switch_port(
+    service: crate::iokit_ffi::io_service_t,
+    number: i64,
+) -> Option<crate::iokit_ffi::io_service_t> {
+    use std::ffi::c_char;
+
+    use crate::iokit_ffi::*;
+    use crate::iokit_source::{TB_PORT_CLASS, number_property, registry_properties};
+
+    let mut iterator: io_iterator_t = 0;
+    let kr = unsafe {
+        IORegistryEntryGetChildIterator(
+            service,
+            kIOServicePlane.as_ptr() as *const c_char,
+            &mut iterator,
+        )
+    };
+    if kr != KERN_SUCCESS {
+        return None;
+    }
+
+    let mut found = None;
+    loop {
+        let child = unsafe { IOIteratorNext(iterator) };
+        if child == 0 {
+            break;
+        }
+        let wanted = found.is_none()
+            && unsafe { IOObjectConformsTo(child, TB_PORT_CLASS.as_ptr() as *const c_char) } != 0
+            && registry_properties(child)
+                .is_some_and(|props| number_property(&props, "Port Number") == Some(number));
+        if wanted {
+            found = Some(child);
+        } else {
+            unsafe { IOObjectRelease(child) };
+        }
+    }
+    unsafe { IOObjectRelease(iterator) };
+    found
+}
+
+/// Build a tree from devices paired with the UID of the switch each is plugged into.
+/// Devices whose upstream switch is unknown become roots.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn nest(found: Vec<(ThunderboltDevice, Option<String>)>) -> Vec<ThunderboltDevice> {
+    let uids: Vec<_> = found.iter().map(|(d, _)| d.uid.clone()).collect();
+    let (roots, mut rest): (Vec<_>, Vec<_>) = found
+        .into_iter()
+        .partition(|(_, parent)| parent.as_ref().is_none_or(|p| !uids.contains(p)));
+
+    fn adopt(device: &mut ThunderboltDevice, rest: &mut Vec<(ThunderboltDevice, Option<String>)>) {
+        let (mine, others) = std::mem::take(rest)
+            .into_iter()
+            .partition(|(_, parent)| parent.as_ref() == Some(&device.uid));
+        *rest = others;
+        for (mut child, _) in mine {
+            adopt(&mut child, rest);
+            device.children.push(child);
+        }
+    }
+
+    roots
+        .into_iter()
+        .map(|(mut device, _)| {
+            adopt(&mut device, &mut rest);
+            device
+        })
+        .collect()
+}
+
+/// The Thunderbolt topology according to `system_profiler SPThunderboltDataType -xml`.
+#[cfg(target_os = "macos")]
+pub fn system_profiler_thunderbolt_tree() -> Result<Vec<ThunderboltDevice>, String> {
+    let output = std::process::Command::new("system_profiler")
         .args(["SPThunderboltDataType", "-xml"])
         .output()
         .map_err(|e| format!("Failed to run system_profiler: {e}"))?;
@@ -33,36 +422,43 @@ pub fn discover_thunderbolt_devices() -> Result<Vec<ThunderboltDevice>, String>
         ));
     }
 
+    thunderbolt_tree_from_bytes(&output.stdout)
+}
+
+/// Non-Apple Thunderbolt devices in a captured dump, upstream first. See
+/// [`thunderbolt_tree_from_bytes`].
+pub fn thunderbolt_devices_from_bytes(bytes: &[u8]) -> Result<Vec<ThunderboltDevice>, String> {
+    thunderbolt_tree_from_bytes(bytes).map(|tree| arrange(tree, Layout::default()))
+}
+
+/// The Thunderbolt topology in a captured dump: the output of either
+/// `system_profiler SPThunderboltDataType -xml` or `ioreg -a -l`, as XML or binary plist.
+pub fn thunderbolt_tree_from_bytes(bytes: &[u8]) -> Result<Vec<ThunderboltDevice>, String> {
     let value: plist::Value =
-        plist::from_bytes(&output.stdout).map_err(|e| format!("Failed to parse plist: {e}"))?;
+        plist::from_bytes(bytes).map_err(|e| format!("Failed to parse plist: {e}"))?;
 
-    let mut devices = Vec::new();
-    extract_devices(&value, &mut devices);
-    Ok(devices)
+    if is_ioreg(&value) {
+        Ok(ioreg_switches(&value))
+    } else {
+        Ok(system_profiler_buses(&value))
+    }
 }
 
 /// List iPads reachable for Sidecar display extension via SidecarCore.
-pub fn discover_sidecar_devices() -> Vec<SidecarDevice> {
-    if sidecar_ffi::load_framework().is_err() {
-        log::warn!("Could not load SidecarCore framework");
-        return Vec::new();
-    }
+#[cfg(target_os = "macos")]
+pub fn discover_sidecar_devices() -> Result<Vec<SidecarDevice>, String> {
+    use crate::sidecar_ffi;
 
-    let Some(cls) = sidecar_ffi::display_manager_class() else {
-        log::warn!("SidecarDisplayManager class not found");
-        return Vec::new();
-    };
+    sidecar_ffi::load_framework()?;
+
+    let cls =
+        sidecar_ffi::display_manager_class().ok_or("SidecarDisplayManager class not found")?;
 
     unsafe {
-        let Some(manager) = sidecar_ffi::shared_manager(cls) else {
-            log::warn!("Could not obtain SidecarDisplayManager.sharedManager");
-            return Vec::new();
-        };
+        let manager = sidecar_ffi::shared_manager(cls)
+            .ok_or("Could not obtain SidecarDisplayManager.sharedManager")?;
 
-        let Some(array) = sidecar_ffi::devices(&manager) else {
-            log::warn!("Could not obtain devices array");
-            return Vec::new();
-        };
+        let array = sidecar_ffi::devices(&manager).ok_or("Could not obtain devices array")?;
 
         let count = sidecar_ffi::array_count(&array);
         let mut result = Vec::with_capacity(count);
@@ -74,97 +470,182 @@ pub fn discover_sidecar_devices() -> Vec<SidecarDevice> {
                 result.push(SidecarDevice { name });
             }
         }
-        result
+        Ok(result)
     }
 }
 
-/// Run full discovery and print results to stdout.
-pub fn print_discovery() -> Result<(), String> {
-    println!("=== Thunderbolt Devices ===\n");
+/// The Mac's Thunderbolt buses in `system_profiler` output, with what is plugged into
+/// them.
+fn system_profiler_buses(value: &plist::Value) -> Vec<ThunderboltDevice> {
+    let Some(root_array) = value.as_array() else {
+        return Vec::new();
+    };
 
-    let tb_devices = discover_thunderbolt_devices()?;
-    if tb_devices.is_empty() {
-        println!("  (no external Thunderbolt devices found)\n");
-    } else {
-        for d in &tb_devices {
-            println!("  Name:   {}", d.name);
-            println!("  Vendor: {}", d.vendor);
-            println!("  UID:    {}", d.uid);
-            println!();
-        }
-    }
+    root_array
+        .iter()
+        .filter_map(|entry| entry.as_dictionary()?.get("_items")?.as_array())
+        .flatten()
+        .filter_map(|item| system_profiler_device(item, 0))
+        .collect()
+}
 
-    println!("=== Sidecar Devices (iPads) ===\n");
+/// The device described by a `system_profiler` dict, with those in its nested `_items`.
+/// The Mac's own buses are at `depth` 0.
+fn system_profiler_device(value: &plist::Value, depth: u32) -> Option<ThunderboltDevice> {
+    let dict = value.as_dictionary()?;
+    let string = |key: &str| dict.get(key).and_then(|v| v.as_string());
 
-    let sc_devices = discover_sidecar_devices();
-    if sc_devices.is_empty() {
-        println!("  (no Sidecar-capable devices found)\n");
-    } else {
-        for d in &sc_devices {
-            println!("  Name: {}", d.name);
-        }
-        println!();
-    }
+    let vendor = string("vendor_name_key").unwrap_or("").to_string();
+    let route_string = string("route_string_key").map(String::from);
+    // The link to the upstream switch, as seen from this end.
+    let upstream = dict
+        .get("receptacle_upstream_ambiguous_tag")
+        .and_then(|v| v.as_dictionary());
+    let link = |key: &str| upstream?.get(key)?.as_string();
 
-    if !tb_devices.is_empty() {
-        println!("Hint: copy the UID of your dock into the config file.");
-        println!(
-            "Default config path: {}",
-            crate::config::Config::default_path().display()
-        );
-    }
+    let children = dict
+        .get("_items")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|item| system_profiler_device(item, depth + 1))
+        .collect();
 
-    Ok(())
+    Some(ThunderboltDevice {
+        name: string("device_name_key")
+            .or_else(|| string("_name"))
+            .unwrap_or("Unknown")
+            .to_string(),
+        uid: string("switch_uid_key").unwrap_or("N/A").to_string(),
+        port: route_string
+            .as_deref()
+            .and_then(|route| upstream_port(route, depth)),
+        route_string,
+        depth: Some(depth),
+        link_speed: link("current_speed_key").map(String::from),
+        link_width: link("current_link_width_key").and_then(|width| {
+            let width = width.trim_start_matches("0x");
+            u32::from_str_radix(width, 16).ok()
+        }),
+        firmware: string("switch_version_key").map(String::from),
+        apple_internal: vendor == APPLE,
+        vendor,
+        children,
+    })
 }
 
-fn extract_devices(value: &plist::Value, out: &mut Vec<ThunderboltDevice>) {
-    let Some(root_array) = value.as_array() else {
-        return;
+/// Whether `value` is an IORegistry dump rather than `system_profiler` output. `ioreg`
+/// gives a dict for the tree, or an array of them with `-r`; every entry has a class.
+fn is_ioreg(value: &plist::Value) -> bool {
+    let entry = match value.as_array() {
+        Some(entries) => entries.first(),
+        None => Some(value),
     };
-
-    for entry in root_array {
-        if let Some(dict) = entry.as_dictionary()
-            && let Some(items) = dict.get("_items").and_then(|v| v.as_array())
-        {
-            for item in items {
-                extract_device_from_dict(item, out);
-            }
-        }
-    }
+    entry
+        .and_then(|v| v.as_dictionary())
+        .is_some_and(|dict| dict.contains_key("IOObjectClass"))
 }
 
-/// Extract non-Apple devices from a plist dict, recursing into nested `_items`.
-fn extract_device_from_dict(value: &plist::Value, out: &mut Vec<ThunderboltDevice>) {
+/// The topmost Thunderbolt switches in an IORegistry dump, each with the switches found
+/// beneath it in `IORegistryEntryChildren`.
+fn ioreg_switches(value: &plist::Value) -> Vec<ThunderboltDevice> {
+    if let Some(entries) = value.as_array() {
+        return entries.iter().flat_map(ioreg_switches).collect();
+    }
     let Some(dict) = value.as_dictionary() else {
-        return;
+        return Vec::new();
     };
 
-    let vendor = dict
-        .get("vendor_name_key")
+    let below = dict
+        .get("IORegistryEntryChildren")
+        .map(ioreg_switches)
+        .unwrap_or_default();
+
+    let string = |key: &str| dict.get(key).and_then(|v| v.as_string()).map(String::from);
+    if !ioreg_class(dict).starts_with("IOThunderboltSwitch") {
+        return below;
+    }
+    let port = ioreg_number(dict, "Upstream Port Number").and_then(|number| {
+        dict.get("IORegistryEntryChildren")?
+            .as_array()?
+            .iter()
+            .filter_map(|child| child.as_dictionary())
+            .filter(|child| ioreg_class(child).starts_with("IOThunderboltPort"))
+            .find(|child| ioreg_number(child, "Port Number") == Some(number))
+    });
+    let mut device = switch_device(
+        string,
+        |key| ioreg_number(dict, key),
+        |key| ioreg_number(port?, key),
+    );
+    device.children = below;
+    vec![device]
+}
+
+/// The class of an entry in an IORegistry dump.
+fn ioreg_class(dict: &plist::Dictionary) -> &str {
+    dict.get("IOObjectClass")
         .and_then(|v| v.as_string())
-        .unwrap_or("")
-        .to_string();
-
-    if vendor != "Apple Inc." {
-        let name = dict
-            .get("device_name_key")
-            .or_else(|| dict.get("_name"))
-            .and_then(|v| v.as_string())
-            .unwrap_or("Unknown")
-            .to_string();
+        .unwrap_or_default()
+}
 
-        let uid = dict
-            .get("switch_uid_key")
-            .and_then(|v| v.as_string())
-            .unwrap_or("N/A")
-            .to_string();
+/// A numeric property of an entry in an IORegistry dump.
+fn ioreg_number(dict: &plist::Dictionary, key: &str) -> Option<i64> {
+    let value = dict.get(key)?;
+    // `ioreg` may show signed numbers, such as the UID, as unsigned.
+    value
+        .as_signed_integer()
+        .or_else(|| value.as_unsigned_integer().map(|n| n as i64))
+}
 
-        out.push(ThunderboltDevice { name, uid, vendor });
-    }
+/// Vendor name of the Mac's own controllers.
+const APPLE: &str = "Apple Inc.";
 
-    if let Some(items) = dict.get("_items").and_then(|v| v.as_array()) {
-        for item in items {
-            extract_device_from_dict(item, out);
-        }
+/// An IOKit UID, which is stored as a signed 64-bit number, in hex.
+fn format_uid(uid: i64) -> String {
+    format!("0x{:016X}", uid as u64)
+}
+
+/// The device described by an `IOThunderboltSwitch`'s registry properties, read through
+/// `string` and `number`, and by the numeric properties of its upstream port, read
+/// through `port`.
+fn switch_device(
+    string: impl Fn(&str) -> Option<String>,
+    number: impl Fn(&str) -> Option<i64>,
+    port: impl Fn(&str) -> Option<i64>,
+) -> ThunderboltDevice {
+    let vendor = string("Device Vendor Name").unwrap_or_default();
+    let link_width = port("Current Link Width").and_then(|width| u32::try_from(width).ok());
+    let route_string = number("Route String").map(|route| format!("{route:x}"));
+    let depth = number("Depth").and_then(|depth| u32::try_from(depth).ok());
+    ThunderboltDevice {
+        name: string("Device Model Name").unwrap_or_else(|| "Unknown".into()),
+        uid: number("UID").map_or_else(|| "N/A".into(), format_uid),
+        port: route_string
+            .as_deref()
+            .zip(depth)
+            .and_then(|(route, depth)| upstream_port(route, depth)),
+        route_string,
+        depth,
+        link_speed: port("Current Link Speed")
+            .zip(link_width)
+            .and_then(|(speed, lanes)| link_speed(speed, lanes)),
+        link_width,
+        firmware: string("Firmware Version"),
+        apple_internal: vendor == APPLE,
+        vendor,
+        children: Vec::new(),
     }
 }
+
+/// A link's speed as `system_profiler` words it, from the lane adapter's Current Link
+/// Speed field and the number of lanes.
+fn link_speed(speed: i64, lanes: u32) -> Option<String> {
+    let per_lane = match speed {
+        0x8 => 10, // Gen 2
+        0x4 => 20, // Gen 3
+        0x2 => 40, // Gen 4
+        _ => return None,
+    };
+    Some(format!("Up to {} Gb/s", per_lane * lanes))
+}
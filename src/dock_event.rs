@@ -0,0 +1,225 @@
+//! Platform-independent dock hotplug events.
+//!
+//! A [`DockEventSource`] reports Thunderbolt devices appearing and disappearing, and the
+//! system going to sleep and waking up, since devices can change unnoticed meanwhile. The
+//! IOKit source is the one the daemon uses on macOS; [`ChannelSource`] and
+//! [`ReplaySource`] drive the same monitoring loop from tests or a recorded session.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::config;
+
+/// Extra details about a device, such as `vendor_name` and `device_name`.
+pub type Properties = BTreeMap<String, String>;
+
+/// A Thunderbolt device appeared or disappeared, or the system slept or woke.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DockEvent {
+    /// A device was attached. `uid` is `None` if it could not be read.
+    Appeared {
+        uid: Option<u64>,
+        properties: Properties,
+    },
+    /// A device was detached. `uid` is `None` if it could not be read, in which case the
+    /// device may have been any dock.
+    Removed {
+        uid: Option<u64>,
+        properties: Properties,
+    },
+    /// The system is about to sleep. It waits until `guard` and its clones are dropped,
+    /// for a few seconds at most.
+    Sleeping { guard: SleepGuard },
+    /// The system woke up. `attached` lists the UIDs of the devices attached now, if the
+    /// source could look.
+    Woke { attached: Option<Vec<u64>> },
+}
+
+impl DockEvent {
+    /// The device's UID, if known. Always `None` for sleep and wake.
+    pub fn uid(&self) -> Option<u64> {
+        match self {
+            DockEvent::Appeared { uid, .. } | DockEvent::Removed { uid, .. } => *uid,
+            DockEvent::Sleeping { .. } | DockEvent::Woke { .. } => None,
+        }
+    }
+}
+
+/// Holds off system sleep while the daemon gets ready for it.
+#[derive(Debug, Clone)]
+pub struct SleepGuard {
+    _release: Sender<()>,
+}
+
+impl SleepGuard {
+    /// A guard, and a receiver that disconnects once the guard and all its clones are
+    /// dropped.
+    pub fn new() -> (Self, Receiver<()>) {
+        let (tx, rx) = mpsc::channel();
+        (Self { _release: tx }, rx)
+    }
+}
+
+/// Guards carry nothing but their lifetime, so any two are alike.
+impl PartialEq for SleepGuard {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for SleepGuard {}
+
+/// Something that reports dock hotplug events.
+///
+/// Devices already attached when the source starts are reported as
+/// [`DockEvent::Appeared`].
+pub trait DockEventSource: Send {
+    /// Block until the next event. Returns `None` once the source has no more events.
+    fn next_event(&mut self) -> Option<DockEvent>;
+
+    /// Something that ends the source from another thread, making
+    /// [`next_event`](Self::next_event) return `None`, so it can be dropped at shutdown.
+    /// `None` for sources that only end on their own.
+    fn closer(&self) -> Option<Closer> {
+        None
+    }
+}
+
+/// Ends a [`DockEventSource`]. See [`DockEventSource::closer`].
+pub type Closer = Box<dyn FnOnce() + Send>;
+
+/// A source fed by hand through a channel.
+#[derive(Debug)]
+pub struct ChannelSource {
+    events: Receiver<DockEvent>,
+}
+
+/// Create a [`ChannelSource`] and the sender that feeds it. The source ends once every
+/// sender has been dropped.
+pub fn channel() -> (Sender<DockEvent>, ChannelSource) {
+    let (tx, events) = mpsc::channel();
+    (tx, ChannelSource { events })
+}
+
+impl DockEventSource for ChannelSource {
+    fn next_event(&mut self) -> Option<DockEvent> {
+        self.events.recv().ok()
+    }
+}
+
+/// A source that plays back a scripted session.
+///
+/// Scripts are JSON lines; blank lines and lines starting with `#` are ignored:
+///
+/// ```text
+/// {"appeared": {"uid": "0x003DA86E85A8CB00", "properties": {"device_name": "TS4"}}}
+/// {"wait": {"ms": 1500}}
+/// {"removed": {"uid": "0x003DA86E85A8CB00"}}
+/// {"removed": {}}
+/// {"sleeping": {}}
+/// {"woke": {"attached": ["0x003DA86E85A8CB00"]}}
+/// ```
+#[derive(Debug)]
+pub struct ReplaySource {
+    steps: std::vec::IntoIter<Step>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Event(DockEvent),
+    Wait(Duration),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ScriptLine {
+    Appeared(ScriptDevice),
+    Removed(ScriptDevice),
+    Sleeping {},
+    Woke {
+        #[serde(default)]
+        attached: Option<Vec<String>>,
+    },
+    Wait {
+        ms: u64,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptDevice {
+    #[serde(default)]
+    uid: Option<String>,
+    #[serde(default)]
+    properties: Properties,
+}
+
+impl ScriptDevice {
+    fn uid(&self) -> Result<Option<u64>, String> {
+        self.uid.as_deref().map(config::parse_uid).transpose()
+    }
+}
+
+impl ReplaySource {
+    /// Load a replay script from a file.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read replay file {}: {e}", path.display()))?;
+        Self::parse(&data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Parse a replay script.
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for (index, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at_line = |e: String| format!("line {}: {e}", index + 1);
+            let parsed: ScriptLine =
+                serde_json::from_str(line).map_err(|e| at_line(e.to_string()))?;
+            steps.push(match parsed {
+                ScriptLine::Appeared(device) => Step::Event(DockEvent::Appeared {
+                    uid: device.uid().map_err(at_line)?,
+                    properties: device.properties,
+                }),
+                ScriptLine::Removed(device) => Step::Event(DockEvent::Removed {
+                    uid: device.uid().map_err(at_line)?,
+                    properties: device.properties,
+                }),
+                // Nobody is waiting for the system to sleep.
+                ScriptLine::Sleeping {} => Step::Event(DockEvent::Sleeping {
+                    guard: SleepGuard::new().0,
+                }),
+                ScriptLine::Woke { attached } => Step::Event(DockEvent::Woke {
+                    attached: attached
+                        .map(|uids| uids.iter().map(|uid| config::parse_uid(uid)).collect())
+                        .transpose()
+                        .map_err(at_line)?,
+                }),
+                ScriptLine::Wait { ms } => Step::Wait(Duration::from_millis(ms)),
+            });
+        }
+        Ok(Self {
+            steps: steps.into_iter(),
+        })
+    }
+}
+
+impl DockEventSource for ReplaySource {
+    fn next_event(&mut self) -> Option<DockEvent> {
+        loop {
+            match self.steps.next()? {
+                Step::Event(event) => return Some(event),
+                Step::Wait(delay) => thread::sleep(delay),
+            }
+        }
+    }
+}
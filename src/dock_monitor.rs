@@ -1,178 +1,1077 @@
-//! IOKit-based Thunderbolt dock monitoring.
+//! Platform-independent dock monitoring loop.
 //!
-//! Registers for `kIOFirstMatchNotification` and `kIOTerminatedNotification`
-//! on `IOThunderboltSwitch` services, then enters a `CFRunLoop`.
+//! Events from a [`DockEventSource`] and Sidecar completions from a [`SidecarBackend`]
+//! are funnelled into a single channel and fed to each profile's [`state`] machine on
+//! one thread. Settle, retry and grace deadlines are served by waiting on that channel
+//! with a timeout, as are the deadlines for Sidecar to answer. Hooks run on their own
+//! worker thread. Requests from the control socket
+//! arrive through the same channel.
+//!
+//! Before the system sleeps, profiles that disconnect for sleep hold it off until Sidecar
+//! has answered. On wake, dock presence is re-read from the source and each profile is
+//! told whether its session survived, so one that did not is connected again.
+//!
+//! While a dock is attached, each profile periodically checks with Sidecar that its iPad
+//! really is connected, and reconnects with a growing delay when it is not. Every such
+//! correction is kept for the control socket to report. A session that ends while Sidecar
+//! still offers the iPad was disconnected by hand, and is left alone; so is one that
+//! someone else started.
+//!
+//! A `shutdown` request, which the daemon sends itself on SIGTERM or SIGINT, stops
+//! anything new from starting, ends the sessions configured not to outlive the daemon and
+//! closes the dock event source before the loop returns.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use std::cell::Cell;
-use std::ffi::{c_char, c_void};
-use std::ptr;
+use crate::config::Profile;
+use crate::control::{Correction, Incoming, ProfileStatus, Request, Response};
+use crate::dock_event::{Closer, DockEvent, DockEventSource, SleepGuard};
+use crate::hooks::{HookJob, HookRunner};
+use crate::sidecar::{self, SidecarBackend, SidecarError};
+use crate::state::{self, Attempt, Effect, Event, Notice, Policy, State};
 
-use core_foundation::base::{TCFType, kCFAllocatorDefault};
-use core_foundation::number::CFNumber;
-use core_foundation::string::CFString;
-use core_foundation_sys::base::CFRelease;
-use core_foundation_sys::dictionary::{CFDictionaryGetValue, CFDictionaryRef};
-use core_foundation_sys::runloop::{
-    CFRunLoopAddSource, CFRunLoopGetCurrent, CFRunLoopRun, kCFRunLoopDefaultMode,
-};
+/// Something for the monitoring loop to handle.
+enum Input {
+    Dock(DockEvent),
+    Completed(Completed),
+    SourceClosed,
+    Control(Incoming),
+}
+
+/// The outcome of a Sidecar request, on its way back to the monitoring loop.
+struct Completed {
+    /// [`ProfileState::id`] of the requesting profile.
+    profile: usize,
+    /// [`Pending::id`] of the request.
+    id: u64,
+    request: Effect,
+    result: Result<(), SidecarError>,
+}
 
-use crate::iokit_ffi::*;
-use crate::sidecar;
+/// How long to wait for Sidecar when shutting down. launchd kills the agent 20 seconds
+/// after asking it to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
-const TB_SWITCH_CLASS: &[u8] = b"IOThunderboltSwitch\0";
+/// How many corrections are kept for the control socket.
+const MAX_CORRECTIONS: usize = 100;
 
-struct MonitorContext {
+/// Source of [`ProfileState::id`]s.
+static NEXT_PROFILE_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Source of [`Pending::id`]s.
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A Sidecar request the state machine is waiting on.
+struct Pending {
+    id: u64,
+    request: Effect,
+    /// When to stop waiting and treat it as timed out.
+    deadline: Instant,
+}
+
+/// A control client waiting on a Sidecar request made outside the state machine. Taken by
+/// whichever comes first, the answer or the deadline.
+type DirectReply = Arc<Mutex<Option<Sender<Response>>>>;
+
+/// A monitored profile and its position in the [`state`] machine.
+struct ProfileState {
+    /// Identifies the profile across reloads, which may reorder or drop profiles while
+    /// Sidecar requests are outstanding.
+    id: usize,
     dock_uid: u64,
-    ipad_name: Option<String>,
-    sidecar_active: Cell<bool>,
+    profile: Profile,
+    policy: Policy,
+    state: State,
+    /// Control clients waiting for the outcome of a Sidecar request.
+    waiting: Vec<(Effect, Sender<Response>)>,
+    /// The Sidecar requests the state machine is waiting on, at most one of each kind.
+    pending: Vec<Pending>,
+    /// When to next check that Sidecar is in the state it should be in.
+    check_at: Option<Instant>,
+    /// Repairs since Sidecar was last found connected, for backing off.
+    repairs: u32,
 }
 
-/// Start monitoring for the configured dock and block on the `CFRunLoop`. Never returns.
-pub fn run(dock_uid: u64, ipad_name: Option<String>) -> ! {
-    if !sidecar::ensure_loaded() {
-        log::error!("Cannot proceed without SidecarCore");
-        std::process::exit(1);
-    }
-
-    let ctx = Box::leak(Box::new(MonitorContext {
-        dock_uid,
-        ipad_name,
-        sidecar_active: Cell::new(false),
-    }));
-    let refcon: *mut c_void = (ctx as *mut MonitorContext).cast();
-
-    unsafe {
-        let notify_port = IONotificationPortCreate(kIOMasterPortDefault);
-        if notify_port.is_null() {
-            log::error!("IONotificationPortCreate failed");
-            std::process::exit(1);
-        }
-
-        let rls = IONotificationPortGetRunLoopSource(notify_port);
-        CFRunLoopAddSource(CFRunLoopGetCurrent(), rls, kCFRunLoopDefaultMode);
-
-        let mut connect_iter: io_iterator_t = 0;
-        let match_connect = IOServiceMatching(TB_SWITCH_CLASS.as_ptr() as *const c_char);
-        let kr = IOServiceAddMatchingNotification(
-            notify_port,
-            kIOFirstMatchNotification.as_ptr() as *const c_char,
-            match_connect as CFDictionaryRef,
-            device_appeared,
-            refcon,
-            &mut connect_iter,
-        );
-        if kr != KERN_SUCCESS {
-            log::error!("IOServiceAddMatchingNotification (connect) failed: {kr}");
-            std::process::exit(1);
-        }
-        drain_iterator(ctx, connect_iter, true);
-
-        let mut disconnect_iter: io_iterator_t = 0;
-        let match_disconnect = IOServiceMatching(TB_SWITCH_CLASS.as_ptr() as *const c_char);
-        let kr = IOServiceAddMatchingNotification(
-            notify_port,
-            kIOTerminatedNotification.as_ptr() as *const c_char,
-            match_disconnect as CFDictionaryRef,
-            device_removed,
-            refcon,
-            &mut disconnect_iter,
-        );
-        if kr != KERN_SUCCESS {
-            log::error!("IOServiceAddMatchingNotification (disconnect) failed: {kr}");
-            std::process::exit(1);
+/// Loads the profiles to switch to on a `reload` request.
+pub type Reload = Box<dyn FnMut() -> Result<Vec<Profile>, String> + Send>;
+
+/// Lets a control socket drive [`run_with_control`].
+pub struct Control {
+    /// Requests from control clients.
+    pub requests: Receiver<Incoming>,
+    pub reload: Reload,
+}
+
+struct Monitor {
+    profiles: Vec<ProfileState>,
+    backend: Box<dyn SidecarBackend>,
+    hooks: HookRunner,
+    inputs: Sender<Input>,
+    reload: Option<Reload>,
+    /// Control clients following state changes.
+    subscribers: Vec<Sender<Response>>,
+    /// Control clients waiting on a Sidecar request made outside the state machine, with
+    /// when to give up.
+    direct: Vec<(Instant, DirectReply)>,
+    /// UIDs of the docks currently attached, so profiles added by a reload know about them.
+    attached: BTreeSet<u64>,
+    /// Held while getting ready for sleep, with the [`ProfileState::id`]s of the profiles
+    /// still disconnecting.
+    sleep: Option<(SleepGuard, BTreeSet<usize>)>,
+    /// Recent corrections, oldest first.
+    corrections: VecDeque<Correction>,
+    /// Ends the dock event source, if it can be ended.
+    close_source: Option<Closer>,
+    /// Set once shutting down: when to give up waiting for Sidecar, and the clients to
+    /// tell once ready to exit.
+    stopping: Option<(Instant, Vec<Sender<Response>>)>,
+}
+
+/// Monitor `source` for the configured docks, driving Sidecar through `backend`.
+///
+/// Returns once the source has no more events and every profile has settled, i.e. no
+/// request is outstanding and no deadline is pending. Sources that never end make this
+/// run forever.
+pub fn run(
+    profiles: Vec<Profile>,
+    source: Box<dyn DockEventSource>,
+    backend: Box<dyn SidecarBackend>,
+) -> Result<(), String> {
+    monitor(profiles, source, backend, None, None, Vec::new())
+}
+
+/// Like [`run`], also answering requests from `control`.
+pub fn run_with_control(
+    profiles: Vec<Profile>,
+    source: Box<dyn DockEventSource>,
+    backend: Box<dyn SidecarBackend>,
+    control: Control,
+) -> Result<(), String> {
+    monitor(
+        profiles,
+        source,
+        backend,
+        Some(control.requests),
+        Some(control.reload),
+        Vec::new(),
+    )
+}
+
+/// Like [`run`], also answering `requests`, reloading with `reload` and telling
+/// `subscribers` about every state change and reload from the start.
+pub(crate) fn monitor(
+    profiles: Vec<Profile>,
+    mut source: Box<dyn DockEventSource>,
+    backend: Box<dyn SidecarBackend>,
+    requests: Option<Receiver<Incoming>>,
+    reload: Option<Reload>,
+    subscribers: Vec<Sender<Response>>,
+) -> Result<(), String> {
+    let states = profiles
+        .into_iter()
+        .map(ProfileState::new)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (inputs, rx) = mpsc::channel();
+    let events = inputs.clone();
+    let close_source = source.closer();
+    let closable = close_source.is_some();
+    let events_thread = thread::Builder::new()
+        .name("dock-events".into())
+        .spawn(move || {
+            while let Some(event) = source.next_event() {
+                if events.send(Input::Dock(event)).is_err() {
+                    return;
+                }
+            }
+            let _ = events.send(Input::SourceClosed);
+        })
+        .map_err(|e| format!("Failed to start dock event thread: {e}"))?;
+
+    if let Some(requests) = requests {
+        let forward = inputs.clone();
+        thread::Builder::new()
+            .name("control-requests".into())
+            .spawn(move || {
+                for incoming in requests {
+                    if forward.send(Input::Control(incoming)).is_err() {
+                        return;
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to start control request thread: {e}"))?;
+    }
+
+    let mut monitor = Monitor {
+        profiles: states,
+        backend,
+        hooks: HookRunner::start()?,
+        inputs,
+        reload,
+        subscribers,
+        direct: Vec::new(),
+        attached: BTreeSet::new(),
+        sleep: None,
+        corrections: VecDeque::new(),
+        close_source,
+        stopping: None,
+    };
+    log::info!("Monitoring {} dock profile(s)", monitor.profiles.len());
+
+    let mut source_open = true;
+    loop {
+        if monitor.stopped() {
+            break;
+        }
+        if !source_open && monitor.settled() {
+            return Ok(());
         }
-        drain_iterator(ctx, disconnect_iter, false);
 
-        log::info!(
-            "Monitoring for Thunderbolt dock UID 0x{:016X}. Entering run loop...",
-            dock_uid
-        );
-        CFRunLoopRun();
+        let input = match monitor.next_deadline() {
+            Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+            // `monitor` holds a sender, so the channel never disconnects.
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match input {
+            Ok(Input::Dock(_)) if monitor.stopping.is_some() => {}
+            Ok(Input::Dock(DockEvent::Sleeping { guard })) => monitor.prepare_for_sleep(guard),
+            Ok(Input::Dock(DockEvent::Woke { attached })) => monitor.woke(attached),
+            Ok(Input::Dock(event)) => monitor.handle_dock_event(event),
+            Ok(Input::Completed(completed)) => monitor.handle_completion(completed),
+            Ok(Input::Control(incoming)) => monitor.handle_request(incoming),
+            Ok(Input::SourceClosed) => {
+                log::info!("Dock event source closed");
+                source_open = false;
+            }
+            Err(RecvTimeoutError::Timeout) if monitor.stopping.is_some() => {
+                log::warn!(
+                    "Sidecar has not answered after {}s; stopping anyway",
+                    SHUTDOWN_TIMEOUT.as_secs()
+                );
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                monitor.expire_requests();
+                for index in 0..monitor.profiles.len() {
+                    monitor.dispatch(index, Event::Tick);
+                }
+                monitor.reconcile_due();
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
     }
 
-    unreachable!("CFRunLoopRun returned");
+    // Once closed, the source is dropped along with its thread, releasing what it holds.
+    if closable {
+        let _ = events_thread.join();
+    }
+    log::info!("Stopped");
+    for reply in monitor.stopping.take().into_iter().flat_map(|(_, r)| r) {
+        let _ = reply.send(Response::Ok);
+    }
+    Ok(())
 }
 
-unsafe extern "C" fn device_appeared(refcon: *mut c_void, iterator: io_iterator_t) {
-    unsafe {
-        let ctx = &*(refcon as *const MonitorContext);
-        drain_iterator(ctx, iterator, true);
+impl ProfileState {
+    fn new(profile: Profile) -> Result<Self, String> {
+        let mut state = Self {
+            id: NEXT_PROFILE_ID.fetch_add(1, Ordering::Relaxed),
+            dock_uid: profile.dock_uid_u64()?,
+            policy: profile.options.policy(),
+            profile,
+            state: State::Idle,
+            waiting: Vec::new(),
+            pending: Vec::new(),
+            check_at: None,
+            repairs: 0,
+        };
+        state.schedule_check();
+        Ok(state)
+    }
+
+    /// Plan the next check, unless checks are turned off.
+    fn schedule_check(&mut self) {
+        let interval = self.policy.reconcile;
+        self.check_at = (!interval.is_zero()).then(|| Instant::now() + interval);
+    }
+
+    /// Where the profile stands, as far as its state machine knows.
+    fn status(&self) -> ProfileStatus {
+        ProfileStatus {
+            name: self.profile.label().into(),
+            dock_uid: format!("0x{:016X}", self.dock_uid),
+            ipad_name: self.profile.ipad_name.clone(),
+            state: self.state.name().into(),
+            dock_present: self.state.dock_present(),
+            sidecar_connected: self.state.sidecar_connected(),
+        }
     }
-}
 
-unsafe extern "C" fn device_removed(refcon: *mut c_void, iterator: io_iterator_t) {
-    unsafe {
-        let ctx = &*(refcon as *const MonitorContext);
-        drain_iterator(ctx, iterator, false);
+    /// Like [`status`](Self::status), but outside the states that track a session,
+    /// `backend` is asked whether the iPad is connected, since it may have been connected
+    /// by hand.
+    fn live_status(&self, backend: &dyn SidecarBackend) -> ProfileStatus {
+        let mut status = self.status();
+        if !matches!(
+            self.state,
+            State::Connected { .. } | State::Disconnecting { .. } | State::Connecting { .. }
+        ) {
+            status.sidecar_connected =
+                sidecar::is_connected(backend, self.profile.ipad_name.as_deref()).unwrap_or(false);
+        }
+        status
     }
 }
 
-/// Drain an IOKit iterator, checking each service's UID against the configured dock.
-///
-/// The iterator **must** be fully drained for IOKit to re-arm the notification.
-fn drain_iterator(ctx: &MonitorContext, iterator: io_iterator_t, connected: bool) {
-    loop {
-        let service = unsafe { IOIteratorNext(iterator) };
-        if service == 0 {
-            break;
+impl Monitor {
+    fn next_deadline(&self) -> Option<Instant> {
+        if let Some((give_up, _)) = &self.stopping {
+            return Some(*give_up);
         }
+        let pending = self.profiles.iter().flat_map(|s| &s.pending);
+        self.profiles
+            .iter()
+            .flat_map(|s| [s.state.deadline(), s.check_at])
+            .flatten()
+            .chain(pending.map(|p| p.deadline))
+            .chain(self.direct.iter().map(|(deadline, _)| *deadline))
+            .min()
+    }
 
-        if let Some(uid) = read_uid(service) {
-            log::debug!(
-                "Thunderbolt switch {} – UID 0x{:016X}",
-                if connected { "appeared" } else { "removed" },
-                uid,
-            );
+    /// Whether nothing is left to wait for. Periodic checks don't count.
+    fn settled(&self) -> bool {
+        self.sleep.is_none()
+            && self.profiles.iter().all(|s| {
+                s.state.deadline().is_none()
+                    && !matches!(
+                        s.state,
+                        State::Connecting { .. } | State::Disconnecting { .. }
+                    )
+            })
+    }
 
-            if uid == ctx.dock_uid {
-                if connected {
-                    log::info!("Dock connected (UID 0x{:016X}). Starting Sidecar...", uid);
-                    sidecar::connect(ctx.ipad_name.as_deref());
-                    ctx.sidecar_active.set(true);
-                } else {
-                    log::info!(
-                        "Dock disconnected (UID 0x{:016X}). Stopping Sidecar...",
-                        uid
-                    );
-                    sidecar::disconnect(ctx.ipad_name.as_deref());
-                    ctx.sidecar_active.set(false);
+    /// Whether shutting down and no Sidecar request is outstanding.
+    fn stopped(&self) -> bool {
+        self.stopping.is_some()
+            && !self.profiles.iter().any(|s| {
+                matches!(
+                    s.state,
+                    State::Connecting { .. } | State::Disconnecting { .. }
+                )
+            })
+    }
+
+    /// Stop starting anything new, end the sessions that should not outlive the daemon and
+    /// close the dock event source. `reply` is answered once ready to exit.
+    fn shut_down(&mut self, reply: Sender<Response>) {
+        if let Some((_, replies)) = &mut self.stopping {
+            replies.push(reply);
+            return;
+        }
+        log::info!("Shutting down");
+        self.stopping = Some((Instant::now() + SHUTDOWN_TIMEOUT, vec![reply]));
+        self.sleep = None;
+        if let Some(close) = self.close_source.take() {
+            close();
+        }
+        for index in 0..self.profiles.len() {
+            self.dispatch(index, Event::Shutdown);
+        }
+    }
+
+    /// Route a hotplug event to the profiles whose dock it concerns.
+    fn handle_dock_event(&mut self, event: DockEvent) {
+        let appeared = matches!(event, DockEvent::Appeared { .. });
+        let Some(uid) = event.uid() else {
+            if !appeared {
+                self.attached.clear();
+                log::debug!("Thunderbolt switch removed (UID unreadable)");
+                for index in 0..self.profiles.len() {
+                    self.dispatch(index, Event::UnidentifiedRemoval);
+                }
+            }
+            return;
+        };
+
+        log::debug!(
+            "Thunderbolt switch {} – UID 0x{:016X}",
+            if appeared { "appeared" } else { "removed" },
+            uid,
+        );
+        if appeared {
+            self.attached.insert(uid);
+        } else {
+            self.attached.remove(&uid);
+        }
+        for index in 0..self.profiles.len() {
+            let state = &self.profiles[index];
+            if state.dock_uid != uid {
+                continue;
+            }
+            if appeared && state.state == State::Idle && self.connected_by_hand(index) {
+                log::info!(
+                    "[{}] Sidecar is already connected; leaving that session alone",
+                    state.profile.label()
+                );
+                self.dispatch(index, Event::ConnectedExternally);
+            }
+            let state = &self.profiles[index];
+            if appeared {
+                log::info!(
+                    "[{}] Dock connected (UID 0x{:016X})",
+                    state.profile.label(),
+                    uid
+                );
+                self.dispatch(index, Event::DockAppeared);
+            } else {
+                log::info!(
+                    "[{}] Dock disconnected (UID 0x{:016X})",
+                    state.profile.label(),
+                    uid
+                );
+                self.dispatch(index, Event::DockRemoved);
+            }
+        }
+    }
+
+    /// Whether the profile's iPad has a session the daemon did not start. Only asked while
+    /// the profile has none of its own.
+    fn connected_by_hand(&self, index: usize) -> bool {
+        let state = &self.profiles[index];
+        sidecar::is_connected(&*self.backend, state.profile.ipad_name.as_deref())
+            .inspect_err(|e| log::warn!("[{}] {e}", state.profile.label()))
+            .unwrap_or(false)
+    }
+
+    /// Tell every profile the system is going to sleep, holding on to `guard` until
+    /// those that disconnect for sleep have done so.
+    fn prepare_for_sleep(&mut self, guard: SleepGuard) {
+        log::info!("System is going to sleep");
+        let mut disconnecting = BTreeSet::new();
+        for index in 0..self.profiles.len() {
+            if self
+                .dispatch(index, Event::Sleep)
+                .contains(&Effect::Disconnect)
+            {
+                disconnecting.insert(self.profiles[index].id);
+            }
+        }
+        if !disconnecting.is_empty() {
+            self.sleep = Some((guard, disconnecting));
+        }
+    }
+
+    /// Catch up with dock changes missed during sleep, then let each profile know whether
+    /// its Sidecar session survived.
+    fn woke(&mut self, attached: Option<Vec<u64>>) {
+        log::info!("System woke from sleep");
+        self.sleep = None;
+
+        if let Some(attached) = attached {
+            let removed: Vec<_> = self
+                .attached
+                .iter()
+                .filter(|uid| !attached.contains(uid))
+                .copied()
+                .collect();
+            for uid in removed {
+                self.handle_dock_event(DockEvent::Removed {
+                    uid: Some(uid),
+                    properties: Default::default(),
+                });
+            }
+            for uid in attached {
+                if !self.attached.contains(&uid) {
+                    self.handle_dock_event(DockEvent::Appeared {
+                        uid: Some(uid),
+                        properties: Default::default(),
+                    });
+                }
+            }
+        }
+
+        for index in 0..self.profiles.len() {
+            let state = &self.profiles[index];
+            if !matches!(state.state, State::Asleep { .. } | State::Connected { .. }) {
+                continue;
+            }
+            let ipad_name = state.profile.ipad_name.as_deref();
+            let sidecar_connected = sidecar::is_connected(&*self.backend, ipad_name)
+                .inspect_err(|e| log::warn!("[{}] {e}", state.profile.label()))
+                .unwrap_or(false);
+            self.dispatch(index, Event::Woke { sidecar_connected });
+        }
+    }
+
+    /// Check the profiles whose check is due.
+    fn reconcile_due(&mut self) {
+        let now = Instant::now();
+        for index in 0..self.profiles.len() {
+            if self.profiles[index].check_at.is_some_and(|at| at <= now) {
+                self.reconcile(index);
+                self.profiles[index].schedule_check();
+            }
+        }
+    }
+
+    /// Compare what Sidecar is actually doing with what the profile's state says it should
+    /// be doing. Sessions that dropped or never came up are connected again; changes made
+    /// by hand are taken on board instead.
+    ///
+    /// Sidecar does not say why a session ended. One that ends while the iPad is still
+    /// offered is taken as disconnected by hand on the first check, and is not repaired.
+    fn reconcile(&mut self, index: usize) {
+        if !self.profiles[index].state.dock_present() {
+            self.profiles[index].repairs = 0;
+        }
+        let state = &self.profiles[index];
+        let label = state.profile.label();
+        if !matches!(
+            state.state,
+            State::DockPresent { .. }
+                | State::Connected { .. }
+                | State::Failed { .. }
+                | State::External { .. }
+        ) {
+            return;
+        }
+        let ipad_name = state.profile.ipad_name.as_deref();
+        let connected = match sidecar::is_connected(&*self.backend, ipad_name) {
+            Ok(connected) => connected,
+            Err(e) => {
+                log::warn!("[{label}] Cannot check the Sidecar session: {e}");
+                return;
+            }
+        };
+
+        let problem = match (state.state, connected) {
+            (State::Connected { .. } | State::External { .. }, true) => {
+                self.profiles[index].repairs = 0;
+                return;
+            }
+            (State::DockPresent { .. } | State::Failed { .. }, true) => {
+                log::info!("[{label}] Sidecar was connected by hand; leaving that session alone");
+                self.dispatch(index, Event::ConnectedExternally);
+                return;
+            }
+            (State::DockPresent { .. }, false) | (_, true) => return,
+            (State::Failed { .. }, false) => "Sidecar had failed to connect",
+            (
+                State::Connected {
+                    disconnect_at: None,
+                },
+                false,
+            ) if !sidecar::is_available(&*self.backend, ipad_name).unwrap_or(false) => {
+                "Sidecar session dropped"
+            }
+            (_, false) => {
+                let until = match state.policy.manual_cooldown {
+                    _ if !state.state.dock_present() => String::new(),
+                    Some(cooldown) => format!(" for {}s", cooldown.as_secs()),
+                    None => " until the dock is next attached".into(),
+                };
+                log::info!("[{label}] Sidecar was disconnected by hand; leaving it{until}");
+                self.dispatch(index, Event::DisconnectedExternally);
+                return;
+            }
+        };
+
+        let after = state.policy.repair_delay(state.repairs);
+        log::warn!(
+            "[{label}] {problem} while the dock is attached; reconnecting in {}s",
+            after.as_secs()
+        );
+        let correction = Correction {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            profile: label.into(),
+            problem: problem.into(),
+            retry_in_secs: after.as_secs(),
+        };
+        if self.corrections.len() == MAX_CORRECTIONS {
+            self.corrections.pop_front();
+        }
+        self.corrections.push_back(correction);
+        self.profiles[index].repairs += 1;
+        self.dispatch(index, Event::Repair { after });
+    }
+
+    /// Treat Sidecar requests unanswered for [`sidecar::COMPLETION_TIMEOUT`] as timed out.
+    /// A connect that succeeds afterwards is still taken into account.
+    fn expire_requests(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .profiles
+            .iter()
+            .flat_map(|s| {
+                s.pending
+                    .iter()
+                    .filter(|p| p.deadline <= now)
+                    .map(|pending| Completed {
+                        profile: s.id,
+                        id: pending.id,
+                        request: pending.request,
+                        result: Err(SidecarError::Timeout),
+                    })
+            })
+            .collect();
+        for completed in expired {
+            self.handle_completion(completed);
+        }
+
+        self.direct.retain(|(deadline, waiting)| {
+            let mut waiting = waiting.lock().unwrap_or_else(|e| e.into_inner());
+            if *deadline <= now
+                && let Some(reply) = waiting.take()
+            {
+                let _ = reply.send(Response::sidecar_error(&SidecarError::Timeout));
+            }
+            waiting.is_some()
+        });
+    }
+
+    fn handle_completion(&mut self, completed: Completed) {
+        let index = self.profiles.iter().position(|s| s.id == completed.profile);
+        // Whether the state machine still wants the answer. Stale ones are still passed on
+        // to waiting clients and to the sleep bookkeeping.
+        let current = index.is_some_and(|index| {
+            let state = &mut self.profiles[index];
+            let label = state.profile.label();
+            let before = state.pending.len();
+            state.pending.retain(|p| p.id != completed.id);
+            if state.pending.len() < before {
+                true
+            } else if completed.request == Effect::Connect && completed.result.is_ok() {
+                // Given up on, but the session came up after all.
+                log::info!("[{label}] Sidecar answered after timing out");
+                true
+            } else {
+                log::debug!(
+                    "[{label}] Ignoring late Sidecar completion: {:?}",
+                    completed.result
+                );
+                false
+            }
+        });
+        if completed.request == Effect::Disconnect
+            && let Some((_, disconnecting)) = &mut self.sleep
+        {
+            disconnecting.remove(&completed.profile);
+            if disconnecting.is_empty() {
+                log::debug!("Ready for sleep");
+                self.sleep = None;
+            }
+        }
+        let Some(index) = index else {
+            log::debug!("Ignoring Sidecar completion for a profile removed by reload");
+            return;
+        };
+        let state = &mut self.profiles[index];
+        let (answered, waiting) = state
+            .waiting
+            .drain(..)
+            .partition::<Vec<_>, _>(|(request, _)| *request == completed.request);
+        state.waiting = waiting;
+        for (_, reply) in answered {
+            let _ = reply.send(match &completed.result {
+                Ok(()) => Response::Ok,
+                Err(e) => Response::sidecar_error(e),
+            });
+        }
+        if !current {
+            return;
+        }
+
+        let state = &self.profiles[index];
+        let event = match (completed.request, completed.result) {
+            (Effect::Connect, Ok(())) => {
+                log::info!("[{}] Sidecar connected successfully", state.profile.label());
+                Event::ConnectSucceeded
+            }
+            (Effect::Connect, Err(e)) => {
+                log_connect_failure(state, &e);
+                Event::ConnectFailed {
+                    retryable: e.is_retryable(),
+                }
+            }
+            (Effect::Disconnect, Ok(())) => {
+                log::info!(
+                    "[{}] Sidecar disconnected successfully",
+                    state.profile.label()
+                );
+                Event::DisconnectCompleted
+            }
+            (Effect::Disconnect, Err(SidecarError::DeviceNotFound { .. })) => {
+                log::debug!(
+                    "[{}] No matching Sidecar device found for disconnect (may already be gone)",
+                    state.profile.label()
+                );
+                Event::DisconnectCompleted
+            }
+            (Effect::Disconnect, Err(e)) => {
+                log::error!("[{}] Sidecar disconnect failed: {e}", state.profile.label());
+                Event::DisconnectCompleted
+            }
+        };
+        self.dispatch(index, event);
+        if self.stopping.is_some() {
+            // A session that came up while shutting down may still need ending.
+            self.dispatch(index, Event::Shutdown);
+        }
+    }
+
+    fn handle_request(&mut self, Incoming { request, reply }: Incoming) {
+        if self.stopping.is_some() && request != Request::Shutdown {
+            let _ = reply.send(Response::error("The daemon is shutting down"));
+            return;
+        }
+        let response = match request {
+            Request::Status => Response::Status {
+                profiles: self
+                    .profiles
+                    .iter()
+                    .map(|s| s.live_status(&*self.backend))
+                    .collect(),
+            },
+            Request::Connect { profile } => {
+                return self.request_sidecar(profile.as_deref(), Effect::Connect, reply);
+            }
+            Request::Disconnect { profile } => {
+                return self.request_sidecar(profile.as_deref(), Effect::Disconnect, reply);
+            }
+            Request::Pause { profile } => self.dispatch_to(profile.as_deref(), Event::Pause),
+            Request::Resume { profile } => self.dispatch_to(profile.as_deref(), Event::Resume),
+            Request::Reload => match self.reload() {
+                Ok(()) => Response::Ok,
+                Err(message) => Response::error(message),
+            },
+            Request::Corrections => Response::Corrections {
+                corrections: self.corrections.iter().cloned().collect(),
+            },
+            Request::Shutdown => return self.shut_down(reply),
+            Request::Subscribe => {
+                let snapshot = Response::Status {
+                    profiles: self.status(),
+                };
+                if reply.send(snapshot).is_ok() {
+                    self.subscribers.push(reply);
+                }
+                return;
+            }
+        };
+        let _ = reply.send(response);
+    }
+
+    fn status(&self) -> Vec<ProfileStatus> {
+        self.profiles.iter().map(ProfileState::status).collect()
+    }
+
+    /// Ask a profile to connect or disconnect Sidecar now. `reply` is answered once the
+    /// outcome is known, or straight away when there is nothing to do.
+    ///
+    /// Requests the state machine has no use for, such as connecting without the dock or
+    /// while paused, go straight to Sidecar and leave the profile's state alone.
+    fn request_sidecar(&mut self, name: Option<&str>, request: Effect, reply: Sender<Response>) {
+        let index = match self.select_one(name) {
+            Ok(i) => i,
+            Err(message) => {
+                let _ = reply.send(Response::error(message));
+                return;
+            }
+        };
+
+        let state = &self.profiles[index];
+        let label = state.profile.label();
+        log::info!("[{label}] {request:?} requested over the control socket");
+        let response = match (request, state.state) {
+            (Effect::Connect, State::Disconnecting { .. }) => {
+                Response::error(format!("'{label}' is still disconnecting"))
+            }
+            (Effect::Disconnect, State::Connecting { .. }) => {
+                Response::error(format!("'{label}' is still connecting"))
+            }
+            (Effect::Connect, State::Connected { .. }) => Response::Ok,
+            (Effect::Connect, State::DockPresent { .. } | State::Failed { .. })
+            | (Effect::Disconnect, State::Connected { .. }) => {
+                let event = match request {
+                    Effect::Connect => Event::ConnectRequested,
+                    Effect::Disconnect => Event::DisconnectRequested,
+                };
+                self.profiles[index].waiting.push((request, reply));
+                self.dispatch(index, event);
+                return;
+            }
+            // Already underway: answer with the outcome of the pending request.
+            (Effect::Connect, State::Connecting { .. })
+            | (Effect::Disconnect, State::Disconnecting { .. }) => {
+                self.profiles[index].waiting.push((request, reply));
+                return;
+            }
+            _ => {
+                let ipad_name = state.profile.ipad_name.as_deref();
+                let waiting: DirectReply = Arc::new(Mutex::new(Some(reply)));
+                let answer = waiting.clone();
+                let done: sidecar::Completion = Box::new(move |result| {
+                    let Some(reply) = answer.lock().ok().and_then(|mut r| r.take()) else {
+                        return;
+                    };
+                    let _ = reply.send(match result {
+                        Ok(()) => Response::Ok,
+                        Err(e) => Response::sidecar_error(&e),
+                    });
+                });
+                let deadline = Instant::now() + sidecar::COMPLETION_TIMEOUT;
+                self.direct.push((deadline, waiting));
+                match request {
+                    Effect::Connect => sidecar::connect(&*self.backend, ipad_name, done),
+                    Effect::Disconnect => sidecar::disconnect(&*self.backend, ipad_name, done),
                 }
+                return;
             }
-        } else if !connected && ctx.sidecar_active.get() {
+        };
+        let _ = reply.send(response);
+    }
+
+    /// Feed `event` to the named profile, or to every profile.
+    fn dispatch_to(&mut self, name: Option<&str>, event: Event) -> Response {
+        let indices = match name {
+            Some(_) => match self.select_one(name) {
+                Ok(index) => vec![index],
+                Err(message) => return Response::error(message),
+            },
+            None => (0..self.profiles.len()).collect(),
+        };
+        for index in indices {
             log::info!(
-                "Thunderbolt switch removed (UID unreadable). Disconnecting Sidecar as precaution."
+                "[{}] {event:?} requested over the control socket",
+                self.profiles[index].profile.label()
             );
-            sidecar::disconnect(ctx.ipad_name.as_deref());
-            ctx.sidecar_active.set(false);
+            self.dispatch(index, event);
         }
+        Response::Ok
+    }
 
-        unsafe { IOObjectRelease(service) };
+    /// The profile called `name`, or the only profile when no name is given.
+    fn select_one(&self, name: Option<&str>) -> Result<usize, String> {
+        match name {
+            Some(name) => self
+                .profiles
+                .iter()
+                .position(|s| s.profile.label() == name)
+                .ok_or_else(|| format!("No profile named '{name}'")),
+            None if self.profiles.len() == 1 => Ok(0),
+            None => {
+                let names: Vec<_> = self.profiles.iter().map(|s| s.profile.label()).collect();
+                Err(format!("Name a profile: {}", names.join(", ")))
+            }
+        }
     }
-}
 
-/// Read the `"UID"` property (SInt64) from an IORegistry entry.
-fn read_uid(service: io_service_t) -> Option<u64> {
-    unsafe {
-        let mut props_ref: core_foundation_sys::dictionary::CFMutableDictionaryRef =
-            ptr::null_mut();
-        let kr =
-            IORegistryEntryCreateCFProperties(service, &mut props_ref, kCFAllocatorDefault as _, 0);
-        if kr != KERN_SUCCESS || props_ref.is_null() {
-            return None;
+    /// Switch to freshly loaded profiles, keeping the state of those that still pair the
+    /// same dock with the same iPad. The current profiles stay in place if loading fails.
+    fn reload(&mut self) -> Result<(), String> {
+        let Some(load) = self.reload.as_mut() else {
+            return Err("Reloading is not supported".into());
+        };
+        let loaded = load()
+            .and_then(|profiles| {
+                profiles
+                    .into_iter()
+                    .map(ProfileState::new)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .inspect_err(|e| log::error!("Reload failed, keeping the current config: {e}"))?;
+
+        let mut old = std::mem::take(&mut self.profiles);
+        let mut added = Vec::new();
+        for fresh in loaded {
+            let kept = old.iter().position(|s| {
+                s.dock_uid == fresh.dock_uid && s.profile.ipad_name == fresh.profile.ipad_name
+            });
+            match kept {
+                Some(i) => {
+                    let mut state = old.remove(i);
+                    state.policy = fresh.policy;
+                    state.profile = fresh.profile;
+                    state.check_at = fresh.check_at;
+                    self.profiles.push(state);
+                }
+                None => {
+                    added.push(self.profiles.len());
+                    self.profiles.push(fresh);
+                }
+            }
+        }
+        for state in &old {
+            if matches!(state.state, State::Connected { .. }) {
+                log::warn!(
+                    "[{}] Profile removed; its Sidecar session is left connected",
+                    state.profile.label()
+                );
+            } else {
+                log::info!("[{}] Profile removed", state.profile.label());
+            }
+        }
+        log::info!("Reloaded config: {} dock profile(s)", self.profiles.len());
+        if !self.subscribers.is_empty() {
+            let reloaded = Response::Reloaded {
+                profiles: self.status(),
+            };
+            self.subscribers
+                .retain(|subscriber| subscriber.send(reloaded.clone()).is_ok());
+        }
+
+        for index in added {
+            if self.attached.contains(&self.profiles[index].dock_uid) {
+                self.dispatch(index, Event::DockAppeared);
+            }
+        }
+        Ok(())
+    }
+
+    /// Feed `event` into a profile's state machine and start the resulting Sidecar
+    /// requests, which are returned.
+    ///
+    /// Requests never block: their outcomes come back through the input channel, and
+    /// failed connect attempts schedule the next one as a deadline, so dock events keep
+    /// being handled in between.
+    fn dispatch(&mut self, index: usize, event: Event) -> Vec<Effect> {
+        let state = &mut self.profiles[index];
+        let before = state.state;
+        let (after, effects) = state::step(&state.policy, before, event, Instant::now());
+        state.state = after;
+
+        let state = &self.profiles[index];
+        let profile = &state.profile;
+        if before != after {
+            log::debug!("[{}] {before:?} --{event:?}--> {after:?}", profile.label());
+            log_transition(state, before, after);
+            let notices = state::notices(before, after);
+            for notice in &notices {
+                self.fire_hook(index, *notice);
+            }
+            if !self.subscribers.is_empty() {
+                let changed = Response::Changed {
+                    profile: state.status(),
+                    notices,
+                };
+                self.subscribers
+                    .retain(|subscriber| subscriber.send(changed.clone()).is_ok());
+            }
         }
 
-        let uid_key = CFString::new("UID");
-        let raw_val = CFDictionaryGetValue(props_ref as CFDictionaryRef, uid_key.as_CFTypeRef());
+        for &effect in &effects {
+            let inputs = self.inputs.clone();
+            let profile_id = self.profiles[index].id;
+            let id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+            let done: sidecar::Completion = Box::new(move |result| {
+                let _ = inputs.send(Input::Completed(Completed {
+                    profile: profile_id,
+                    id,
+                    request: effect,
+                    result,
+                }));
+            });
+            let state = &mut self.profiles[index];
+            // A newer request of the same kind supersedes the one before.
+            state.pending.retain(|p| p.request != effect);
+            state.pending.push(Pending {
+                id,
+                request: effect,
+                deadline: Instant::now() + sidecar::COMPLETION_TIMEOUT,
+            });
+            let profile = &state.profile;
+            match effect {
+                Effect::Connect => {
+                    if matches!(after, State::Connecting { attempt: 1, .. }) {
+                        log::info!("[{}] Starting Sidecar...", profile.label());
+                    }
+                    sidecar::connect(&*self.backend, profile.ipad_name.as_deref(), done);
+                }
+                Effect::Disconnect => {
+                    log::info!("[{}] Stopping Sidecar...", profile.label());
+                    sidecar::disconnect(&*self.backend, profile.ipad_name.as_deref(), done);
+                }
+            }
+        }
+        effects
+    }
 
-        let result = if raw_val.is_null() {
-            None
-        } else {
-            let number =
-                CFNumber::wrap_under_get_rule(raw_val as core_foundation_sys::number::CFNumberRef);
-            number.to_i64().map(|v| v as u64)
+    /// Queue the profile's hook for `notice`, if it has one.
+    fn fire_hook(&self, index: usize, notice: Notice) {
+        let state = &self.profiles[index];
+        let profile = &state.profile;
+        let (event, Some(hook)) = profile.options.hooks.for_notice(notice) else {
+            return;
         };
+        self.hooks.fire(HookJob {
+            label: profile.label().into(),
+            event,
+            hook: hook.clone(),
+            env: vec![
+                ("DOCK_UID".into(), format!("0x{:016X}", state.dock_uid)),
+                (
+                    "IPAD_NAME".into(),
+                    profile.ipad_name.clone().unwrap_or_default(),
+                ),
+                ("PROFILE".into(), profile.label().into()),
+            ],
+        });
+    }
+}
+
+fn log_connect_failure(state: &ProfileState, error: &SidecarError) {
+    let label = state.profile.label();
+    match error {
+        SidecarError::DeviceNotFound { wanted, available } => {
+            if !matches!(state.state, State::Connecting { attempt: 1, .. }) {
+                return;
+            }
+            if available.is_empty() {
+                log::info!("[{label}] Sidecar devices list is currently empty");
+            } else {
+                log::info!("[{label}] Available Sidecar devices: {available:?}");
+                if let Some(target) = wanted {
+                    log::info!(
+                        "[{label}] Looking for: {target:?} (check config if name doesn't match)"
+                    );
+                }
+            }
+        }
+        e => log::error!("[{label}] Sidecar connection failed: {e}"),
+    }
+}
+
+/// Log retries, give-ups and cancellations that the effects alone don't reveal.
+fn log_transition(state: &ProfileState, before: State, after: State) {
+    let label = state.profile.label();
+    let max = state.policy.max_attempts;
 
-        CFRelease(props_ref as *const c_void);
-        result
+    match (before, after) {
+        (
+            State::Connecting { attempt, .. },
+            State::DockPresent {
+                next_attempt: Some(next),
+            },
+        ) => log::info!(
+            "[{label}] Sidecar connect attempt {attempt}/{max} failed, retrying in {}s...",
+            next.at.saturating_duration_since(Instant::now()).as_secs()
+        ),
+        (State::Connecting { attempt, .. }, State::Failed { .. }) => {
+            log::warn!("[{label}] Giving up on Sidecar connect after {attempt}/{max} attempts")
+        }
+        (
+            State::DockPresent {
+                next_attempt: Some(Attempt { number, .. }),
+            },
+            State::Idle,
+        ) if number > 1 => log::info!("[{label}] Dock removed; cancelled pending Sidecar connect"),
+        _ => {}
     }
 }
@@ -0,0 +1,269 @@
+//! Dock and Sidecar events as an async [`Stream`], for tools built on tokio or any other
+//! executor. Needs the `stream` feature.
+//!
+//! ```no_run
+//! # async fn watch() -> Result<(), String> {
+//! use std::future::poll_fn;
+//! use std::pin::Pin;
+//!
+//! use futures_core::Stream;
+//!
+//! let mut events = sidecar_on_dock::events()?;
+//! // Or `events.next().await` with `StreamExt` from `futures` or `tokio-stream`.
+//! while let Some(event) = poll_fn(|cx| Pin::new(&mut events).poll_next(cx)).await {
+//!     println!("{event:?}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`events`] follows the daemon already running, normally the launchd agent, over its
+//! control socket, and fails when there is none. An app that monitors the docks itself
+//! instead builds a [`Daemon`](crate::daemon::Daemon) and streams its events with
+//! [`DaemonBuilder::events`].
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures_core::Stream;
+
+use crate::control::{self, Client, ProfileStatus, Response};
+use crate::daemon::{DaemonBuilder, Handle};
+use crate::dock_event::Closer;
+use crate::state::{Notice, State};
+
+/// Something that happened to a profile's dock or Sidecar session, or to the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The profile's dock was attached.
+    DockAppeared { profile: String },
+    /// The profile's dock was removed.
+    DockRemoved { profile: String },
+    /// Sidecar is being connected to the profile's iPad.
+    SidecarConnecting { profile: String },
+    /// Sidecar connected.
+    SidecarConnected { profile: String },
+    /// Every connect attempt failed.
+    SidecarFailed { profile: String },
+    /// The profile's iPad no longer has a Sidecar session.
+    SidecarDisconnected { profile: String },
+    /// The config was reloaded; `profiles` is how many it now has.
+    ConfigReloaded { profiles: usize },
+}
+
+/// The [`Event`]s of a running daemon.
+pub struct Events {
+    queue: Arc<Mutex<Queue>>,
+    source: Source,
+}
+
+/// Where an [`Events`] stream comes from.
+enum Source {
+    /// A daemon's control socket, with what ends the subscription.
+    Socket(Option<Closer>),
+    /// A daemon started for the stream.
+    Daemon(Handle),
+}
+
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<Event>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// Stream what happens to the docks of the daemon listening on the default control
+/// socket.
+pub fn events() -> Result<Events, String> {
+    Events::connect(&control::default_socket_path())
+}
+
+impl DaemonBuilder {
+    /// Build the daemon and run it on a thread of its own, streaming its [`Event`]s.
+    /// Dropping the stream stops the daemon.
+    ///
+    /// The daemon drives Sidecar itself, so this is for apps that monitor the docks
+    /// instead of the launchd agent, not alongside it; to follow the agent, use
+    /// [`events`].
+    pub fn events(self) -> Result<Events, String> {
+        let (changes_tx, changes) = mpsc::channel();
+        let daemon = self.subscriber(changes_tx).build()?;
+        let handle = daemon.handle();
+        let queue = Arc::new(Mutex::new(Queue::default()));
+
+        let feed = queue.clone();
+        thread::Builder::new()
+            .name("stream-events".into())
+            .spawn(move || translate(changes, HashMap::new(), &feed))
+            .map_err(|e| format!("Failed to start event thread: {e}"))?;
+        thread::Builder::new()
+            .name("daemon".into())
+            .spawn(move || {
+                if let Err(e) = daemon.run() {
+                    log::error!("Daemon stopped: {e}");
+                }
+            })
+            .map_err(|e| format!("Failed to start daemon thread: {e}"))?;
+        Ok(Events {
+            queue,
+            source: Source::Daemon(handle),
+        })
+    }
+}
+
+impl Events {
+    /// Stream what happens to the docks of the daemon listening on `socket`. Dropping the
+    /// stream leaves the daemon running.
+    pub fn connect(socket: &Path) -> Result<Self, String> {
+        let mut subscription = Client::open(socket)?.subscribe()?;
+        // Waiting for the snapshot makes sure nothing after this call is missed.
+        let last = match subscription.next() {
+            Some(Ok(Response::Status { profiles })) => seen_all(&profiles),
+            Some(Ok(other)) => return Err(control::unexpected(other)),
+            Some(Err(e)) => return Err(e),
+            None => return Err("The daemon closed the connection".into()),
+        };
+        let closer = subscription.closer()?;
+        let queue = Arc::new(Mutex::new(Queue::default()));
+
+        let feed = queue.clone();
+        thread::Builder::new()
+            .name("stream-events".into())
+            .spawn(move || {
+                let changes = subscription.map_while(|change| {
+                    change
+                        .inspect_err(|e| log::warn!("Event stream ended: {e}"))
+                        .ok()
+                });
+                translate(changes, last, &feed)
+            })
+            .map_err(|e| format!("Failed to start event thread: {e}"))?;
+        Ok(Events {
+            queue,
+            source: Source::Socket(Some(closer)),
+        })
+    }
+
+    /// A handle for sending requests to the daemon started by
+    /// [`DaemonBuilder::events`]. `None` when following a daemon over its control socket,
+    /// which takes requests through a [`Client`] instead.
+    pub fn handle(&self) -> Option<Handle> {
+        match &self.source {
+            Source::Daemon(handle) => Some(handle.clone()),
+            Source::Socket(_) => None,
+        }
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(event) = queue.events.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+        if queue.closed {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        match &mut self.source {
+            Source::Socket(closer) => {
+                if let Some(close) = closer.take() {
+                    close();
+                }
+            }
+            Source::Daemon(handle) => handle.stop(),
+        }
+    }
+}
+
+/// Turn the daemon's updates into [`Event`]s until it stops, then end the stream.
+///
+/// `last` holds whether every profile was connecting, and had a session, as of its last
+/// update, to spot the changes that come without a notice.
+fn translate(
+    changes: impl IntoIterator<Item = Response>,
+    mut last: HashMap<String, (bool, bool)>,
+    queue: &Mutex<Queue>,
+) {
+    for change in changes {
+        let mut events = Vec::new();
+        match change {
+            Response::Changed { profile, notices } => {
+                let (connecting, connected) = last
+                    .insert(profile.name.clone(), seen(&profile))
+                    .unwrap_or_default();
+                let name = profile.name;
+                for notice in notices {
+                    events.push(match notice {
+                        Notice::DockConnected => Event::DockAppeared {
+                            profile: name.clone(),
+                        },
+                        Notice::DockDisconnected => Event::DockRemoved {
+                            profile: name.clone(),
+                        },
+                        Notice::SidecarConnected => Event::SidecarConnected {
+                            profile: name.clone(),
+                        },
+                        Notice::SidecarFailed => Event::SidecarFailed {
+                            profile: name.clone(),
+                        },
+                    });
+                }
+                if profile.state == State::CONNECTING && !connecting {
+                    events.push(Event::SidecarConnecting {
+                        profile: name.clone(),
+                    });
+                }
+                if connected && !profile.sidecar_connected {
+                    events.push(Event::SidecarDisconnected { profile: name });
+                }
+            }
+            Response::Reloaded { profiles } => {
+                last = seen_all(&profiles);
+                events.push(Event::ConfigReloaded {
+                    profiles: profiles.len(),
+                });
+            }
+            _ => {}
+        }
+        push(queue, events, false);
+    }
+    push(queue, Vec::new(), true);
+}
+
+fn seen(profile: &ProfileStatus) -> (bool, bool) {
+    (
+        profile.state == State::CONNECTING,
+        profile.sidecar_connected,
+    )
+}
+
+fn seen_all(profiles: &[ProfileStatus]) -> HashMap<String, (bool, bool)> {
+    profiles.iter().map(|p| (p.name.clone(), seen(p))).collect()
+}
+
+fn push(queue: &Mutex<Queue>, events: Vec<Event>, closed: bool) {
+    if events.is_empty() && !closed {
+        return;
+    }
+    let mut queue = queue.lock().unwrap();
+    queue.events.extend(events);
+    queue.closed |= closed;
+    if let Some(waker) = queue.waker.take() {
+        waker.wake();
+    }
+}
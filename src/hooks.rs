@@ -0,0 +1,209 @@
+//! Run user-configured shell hooks.
+//!
+//! Hooks are queued on a [`HookRunner`] and executed one at a time on a worker thread,
+//! in the order they were fired, so a slow script never holds up dock handling.
+
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::config::{FailurePolicy, Hook, Hooks};
+use crate::state::Notice;
+
+/// How often a running hook is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long past the timeout to keep reading output, for the last lines of a killed hook.
+const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+/// A hook to run, with the details passed to it.
+#[derive(Debug, Clone)]
+pub struct HookJob {
+    /// Profile label, used as the log prefix.
+    pub label: String,
+    /// Hook name, e.g. `on_connect`. Exported as `EVENT`.
+    pub event: &'static str,
+    pub hook: Hook,
+    /// Extra environment variables, e.g. `DOCK_UID` and `IPAD_NAME`.
+    pub env: Vec<(String, String)>,
+}
+
+/// Runs [`HookJob`]s in order on a background thread. Dropping the runner waits for
+/// queued hooks to finish.
+#[derive(Debug)]
+pub struct HookRunner {
+    jobs: Option<Sender<HookJob>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Hooks {
+    /// The hook configured for `notice`, and its name.
+    pub fn for_notice(&self, notice: Notice) -> (&'static str, Option<&Hook>) {
+        match notice {
+            Notice::DockConnected => ("on_connect", self.on_connect.as_ref()),
+            Notice::DockDisconnected => ("on_disconnect", self.on_disconnect.as_ref()),
+            Notice::SidecarConnected => {
+                ("on_sidecar_connected", self.on_sidecar_connected.as_ref())
+            }
+            Notice::SidecarFailed => ("on_sidecar_failed", self.on_sidecar_failed.as_ref()),
+        }
+    }
+}
+
+impl HookRunner {
+    /// Start the worker thread.
+    pub fn start() -> Result<Self, String> {
+        let (jobs, rx) = mpsc::channel::<HookJob>();
+        let worker = thread::Builder::new()
+            .name("hooks".into())
+            .spawn(move || {
+                for job in rx {
+                    let _ = run(&job);
+                }
+            })
+            .map_err(|e| format!("Failed to start hook thread: {e}"))?;
+        Ok(Self {
+            jobs: Some(jobs),
+            worker: Some(worker),
+        })
+    }
+
+    /// Queue `job` without waiting for it.
+    pub fn fire(&self, job: HookJob) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(job);
+        }
+    }
+}
+
+impl Drop for HookRunner {
+    fn drop(&mut self) {
+        self.jobs.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Run a hook to completion, applying its failure policy, and log its output.
+///
+/// Returns the output lines of the successful run, or why the last attempt failed.
+pub fn run(job: &HookJob) -> Result<Vec<String>, String> {
+    let HookJob {
+        label, event, hook, ..
+    } = job;
+    let attempts = match hook.on_failure {
+        FailurePolicy::Retry(retries) => retries.saturating_add(1),
+        FailurePolicy::Warn | FailurePolicy::Ignore => 1,
+    };
+
+    let mut failure = String::new();
+    for attempt in 1..=attempts {
+        log::info!("[{label}] Running {event} hook");
+        match run_once(job) {
+            Ok(output) => return Ok(output),
+            Err(e) if attempt < attempts => {
+                log::info!("[{label}] {event} hook failed ({e}), retrying ({attempt}/{attempts})")
+            }
+            Err(e) => failure = e,
+        }
+    }
+
+    match hook.on_failure {
+        FailurePolicy::Ignore => log::debug!("[{label}] {event} hook failed: {failure}"),
+        FailurePolicy::Warn | FailurePolicy::Retry(_) => {
+            log::warn!("[{label}] {event} hook failed: {failure}")
+        }
+    }
+    Err(failure)
+}
+
+/// Run the command once, then log its output.
+fn run_once(job: &HookJob) -> Result<Vec<String>, String> {
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&job.hook.command)
+        .env("EVENT", job.event)
+        .envs(job.env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("could not start: {e}"))?;
+
+    let stdout = collect_lines(child.stdout.take());
+    let stderr = collect_lines(child.stderr.take());
+
+    let timeout = Duration::from_secs(job.hook.timeout_secs);
+    let deadline = Instant::now() + timeout;
+    let status: Option<ExitStatus> = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if Instant::now() >= deadline => {
+                // Kill the whole group so commands started by the shell die too and
+                // release the output pipes.
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(format!("could not wait for command: {e}")),
+        }
+    };
+
+    // A process the command left running in the background may hold the pipes open long
+    // after the shell has exited, so they are only read until about the deadline.
+    let read_by = deadline.max(Instant::now()) + OUTPUT_GRACE;
+    let (stdout, stdout_done) = lines_until(&stdout, read_by);
+    let (stderr, stderr_done) = lines_until(&stderr, read_by);
+    if !(stdout_done && stderr_done) {
+        log::debug!(
+            "[{}] {}: stopped reading output still held open by a background process",
+            job.label,
+            job.event
+        );
+    }
+    for line in &stdout {
+        log::info!("[{}] {}: {line}", job.label, job.event);
+    }
+    for line in &stderr {
+        log::warn!("[{}] {}: {line}", job.label, job.event);
+    }
+
+    match status {
+        None => Err(format!("timed out after {}s", timeout.as_secs())),
+        Some(status) if status.success() => Ok(stdout),
+        Some(status) => Err(format!("exited with {status}")),
+    }
+}
+
+/// Read `pipe` to the end on a separate thread so the child never blocks on a full pipe,
+/// passing on each line. The channel closes once the pipe does.
+fn collect_lines<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let lines = pipe.into_iter().flat_map(|p| BufReader::new(p).lines());
+        for line in lines.map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                return;
+            }
+        }
+    });
+    rx
+}
+
+/// The lines read from a pipe by `deadline`, and whether that was all of them.
+fn lines_until(lines: &Receiver<String>, deadline: Instant) -> (Vec<String>, bool) {
+    let mut read = Vec::new();
+    loop {
+        match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(line) => read.push(line),
+            Err(RecvTimeoutError::Disconnected) => return (read, true),
+            Err(RecvTimeoutError::Timeout) => return (read, false),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! The `init` wizard: pick a dock and an iPad from what is connected and write a config
+//! for them. Also finds a dock's UID by watching which devices change while it is
+//! plugged in or out, for `discover --identify`.
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::config::{Config, parse_uid};
+use crate::sidecar::normalise_quotes;
+
+/// A dock the user can pick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dock {
+    pub name: String,
+    pub vendor: String,
+    /// UID as reported by discovery, e.g. `"0x003DA86E85A8CB00"`.
+    pub uid: String,
+}
+
+/// What was given on the command line. Anything missing is asked for.
+#[derive(Debug, Clone, Default)]
+pub struct Answers {
+    /// Dock UID as a hex string.
+    pub dock: Option<String>,
+    /// iPad name.
+    pub ipad: Option<String>,
+}
+
+/// Build a config from `answers`, asking on `input` and `output` for whatever is missing.
+///
+/// `docks` and `ipads` are what discovery found; a dock or iPad given in `answers` need
+/// not be among them.
+pub fn build(
+    docks: &[Dock],
+    ipads: &[String],
+    answers: Answers,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Config, String> {
+    let dock_uid = match answers.dock {
+        Some(uid) => format!("0x{:016X}", parse_uid(&uid)?),
+        None => {
+            let docks: Vec<_> = docks.iter().filter(|d| parse_uid(&d.uid).is_ok()).collect();
+            if docks.is_empty() {
+                return Err("No Thunderbolt docks found; plug the dock in or pass --dock".into());
+            }
+            let labels: Vec<_> = docks
+                .iter()
+                .map(|d| format!("{} ({}) {}", d.name, d.vendor, d.uid))
+                .collect();
+            let picked =
+                choose("Docks", "Which dock?", &labels, None, input, output).map_err(unattended)?;
+            let uid = parse_uid(&docks[picked.unwrap_or_default()].uid)?;
+            format!("0x{uid:016X}")
+        }
+    };
+
+    let ipad_name = match answers.ipad {
+        // Keep the name the way Sidecar spells it, whichever quotes were typed.
+        Some(name) => Some(
+            ipads
+                .iter()
+                .find(|d| normalise_quotes(d) == normalise_quotes(&name))
+                .cloned()
+                .unwrap_or(name),
+        ),
+        None if ipads.is_empty() => {
+            writeln!(
+                output,
+                "No iPads found; Sidecar will use whichever one is available"
+            )
+            .map_err(write_error)?;
+            None
+        }
+        None => {
+            let any = Some("Whichever is available");
+            choose("iPads", "Which iPad?", ipads, any, input, output)
+                .map_err(unattended)?
+                .map(|i| ipads[i].clone())
+        }
+    };
+
+    Ok(Config {
+        dock_uid: Some(dock_uid),
+        ipad_name,
+        ..Default::default()
+    })
+}
+
+/// Fail unless `path` can be written: it must not exist, or `force` must be set.
+pub fn check_target(path: &Path, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists; pass --force to replace it",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Save `config` to `path`, refusing to replace an existing file without `force`.
+pub fn write(config: &Config, path: &Path, force: bool) -> Result<(), String> {
+    check_target(path, force)?;
+    config.save(path)
+}
+
+/// A dock that was plugged in or unplugged between two snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub dock: Dock,
+    /// Whether it was plugged in, rather than unplugged.
+    pub attached: bool,
+}
+
+/// Devices in `after` but not `before`, then those in `before` but not `after`.
+pub fn changed_docks(before: &[Dock], after: &[Dock]) -> Vec<Change> {
+    let missing_from = |docks: &[Dock], dock: &Dock| docks.iter().all(|d| d.uid != dock.uid);
+    let attached = after
+        .iter()
+        .filter(|d| missing_from(before, d))
+        .map(|d| Change {
+            dock: d.clone(),
+            attached: true,
+        });
+    let detached = before
+        .iter()
+        .filter(|d| missing_from(after, d))
+        .map(|d| Change {
+            dock: d.clone(),
+            attached: false,
+        });
+    attached.chain(detached).collect()
+}
+
+/// Ask the user to plug in or unplug their dock, and report which devices changed.
+///
+/// `snapshot` lists the connected devices. Asks again while nothing has changed.
+pub fn identify(
+    mut snapshot: impl FnMut() -> Result<Vec<Dock>, String>,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Vec<Change>, String> {
+    let before = snapshot()?;
+    writeln!(
+        output,
+        "Found {} Thunderbolt device(s). Plug in or unplug your dock, wait a few seconds, \
+         then press Enter.",
+        before.len()
+    )
+    .map_err(write_error)?;
+
+    loop {
+        read_answer(input)?;
+        let changes = changed_docks(&before, &snapshot()?);
+        if changes.is_empty() {
+            writeln!(
+                output,
+                "Nothing changed yet. Press Enter to look again, or Ctrl-D to stop."
+            )
+            .map_err(write_error)?;
+            continue;
+        }
+
+        for (attached, heading) in [(true, "Plugged in"), (false, "Unplugged")] {
+            let docks: Vec<_> = changes.iter().filter(|c| c.attached == attached).collect();
+            if docks.is_empty() {
+                continue;
+            }
+            writeln!(output, "{heading}:").map_err(write_error)?;
+            for change in docks {
+                let d = &change.dock;
+                writeln!(output, "  {} ({}) {}", d.name, d.vendor, d.uid).map_err(write_error)?;
+            }
+        }
+        if changes.len() > 1 {
+            writeln!(
+                output,
+                "Docks that chain several Thunderbolt controllers show up more than once; \
+                 the first is usually the dock itself."
+            )
+            .map_err(write_error)?;
+        }
+        return Ok(changes);
+    }
+}
+
+/// Offer to save one of the `changes` as the dock UID in the config at `path`.
+///
+/// A missing config is created. In a config with several profiles, the user picks the
+/// profile to update. Returns whether anything was saved.
+pub fn offer_to_save(
+    changes: &[Change],
+    path: &Path,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<bool, String> {
+    let labels: Vec<_> = changes
+        .iter()
+        .map(|c| format!("{} ({})", c.dock.uid, c.dock.name))
+        .collect();
+    let heading = format!("Save a dock UID to {}", path.display());
+    let Some(picked) = choose(
+        &heading,
+        "Which UID?",
+        &labels,
+        Some("Don't save"),
+        input,
+        output,
+    )?
+    else {
+        return Ok(false);
+    };
+    let uid = format!("0x{:016X}", parse_uid(&changes[picked].dock.uid)?);
+
+    let mut config = if path.exists() {
+        Config::load(path)?
+    } else {
+        Config::default()
+    };
+    if config.profiles.is_empty() {
+        config.dock_uid = Some(uid);
+    } else {
+        let labels: Vec<_> = config
+            .profiles
+            .iter()
+            .map(|p| format!("{} ({})", p.label(), p.dock_uid))
+            .collect();
+        let picked = choose("Profiles", "Which profile?", &labels, None, input, output)?;
+        config.profiles[picked.unwrap_or_default()].dock_uid = uid;
+    }
+    config.save(path)?;
+    writeln!(output, "Saved {}", path.display()).map_err(write_error)?;
+    Ok(true)
+}
+
+/// List `options` under `heading` and ask for one by number. With a `none` label, `0`
+/// picks none of them. Pressing Enter picks the only option when there is just one.
+fn choose(
+    heading: &str,
+    question: &str,
+    options: &[String],
+    none: Option<&str>,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> Result<Option<usize>, String> {
+    writeln!(output, "{heading}:").map_err(write_error)?;
+    if let Some(none) = none {
+        writeln!(output, "  0) {none}").map_err(write_error)?;
+    }
+    for (i, option) in options.iter().enumerate() {
+        writeln!(output, "  {}) {option}", i + 1).map_err(write_error)?;
+    }
+    let first = if none.is_some() { 0 } else { 1 };
+    let default = (options.len() == 1).then_some(1);
+
+    loop {
+        match default {
+            Some(n) => write!(output, "{question} [{n}]: "),
+            None => write!(output, "{question} "),
+        }
+        .and_then(|()| output.flush())
+        .map_err(write_error)?;
+
+        let answer = match read_answer(input)?.as_str() {
+            "" => default,
+            text => text.parse().ok(),
+        };
+        match answer {
+            Some(0) if none.is_some() => return Ok(None),
+            Some(n) if (1..=options.len()).contains(&n) => return Ok(Some(n - 1)),
+            _ => writeln!(output, "Enter a number from {first} to {}", options.len())
+                .map_err(write_error)?,
+        }
+    }
+}
+
+fn unattended(e: String) -> String {
+    format!("{e}; pass --dock and --ipad to run without prompts")
+}
+
+/// The next line of `input`, trimmed.
+fn read_answer(input: &mut impl BufRead) -> Result<String, String> {
+    let mut line = String::new();
+    let read = input
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read answer: {e}"))?;
+    if read == 0 {
+        return Err("No answer given".into());
+    }
+    Ok(line.trim().to_string())
+}
+
+fn write_error(e: std::io::Error) -> String {
+    format!("Failed to write prompt: {e}")
+}
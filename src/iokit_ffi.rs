@@ -12,6 +12,7 @@ pub type mach_port_t = u32;
 pub type io_object_t = mach_port_t;
 pub type io_iterator_t = io_object_t;
 pub type io_service_t = io_object_t;
+pub type io_connect_t = io_object_t;
 pub type kern_return_t = i32;
 pub type IONotificationPortRef = *mut c_void;
 
@@ -19,10 +20,23 @@ pub const KERN_SUCCESS: kern_return_t = 0;
 pub const kIOMasterPortDefault: mach_port_t = 0;
 pub const kIOFirstMatchNotification: &[u8] = b"IOServiceMatched\0";
 pub const kIOTerminatedNotification: &[u8] = b"IOServiceTerminate\0";
+pub const kIOServicePlane: &[u8] = b"IOService\0";
+
+/// Power messages (`iokit_common_msg` values from `IOMessage.h`).
+pub const kIOMessageCanSystemSleep: u32 = 0xE000_0270;
+pub const kIOMessageSystemWillSleep: u32 = 0xE000_0280;
+pub const kIOMessageSystemHasPoweredOn: u32 = 0xE000_0300;
 
 pub type IOServiceMatchingCallback =
     unsafe extern "C" fn(refcon: *mut c_void, iterator: io_iterator_t);
 
+pub type IOServiceInterestCallback = unsafe extern "C" fn(
+    refcon: *mut c_void,
+    service: io_service_t,
+    message_type: u32,
+    message_argument: *mut c_void,
+);
+
 unsafe extern "C" {
     pub fn IONotificationPortCreate(master_port: mach_port_t) -> IONotificationPortRef;
     pub fn IONotificationPortGetRunLoopSource(notify: IONotificationPortRef) -> CFRunLoopSourceRef;
@@ -53,5 +67,32 @@ unsafe extern "C" {
         options: u32,
     ) -> kern_return_t;
 
+    pub fn IORegistryEntryGetParentEntry(
+        entry: io_object_t,
+        plane: *const c_char,
+        parent: *mut io_object_t,
+    ) -> kern_return_t;
+
+    pub fn IORegistryEntryGetChildIterator(
+        entry: io_object_t,
+        plane: *const c_char,
+        iterator: *mut io_iterator_t,
+    ) -> kern_return_t;
+
+    pub fn IOObjectConformsTo(object: io_object_t, class_name: *const c_char) -> u32;
+
+    pub fn IORegisterForSystemPower(
+        refcon: *mut c_void,
+        thePortRef: *mut IONotificationPortRef,
+        callback: IOServiceInterestCallback,
+        notifier: *mut io_object_t,
+    ) -> io_connect_t;
+
+    pub fn IOAllowPowerChange(kernelPort: io_connect_t, notificationID: isize) -> kern_return_t;
+
+    pub fn IODeregisterForSystemPower(notifier: *mut io_object_t) -> kern_return_t;
+
+    pub fn IOServiceClose(connect: io_connect_t) -> kern_return_t;
+
     pub fn IOObjectRelease(object: io_object_t) -> kern_return_t;
 }
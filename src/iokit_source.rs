@@ -0,0 +1,404 @@
+//! IOKit-based Thunderbolt dock events.
+//!
+//! Registers for `kIOFirstMatchNotification` and `kIOTerminatedNotification` on
+//! `IOThunderboltSwitch` services and services them from a `CFRunLoop` on a dedicated
+//! thread. System power notifications from `IORegisterForSystemPower` are serviced there
+//! too, and reported as sleep and wake events. Dropping or closing the source stops that
+//! run loop, and the thread releases every registration before it exits.
+
+use std::ffi::{c_char, c_void};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use core_foundation::base::{CFType, TCFType, kCFAllocatorDefault};
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::runloop::CFRunLoop;
+use core_foundation::string::CFString;
+use core_foundation_sys::dictionary::{CFDictionaryRef, CFMutableDictionaryRef};
+use core_foundation_sys::runloop::{
+    CFRunLoopAddSource, CFRunLoopGetCurrent, CFRunLoopRun, kCFRunLoopDefaultMode,
+};
+
+use crate::dock_event::{Closer, DockEvent, DockEventSource, Properties, SleepGuard};
+use crate::iokit_ffi::*;
+
+/// IOKit class of every Thunderbolt switch, the dock's and the Mac's own.
+pub(crate) const TB_SWITCH_CLASS: &[u8] = b"IOThunderboltSwitch\0";
+
+/// IOKit class of a port on a Thunderbolt switch.
+pub(crate) const TB_PORT_CLASS: &[u8] = b"IOThunderboltPort\0";
+
+/// IORegistry string properties reported with each event, and the names they are
+/// reported under.
+const STRING_PROPERTIES: &[(&str, &str)] = &[
+    ("Device Vendor Name", "vendor_name"),
+    ("Device Model Name", "device_name"),
+];
+
+/// How long sleep is held off while the daemon gets ready. macOS itself waits at most 30
+/// seconds for an answer.
+const SLEEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Dock events from IOKit hotplug notifications.
+#[derive(Debug)]
+pub struct IOKitSource {
+    events: Receiver<DockEvent>,
+    /// The IOKit thread's run loop, stopped to end the source.
+    run_loop: CFRunLoop,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl IOKitSource {
+    /// Register for Thunderbolt switch and system power notifications. Switches already
+    /// attached are reported as the first events.
+    pub fn start() -> Result<Self, String> {
+        let (tx, events) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("iokit".into())
+            .spawn(move || match Switches::register(tx.clone()) {
+                Ok(switches) => {
+                    let power = Power::register(tx)
+                        .inspect_err(|e| log::warn!("{e}; Sidecar will not be checked after sleep"))
+                        .ok();
+                    let _ = ready_tx.send(Ok(CFRunLoop::get_current()));
+                    unsafe { CFRunLoopRun() };
+                    // Stopped by `close`: release the notifications, then the sender, which
+                    // ends the source.
+                    drop(power);
+                    drop(switches);
+                    log::debug!("IOKit notifications released");
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                }
+            })
+            .map_err(|e| format!("Failed to start IOKit thread: {e}"))?;
+
+        let run_loop = ready_rx
+            .recv()
+            .map_err(|_| "IOKit thread exited during setup".to_string())??;
+        Ok(Self {
+            events,
+            run_loop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl DockEventSource for IOKitSource {
+    fn next_event(&mut self) -> Option<DockEvent> {
+        self.events.recv().ok()
+    }
+
+    fn closer(&self) -> Option<Closer> {
+        let run_loop = self.run_loop.clone();
+        Some(Box::new(move || run_loop.stop()))
+    }
+}
+
+impl Drop for IOKitSource {
+    fn drop(&mut self) {
+        self.run_loop.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Thunderbolt switch notifications, registered on the current run loop. Dropping them
+/// releases the iterators and the port, then the sender they report to.
+struct Switches {
+    port: IONotificationPortRef,
+    iterators: Vec<io_iterator_t>,
+    tx: *mut Sender<DockEvent>,
+}
+
+impl Switches {
+    /// Add a notification port and both matching notifications to the current run loop.
+    fn register(tx: Sender<DockEvent>) -> Result<Self, String> {
+        let mut switches = Switches {
+            port: ptr::null_mut(),
+            iterators: Vec::new(),
+            tx: Box::into_raw(Box::new(tx)),
+        };
+        let refcon = switches.tx as *mut c_void;
+
+        unsafe {
+            switches.port = IONotificationPortCreate(kIOMasterPortDefault);
+            if switches.port.is_null() {
+                return Err("IONotificationPortCreate failed".into());
+            }
+
+            let rls = IONotificationPortGetRunLoopSource(switches.port);
+            CFRunLoopAddSource(CFRunLoopGetCurrent(), rls, kCFRunLoopDefaultMode);
+
+            let tx = &*switches.tx;
+            for (kind, notification, callback, connected) in [
+                (
+                    "connect",
+                    kIOFirstMatchNotification,
+                    device_appeared as IOServiceMatchingCallback,
+                    true,
+                ),
+                (
+                    "disconnect",
+                    kIOTerminatedNotification,
+                    device_removed,
+                    false,
+                ),
+            ] {
+                let mut iterator: io_iterator_t = 0;
+                let matching = IOServiceMatching(TB_SWITCH_CLASS.as_ptr() as *const c_char);
+                let kr = IOServiceAddMatchingNotification(
+                    switches.port,
+                    notification.as_ptr() as *const c_char,
+                    matching as CFDictionaryRef,
+                    callback,
+                    refcon,
+                    &mut iterator,
+                );
+                if kr != KERN_SUCCESS {
+                    return Err(format!(
+                        "IOServiceAddMatchingNotification ({kind}) failed: {kr}"
+                    ));
+                }
+                switches.iterators.push(iterator);
+                drain_iterator(tx, iterator, connected);
+            }
+        }
+
+        Ok(switches)
+    }
+}
+
+impl Drop for Switches {
+    fn drop(&mut self) {
+        unsafe {
+            for iterator in &self.iterators {
+                IOObjectRelease(*iterator);
+            }
+            if !self.port.is_null() {
+                IONotificationPortDestroy(self.port);
+            }
+            // No callback can run once the port is gone.
+            drop(Box::from_raw(self.tx));
+        }
+    }
+}
+
+unsafe extern "C" fn device_appeared(refcon: *mut c_void, iterator: io_iterator_t) {
+    unsafe {
+        let tx = &*(refcon as *const Sender<DockEvent>);
+        drain_iterator(tx, iterator, true);
+    }
+}
+
+unsafe extern "C" fn device_removed(refcon: *mut c_void, iterator: io_iterator_t) {
+    unsafe {
+        let tx = &*(refcon as *const Sender<DockEvent>);
+        drain_iterator(tx, iterator, false);
+    }
+}
+
+/// System power notifications, registered on the current run loop. Dropping them
+/// deregisters and releases the callback's state.
+struct Power {
+    state: *mut PowerState,
+    port: IONotificationPortRef,
+    notifier: io_object_t,
+}
+
+/// What the power callback needs.
+struct PowerState {
+    tx: Sender<DockEvent>,
+    /// Where sleep is acknowledged.
+    root_port: io_connect_t,
+}
+
+impl Power {
+    /// Add system power notifications to the current run loop.
+    fn register(tx: Sender<DockEvent>) -> Result<Self, String> {
+        let state = Box::into_raw(Box::new(PowerState { tx, root_port: 0 }));
+        unsafe {
+            let mut port: IONotificationPortRef = ptr::null_mut();
+            let mut notifier: io_object_t = 0;
+            let root_port = IORegisterForSystemPower(
+                state as *mut c_void,
+                &mut port,
+                power_changed,
+                &mut notifier,
+            );
+            if root_port == 0 {
+                drop(Box::from_raw(state));
+                return Err("IORegisterForSystemPower failed".into());
+            }
+            // The callback only runs from the run loop, which has not started yet.
+            (*state).root_port = root_port;
+
+            let rls = IONotificationPortGetRunLoopSource(port);
+            CFRunLoopAddSource(CFRunLoopGetCurrent(), rls, kCFRunLoopDefaultMode);
+            Ok(Self {
+                state,
+                port,
+                notifier,
+            })
+        }
+    }
+}
+
+impl Drop for Power {
+    fn drop(&mut self) {
+        unsafe {
+            IODeregisterForSystemPower(&mut self.notifier);
+            IOServiceClose((*self.state).root_port);
+            IONotificationPortDestroy(self.port);
+            drop(Box::from_raw(self.state));
+        }
+    }
+}
+
+unsafe extern "C" fn power_changed(
+    refcon: *mut c_void,
+    _service: io_service_t,
+    message_type: u32,
+    message_argument: *mut c_void,
+) {
+    let power = unsafe { &*(refcon as *const PowerState) };
+    let allow = || unsafe { IOAllowPowerChange(power.root_port, message_argument as isize) };
+
+    if message_type == kIOMessageCanSystemSleep {
+        // Idle sleep could be vetoed here, but that is not ours to decide.
+        allow();
+    } else if message_type == kIOMessageSystemWillSleep {
+        let (guard, released) = SleepGuard::new();
+        if power.tx.send(DockEvent::Sleeping { guard }).is_ok()
+            && matches!(
+                released.recv_timeout(SLEEP_TIMEOUT),
+                Err(RecvTimeoutError::Timeout)
+            )
+        {
+            log::warn!(
+                "Not ready for sleep after {}s; sleeping anyway",
+                SLEEP_TIMEOUT.as_secs()
+            );
+        }
+        allow();
+    } else if message_type == kIOMessageSystemHasPoweredOn {
+        let attached = attached_uids()
+            .inspect_err(|e| log::warn!("Cannot list Thunderbolt switches after wake: {e}"))
+            .ok();
+        let _ = power.tx.send(DockEvent::Woke { attached });
+    }
+}
+
+/// UIDs of the Thunderbolt switches attached now.
+pub fn attached_uids() -> Result<Vec<u64>, String> {
+    let mut uids = Vec::new();
+    for_each_switch(|service| {
+        if let (Some(uid), _) = read_properties(service) {
+            uids.push(uid);
+        }
+    })?;
+    Ok(uids)
+}
+
+/// Call `each` with every Thunderbolt switch attached now. The service is released once
+/// `each` returns.
+pub(crate) fn for_each_switch(mut each: impl FnMut(io_service_t)) -> Result<(), String> {
+    let mut iterator: io_iterator_t = 0;
+    let kr = unsafe {
+        // The matching dictionary is consumed by the call.
+        let matching = IOServiceMatching(TB_SWITCH_CLASS.as_ptr() as *const c_char);
+        IOServiceGetMatchingServices(
+            kIOMasterPortDefault,
+            matching as CFDictionaryRef,
+            &mut iterator,
+        )
+    };
+    if kr != KERN_SUCCESS {
+        return Err(format!("IOServiceGetMatchingServices failed: {kr}"));
+    }
+
+    loop {
+        let service = unsafe { IOIteratorNext(iterator) };
+        if service == 0 {
+            break;
+        }
+        each(service);
+        unsafe { IOObjectRelease(service) };
+    }
+    unsafe { IOObjectRelease(iterator) };
+    Ok(())
+}
+
+/// Drain an IOKit iterator, reporting each service as an event.
+///
+/// The iterator **must** be fully drained for IOKit to re-arm the notification.
+fn drain_iterator(tx: &Sender<DockEvent>, iterator: io_iterator_t, connected: bool) {
+    loop {
+        let service = unsafe { IOIteratorNext(iterator) };
+        if service == 0 {
+            break;
+        }
+
+        let (uid, properties) = read_properties(service);
+        let event = if connected {
+            DockEvent::Appeared { uid, properties }
+        } else {
+            DockEvent::Removed { uid, properties }
+        };
+        let _ = tx.send(event);
+
+        unsafe { IOObjectRelease(service) };
+    }
+}
+
+/// Read the `"UID"` property (SInt64) and the [`STRING_PROPERTIES`] from an IORegistry
+/// entry.
+fn read_properties(service: io_service_t) -> (Option<u64>, Properties) {
+    let mut properties = Properties::new();
+    let Some(dict) = registry_properties(service) else {
+        return (None, properties);
+    };
+
+    let uid = number_property(&dict, "UID").map(|v| v as u64);
+    for (key, name) in STRING_PROPERTIES {
+        if let Some(value) = string_property(&dict, key) {
+            properties.insert((*name).into(), value);
+        }
+    }
+    (uid, properties)
+}
+
+/// All IORegistry properties of `service`.
+pub(crate) fn registry_properties(service: io_service_t) -> Option<CFDictionary<CFString, CFType>> {
+    unsafe {
+        let mut props_ref: CFMutableDictionaryRef = ptr::null_mut();
+        let kr =
+            IORegistryEntryCreateCFProperties(service, &mut props_ref, kCFAllocatorDefault as _, 0);
+        if kr != KERN_SUCCESS || props_ref.is_null() {
+            return None;
+        }
+        Some(CFDictionary::wrap_under_create_rule(
+            props_ref as CFDictionaryRef,
+        ))
+    }
+}
+
+/// A string property from [`registry_properties`].
+pub(crate) fn string_property(dict: &CFDictionary<CFString, CFType>, key: &str) -> Option<String> {
+    dict.find(CFString::new(key))
+        .and_then(|v| v.downcast::<CFString>())
+        .map(|v| v.to_string())
+}
+
+/// A numeric property from [`registry_properties`].
+pub(crate) fn number_property(dict: &CFDictionary<CFString, CFType>, key: &str) -> Option<i64> {
+    dict.find(CFString::new(key))
+        .and_then(|v| v.downcast::<CFNumber>())
+        .and_then(|n| n.to_i64())
+}
@@ -1,7 +1,32 @@
 pub mod config;
+pub mod config_check;
+pub mod config_watch;
+pub mod control;
+pub mod daemon;
 pub mod discovery;
+pub mod dock_event;
 pub mod dock_monitor;
+#[cfg(feature = "stream")]
+pub mod events;
+pub mod hooks;
+pub mod init;
+#[cfg(target_os = "macos")]
 pub mod iokit_ffi;
+#[cfg(target_os = "macos")]
+pub mod iokit_source;
 pub mod launchd;
+pub mod manual;
 pub mod sidecar;
+#[cfg(target_os = "macos")]
+pub mod sidecar_core;
+#[cfg(any(test, feature = "testing"))]
+pub mod sidecar_fake;
+#[cfg(target_os = "macos")]
 pub mod sidecar_ffi;
+#[cfg(unix)]
+pub mod signals;
+pub mod state;
+pub mod sysfs_source;
+
+#[cfg(feature = "stream")]
+pub use events::events;
@@ -1,8 +1,11 @@
-use sidecar_on_dock::{config, discovery, dock_monitor, launchd};
+use sidecar_on_dock::daemon::Daemon;
+use sidecar_on_dock::discovery::{self, Layout};
+use sidecar_on_dock::manual::{self, Action, Direct, Failure, Status};
+use sidecar_on_dock::{config, config_check, config_watch, control, init, launchd, signals};
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(
@@ -17,13 +20,58 @@ struct Cli {
 #[derive(Subcommand)]
 enum Command {
     /// List connected Thunderbolt devices and available Sidecar (iPad) devices.
-    Discover,
+    Discover {
+        /// Find the dock by plugging it in or unplugging it, and offer to save its UID.
+        #[arg(long)]
+        identify: bool,
+        /// Config file to save the dock UID to with --identify.
+        #[arg(short, long)]
+        config: Option<PathBuf>,
+        /// Output format. `json` and `yaml` carry a `schema_version` field.
+        #[arg(long, value_enum, default_value_t = Format::Table)]
+        format: Format,
+        /// Only list these kinds of device (default: all).
+        #[arg(long, value_enum, value_delimiter = ',')]
+        section: Vec<Section>,
+        /// Show each Thunderbolt device under the one it is plugged into, with link
+        /// speeds and firmware versions.
+        #[arg(long, conflicts_with = "identify")]
+        tree: bool,
+        /// Include the Mac's own Thunderbolt controllers.
+        #[arg(long, conflicts_with = "identify")]
+        all: bool,
+        /// Read Thunderbolt devices from a saved `system_profiler SPThunderboltDataType
+        /// -xml` or `ioreg -a -l` dump instead of this machine.
+        #[arg(long, value_name = "DUMP", conflicts_with = "identify")]
+        from_file: Option<PathBuf>,
+    },
+    /// Create a config file for a connected dock and iPad.
+    Init(InitArgs),
     /// Run the daemon (default when no subcommand is given).
     Run {
         /// Path to the JSON config file.
         #[arg(short, long)]
         config: Option<PathBuf>,
     },
+    /// Show what the daemon is doing, or which iPads Sidecar sees when it isn't running.
+    #[command(after_help = EXIT_CODES)]
+    Status {
+        /// Print JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Connect Sidecar now.
+    #[command(after_help = EXIT_CODES)]
+    Connect(ManualArgs),
+    /// Disconnect Sidecar now.
+    #[command(after_help = EXIT_CODES)]
+    Disconnect(ManualArgs),
+    /// Disconnect Sidecar if it is connected, otherwise connect it.
+    #[command(after_help = EXIT_CODES)]
+    Toggle(ManualArgs),
+    /// Check or inspect the config file.
+    #[command(subcommand)]
+    Config(ConfigCommand),
     /// Print the default config file path.
     ConfigPath,
     /// Install a launchd agent so the daemon starts automatically on login.
@@ -32,6 +80,61 @@ enum Command {
     Uninstall,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+    Yaml,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Section {
+    Thunderbolt,
+    Sidecar,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Report every problem in a config file, with line and column.
+    #[command(after_help = "Exit codes: 0 no errors (warnings allowed), 2 errors found")]
+    Validate {
+        /// Config file to check. Defaults to the standard location.
+        path: Option<PathBuf>,
+    },
+}
+
+const EXIT_CODES: &str = "Exit codes: 0 success, 1 request failed, 2 usage or config error, \
+                          3 Sidecar unavailable, 4 iPad not found";
+
+#[derive(Args)]
+struct InitArgs {
+    /// Dock UID to use instead of choosing from the connected docks.
+    #[arg(long)]
+    dock: Option<String>,
+    /// iPad name to use instead of choosing from the available iPads.
+    #[arg(long)]
+    ipad: Option<String>,
+    /// Config file to write. Defaults to the standard location.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Replace an existing config file.
+    #[arg(long)]
+    force: bool,
+}
+
+/// Options shared by `connect`, `disconnect` and `toggle`.
+#[derive(Args)]
+struct ManualArgs {
+    /// Profile to act on. Required when several are configured.
+    profile: Option<String>,
+    /// Config file used to find the iPad when the daemon is not running.
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Print JSON instead of text.
+    #[arg(long)]
+    json: bool,
+}
+
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
         .format_timestamp_secs()
@@ -40,8 +143,30 @@ fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Some(Command::Discover) => cmd_discover(),
+        Some(Command::Discover {
+            from_file: Some(path),
+            format,
+            section,
+            tree,
+            all,
+            ..
+        }) => cmd_discover_file(&path, format, &section, Layout { tree, all }),
+        Some(Command::Discover {
+            identify,
+            config,
+            format,
+            section,
+            tree,
+            all,
+            from_file: None,
+        }) => cmd_discover(identify, config, format, &section, Layout { tree, all }),
+        Some(Command::Init(args)) => cmd_init(args),
         Some(Command::Run { config }) => cmd_run(config),
+        Some(Command::Status { json }) => cmd_status(json),
+        Some(Command::Connect(args)) => cmd_manual(Action::Connect, args),
+        Some(Command::Disconnect(args)) => cmd_manual(Action::Disconnect, args),
+        Some(Command::Toggle(args)) => cmd_manual(Action::Toggle, args),
+        Some(Command::Config(ConfigCommand::Validate { path })) => cmd_config_validate(path),
         Some(Command::ConfigPath) => cmd_config_path(),
         Some(Command::Install) => cmd_install(),
         Some(Command::Uninstall) => cmd_uninstall(),
@@ -49,11 +174,159 @@ fn main() {
     }
 }
 
-fn cmd_discover() {
-    if let Err(e) = discovery::print_discovery() {
-        log::error!("{e}");
+#[cfg(target_os = "macos")]
+fn cmd_discover(
+    identify: bool,
+    config_path: Option<PathBuf>,
+    format: Format,
+    sections: &[Section],
+    layout: Layout,
+) {
+    if identify {
+        let path = config_path.unwrap_or_else(config::Config::default_path);
+        let stdin = std::io::stdin();
+        let mut input = stdin.lock();
+        let mut output = std::io::stdout();
+        let result = init::identify(dock_snapshot, &mut input, &mut output)
+            .and_then(|changes| init::offer_to_save(&changes, &path, &mut input, &mut output));
+        if let Err(e) = result {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let wants = |section| sections.is_empty() || sections.contains(&section);
+    let report = discovery::discover(wants(Section::Thunderbolt), wants(Section::Sidecar), layout);
+    print_report(&report, format);
+}
+
+#[cfg(not(target_os = "macos"))]
+fn cmd_discover(
+    identify: bool,
+    _config_path: Option<PathBuf>,
+    format: Format,
+    sections: &[Section],
+    _layout: Layout,
+) {
+    const UNSUPPORTED: &str = "Discovery is only supported on macOS";
+    if identify {
+        log::error!("{UNSUPPORTED}");
         std::process::exit(1);
     }
+    let wants = |section| sections.is_empty() || sections.contains(&section);
+    let report = discovery::Report::new(
+        wants(Section::Thunderbolt).then(|| Err(UNSUPPORTED.into())),
+        wants(Section::Sidecar).then(|| Err(UNSUPPORTED.into())),
+    );
+    print_report(&report, format);
+}
+
+/// List the devices in a saved dump, on any platform.
+fn cmd_discover_file(path: &Path, format: Format, sections: &[Section], layout: Layout) {
+    let wants = |section| sections.is_empty() || sections.contains(&section);
+    let thunderbolt = wants(Section::Thunderbolt).then(|| {
+        std::fs::read(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))
+            .and_then(|bytes| discovery::thunderbolt_tree_from_bytes(&bytes))
+            .map(|tree| discovery::arrange(tree, layout))
+    });
+    // Only asked for explicitly: a dump never lists iPads.
+    let sidecar = sections
+        .contains(&Section::Sidecar)
+        .then(|| Err("A dump only lists Thunderbolt devices".to_string()));
+    print_report(&discovery::Report::new(thunderbolt, sidecar), format);
+}
+
+/// Print `report` in `format`, exiting with 1 if any section failed.
+fn print_report(report: &discovery::Report, format: Format) {
+    match format {
+        Format::Table => print!("{report}"),
+        Format::Json => println!("{}", serde_json::to_string_pretty(report).unwrap()),
+        Format::Yaml => print!("{}", serde_norway::to_string(report).unwrap()),
+    }
+    if report.failed() {
+        std::process::exit(1);
+    }
+}
+
+fn cmd_init(args: InitArgs) {
+    let path = args.config.unwrap_or_else(config::Config::default_path);
+    if let Err(e) = init::check_target(&path, args.force) {
+        log::error!("{e}");
+        std::process::exit(2);
+    }
+
+    let docks = if args.dock.is_none() {
+        discover_docks()
+    } else {
+        Vec::new()
+    };
+    let ipads = discover_ipads();
+    let answers = init::Answers {
+        dock: args.dock,
+        ipad: args.ipad,
+    };
+    let stdin = std::io::stdin();
+    let result = init::build(
+        &docks,
+        &ipads,
+        answers,
+        &mut stdin.lock(),
+        &mut std::io::stdout(),
+    )
+    .and_then(|cfg| init::write(&cfg, &path, args.force));
+    if let Err(e) = result {
+        log::error!("{e}");
+        std::process::exit(2);
+    }
+    println!("Wrote {}", path.display());
+    println!(
+        "Start the daemon with `sidecar-on-dock run`, or `sidecar-on-dock install` to run it at login"
+    );
+}
+
+#[cfg(target_os = "macos")]
+fn discover_docks() -> Vec<init::Dock> {
+    dock_snapshot().unwrap_or_else(|e| {
+        log::warn!("{e}");
+        Vec::new()
+    })
+}
+
+/// The Thunderbolt devices connected now.
+#[cfg(target_os = "macos")]
+fn dock_snapshot() -> Result<Vec<init::Dock>, String> {
+    let devices = discovery::discover_thunderbolt_devices()?;
+    Ok(devices
+        .into_iter()
+        .map(|d| init::Dock {
+            name: d.name,
+            vendor: d.vendor,
+            uid: d.uid,
+        })
+        .collect())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn discover_docks() -> Vec<init::Dock> {
+    Vec::new()
+}
+
+#[cfg(target_os = "macos")]
+fn discover_ipads() -> Vec<String> {
+    match discovery::discover_sidecar_devices() {
+        Ok(devices) => devices.into_iter().map(|d| d.name).collect(),
+        Err(e) => {
+            log::warn!("{e}");
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn discover_ipads() -> Vec<String> {
+    Vec::new()
 }
 
 fn cmd_run(config_path: Option<PathBuf>) {
@@ -61,6 +334,11 @@ fn cmd_run(config_path: Option<PathBuf>) {
 
     let cfg = match config::Config::load(&path) {
         Ok(c) => c,
+        Err(e) if path.exists() => {
+            log::error!("{e}");
+            log::info!("Hint: run `sidecar-on-dock config validate` to list every problem");
+            std::process::exit(1);
+        }
         Err(e) => {
             log::error!("{e}");
             log::info!(
@@ -71,21 +349,223 @@ fn cmd_run(config_path: Option<PathBuf>) {
         }
     };
 
-    let dock_uid = match cfg.dock_uid_u64() {
-        Ok(u) => u,
+    let profiles = match cfg.profiles() {
+        Ok(p) => p,
         Err(e) => {
             log::error!("{e}");
+            log::info!("Hint: run `sidecar-on-dock config validate` to list every problem");
             std::process::exit(1);
         }
     };
 
-    log::info!(
-        "Config loaded. Dock UID: 0x{:016X}, iPad: {}",
-        dock_uid,
-        cfg.ipad_name.as_deref().unwrap_or("(first available)")
-    );
+    for profile in &profiles {
+        log::info!(
+            "Profile '{}': dock UID {}, iPad: {}",
+            profile.label(),
+            profile.dock_uid,
+            profile.ipad_name.as_deref().unwrap_or("(first available)")
+        );
+    }
+
+    let daemon = match build_daemon(cfg, path) {
+        Ok(d) => d,
+        Err(e) => {
+            log::error!("{e}");
+            std::process::exit(1);
+        }
+    };
+    run_daemon(daemon);
+}
+
+/// Set up the daemon with a control socket and a watch on the config at `config_path`.
+/// Both `reload` requests and edits to the file re-read it. SIGTERM and SIGINT stop it.
+fn build_daemon(cfg: config::Config, config_path: PathBuf) -> Result<Daemon, String> {
+    let builder = Daemon::builder();
+    let handle = builder.handle();
+    // Before any thread starts, so that none of them is killed by the signals.
+    signals::on_termination(move |name| {
+        log::info!("Received {name}");
+        handle.stop();
+    })?;
+
+    #[cfg(target_os = "linux")]
+    log::warn!("Sidecar is not available on this platform; only dock changes are tracked");
+    let reload_path = config_path.clone();
+    let builder = builder
+        .config(cfg)
+        .control_socket(control::default_socket_path())
+        .reload(move || config::Config::load(&reload_path)?.profiles());
+    let handle = builder.handle();
+    let daemon = builder.build()?;
+
+    let changes = config_watch::watch(&config_path)?;
+    std::thread::Builder::new()
+        .name("config-reload".into())
+        .spawn(move || {
+            for () in changes {
+                log::info!("Config file changed, reloading");
+                // The daemon logs the outcome.
+                let _ = handle.reload();
+            }
+        })
+        .map_err(|e| format!("Failed to start config reload thread: {e}"))?;
+    Ok(daemon)
+}
+
+/// Run `daemon` until it stops, then exit.
+#[cfg(target_os = "macos")]
+fn run_daemon(daemon: Daemon) -> ! {
+    std::thread::spawn(move || exit_with(daemon.run()));
+    sidecar_on_dock::sidecar_core::run_main_run_loop();
+}
+
+/// Run `daemon` until it stops, then exit.
+#[cfg(not(target_os = "macos"))]
+fn run_daemon(daemon: Daemon) -> ! {
+    exit_with(daemon.run())
+}
 
-    dock_monitor::run(dock_uid, cfg.ipad_name);
+fn exit_with(result: Result<(), String>) -> ! {
+    if let Err(e) = result {
+        log::error!("{e}");
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+fn cmd_status(json: bool) {
+    exit_after(move || {
+        let direct = || {
+            Ok(Direct {
+                backend: manual::platform_backend()?,
+                ipad_name: None,
+            })
+        };
+        match manual::status(&control::default_socket_path(), direct) {
+            Ok(status) if json => println!("{}", serde_json::to_string(&status).unwrap()),
+            Ok(Status::Daemon { profiles }) => {
+                for p in profiles {
+                    println!("{}: {}", p.name, p.state);
+                    let dock = if p.dock_present { "attached" } else { "absent" };
+                    println!("  dock {} {dock}", p.dock_uid);
+                    let ipad = p.ipad_name.as_deref().unwrap_or("first available iPad");
+                    let sidecar = if p.sidecar_connected {
+                        "connected"
+                    } else {
+                        "not connected"
+                    };
+                    println!("  Sidecar {sidecar} ({ipad})");
+                }
+            }
+            Ok(Status::Direct { devices, connected }) => {
+                println!("Daemon not running");
+                println!("Sidecar devices: {}", list(&devices));
+                println!("Connected: {}", list(&connected));
+            }
+            Err(e) => return fail(&e, json),
+        }
+        0
+    })
+}
+
+fn cmd_manual(action: Action, args: ManualArgs) {
+    exit_after(move || {
+        let config_path = args.config.unwrap_or_else(config::Config::default_path);
+        let profile = args.profile.as_deref();
+        let direct = || {
+            Ok(Direct {
+                ipad_name: manual::ipad_for(&config_path, profile)?,
+                backend: manual::platform_backend()?,
+            })
+        };
+        match manual::perform(action, profile, &control::default_socket_path(), direct) {
+            Ok(outcome) if args.json => {
+                let mut value = serde_json::to_value(&outcome).unwrap();
+                value["ok"] = true.into();
+                println!("{value}");
+                0
+            }
+            Ok(outcome) => {
+                let done = match outcome.action {
+                    Action::Disconnect => "Disconnected",
+                    _ => "Connected",
+                };
+                let via = match outcome.via {
+                    manual::Via::Daemon => "via the daemon",
+                    manual::Via::Direct => "directly",
+                };
+                println!("{done} Sidecar {via}");
+                0
+            }
+            Err(e) => fail(&e, args.json),
+        }
+    })
+}
+
+/// Report `failure` and return its exit code.
+fn fail(failure: &Failure, json: bool) -> i32 {
+    if json {
+        let value = serde_json::json!({ "ok": false, "error": failure.to_string() });
+        println!("{value}");
+    } else {
+        log::error!("{failure}");
+    }
+    failure.exit_code()
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        "none".into()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Run `task` and exit with the code it returns. On macOS the main thread meanwhile
+/// services the main dispatch queue, where SidecarCore may deliver completions.
+#[cfg(target_os = "macos")]
+fn exit_after(task: impl FnOnce() -> i32 + Send + 'static) -> ! {
+    std::thread::spawn(move || std::process::exit(task()));
+    sidecar_on_dock::sidecar_core::run_main_run_loop();
+}
+
+#[cfg(not(target_os = "macos"))]
+fn exit_after(task: impl FnOnce() -> i32 + Send + 'static) -> ! {
+    std::process::exit(task())
+}
+
+fn cmd_config_validate(path: Option<PathBuf>) {
+    let path = path.unwrap_or_else(config::Config::default_path);
+    exit_after(move || {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("Failed to read {}: {e}", path.display());
+                return 2;
+            }
+        };
+        // Without Sidecar, iPad names cannot be checked.
+        let devices = manual::platform_backend()
+            .and_then(|backend| backend.devices().map_err(Failure::Sidecar))
+            .inspect_err(|e| log::debug!("Not checking iPad names: {e}"))
+            .ok();
+
+        let found = config_check::check(&text, devices.as_deref());
+        for diagnostic in &found {
+            println!("{}:{diagnostic}", path.display());
+        }
+        let errors = found
+            .iter()
+            .filter(|d| d.severity == config_check::Severity::Error)
+            .count();
+        let warnings = found.len() - errors;
+        if found.is_empty() {
+            println!("{} is valid", path.display());
+        } else {
+            println!("{errors} error(s), {warnings} warning(s)");
+        }
+        if errors > 0 { 2 } else { 0 }
+    })
 }
 
 fn cmd_config_path() {
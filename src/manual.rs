@@ -0,0 +1,289 @@
+//! Manual Sidecar control for the `status`, `connect`, `disconnect` and `toggle` commands.
+//!
+//! Requests go to the running daemon over its control socket, so its state stays in step
+//! with what the user asked for. When no daemon is listening, Sidecar is driven directly.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::mpsc;
+
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::control::{self, Client, ErrorKind, ProfileStatus, Request, Response};
+use crate::sidecar::{self, SidecarBackend, SidecarError};
+
+/// What to do with Sidecar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Connect,
+    Disconnect,
+    /// Disconnect when connected, otherwise connect.
+    Toggle,
+}
+
+/// Who carried out a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Via {
+    /// The running daemon, over its control socket.
+    Daemon,
+    /// This process, calling Sidecar itself.
+    Direct,
+}
+
+/// A request that was carried out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Outcome {
+    /// [`Action::Connect`] or [`Action::Disconnect`]; a toggle reports which one it did.
+    pub action: Action,
+    pub via: Via,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+/// What [`status`] found out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "via", rename_all = "snake_case")]
+pub enum Status {
+    /// The daemon's view of every profile.
+    Daemon { profiles: Vec<ProfileStatus> },
+    /// No daemon is running; what Sidecar itself reports.
+    Direct {
+        devices: Vec<String>,
+        connected: Vec<String>,
+    },
+}
+
+/// How to reach Sidecar when no daemon is running.
+pub struct Direct {
+    pub backend: Box<dyn SidecarBackend>,
+    /// iPad to act on. Without one, the first available device is used.
+    pub ipad_name: Option<String>,
+}
+
+/// Why a manual request failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    /// The command line or config does not say what to act on.
+    Usage(String),
+    /// The daemon could not carry out the request.
+    Daemon(String),
+    /// The daemon found Sidecar unavailable.
+    Unavailable(String),
+    /// The daemon could not find the iPad.
+    NotFound(String),
+    /// Sidecar reported an error.
+    Sidecar(SidecarError),
+}
+
+impl Failure {
+    /// Process exit code: 1 when the request failed, 2 for usage and config problems,
+    /// 3 when Sidecar is unavailable and 4 when the iPad could not be found.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Daemon(_) => 1,
+            Failure::Usage(_) => 2,
+            Failure::Unavailable(_) | Failure::Sidecar(SidecarError::Unavailable(_)) => 3,
+            Failure::NotFound(_) | Failure::Sidecar(SidecarError::DeviceNotFound { .. }) => 4,
+            Failure::Sidecar(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage(message)
+            | Failure::Daemon(message)
+            | Failure::Unavailable(message)
+            | Failure::NotFound(message) => f.write_str(message),
+            Failure::Sidecar(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Carry out `action` through the daemon listening on `socket`, or through `direct` when
+/// no daemon is running. Blocks until Sidecar has answered.
+///
+/// `profile` names the daemon profile to act on; `direct` is only called without a daemon.
+pub fn perform(
+    action: Action,
+    profile: Option<&str>,
+    socket: &Path,
+    direct: impl FnOnce() -> Result<Direct, Failure>,
+) -> Result<Outcome, Failure> {
+    match daemon(socket) {
+        Some(mut client) => via_daemon(&mut client, action, profile),
+        None => via_backend(&direct()?, action),
+    }
+}
+
+/// Ask the daemon listening on `socket` for its status, or Sidecar through `direct` when
+/// no daemon is running.
+pub fn status(
+    socket: &Path,
+    direct: impl FnOnce() -> Result<Direct, Failure>,
+) -> Result<Status, Failure> {
+    if let Some(mut client) = daemon(socket) {
+        let profiles = client.status().map_err(Failure::Daemon)?;
+        return Ok(Status::Daemon { profiles });
+    }
+    let backend = direct()?.backend;
+    Ok(Status::Direct {
+        devices: backend.devices().map_err(Failure::Sidecar)?,
+        connected: backend.connected_devices().map_err(Failure::Sidecar)?,
+    })
+}
+
+/// The iPad configured for `profile` in the config at `path`, for use without a daemon.
+///
+/// Without a profile name, the config's only profile is used. A missing config file is
+/// fine when no profile is named: the first available iPad is then used.
+pub fn ipad_for(path: &Path, profile: Option<&str>) -> Result<Option<String>, Failure> {
+    if profile.is_none() && !path.exists() {
+        return Ok(None);
+    }
+    let profiles = Config::load(path)
+        .and_then(|cfg| cfg.profiles())
+        .map_err(Failure::Usage)?;
+    let found = match profile {
+        Some(name) => profiles.iter().find(|p| p.label() == name),
+        None if profiles.len() == 1 => profiles.first(),
+        None => {
+            let names: Vec<_> = profiles.iter().map(|p| p.label()).collect();
+            return Err(Failure::Usage(format!(
+                "Name a profile: {}",
+                names.join(", ")
+            )));
+        }
+    };
+    match found {
+        Some(p) => Ok(p.ipad_name.clone()),
+        None => Err(Failure::Usage(format!(
+            "No profile named '{}' in {}",
+            profile.unwrap_or_default(),
+            path.display()
+        ))),
+    }
+}
+
+/// The Sidecar backend for this platform.
+pub fn platform_backend() -> Result<Box<dyn SidecarBackend>, Failure> {
+    #[cfg(target_os = "macos")]
+    {
+        crate::sidecar_core::SidecarCore::load()
+            .map(|core| Box::new(core) as Box<dyn SidecarBackend>)
+            .map_err(Failure::Sidecar)
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        Ok(Box::new(sidecar::NoSidecar))
+    }
+}
+
+/// A connection to the daemon, if one is listening on `socket`.
+fn daemon(socket: &Path) -> Option<Client> {
+    match Client::open(socket) {
+        Ok(client) => Some(client),
+        Err(e) => {
+            log::debug!("{e}; calling Sidecar directly");
+            None
+        }
+    }
+}
+
+fn via_daemon(
+    client: &mut Client,
+    action: Action,
+    profile: Option<&str>,
+) -> Result<Outcome, Failure> {
+    let action = match action {
+        Action::Toggle => {
+            let profiles = client.status().map_err(Failure::Daemon)?;
+            let found = match profile {
+                Some(name) => profiles.iter().find(|p| p.name == name),
+                None if profiles.len() == 1 => profiles.first(),
+                None => {
+                    let names: Vec<_> = profiles.iter().map(|p| p.name.as_str()).collect();
+                    return Err(Failure::Usage(format!(
+                        "Name a profile: {}",
+                        names.join(", ")
+                    )));
+                }
+            };
+            let Some(found) = found else {
+                return Err(Failure::Usage(format!(
+                    "No profile named '{}'",
+                    profile.unwrap_or_default()
+                )));
+            };
+            if found.sidecar_connected {
+                Action::Disconnect
+            } else {
+                Action::Connect
+            }
+        }
+        action => action,
+    };
+
+    let profile_name = profile.map(Into::into);
+    let request = match action {
+        Action::Disconnect => Request::Disconnect {
+            profile: profile_name,
+        },
+        _ => Request::Connect {
+            profile: profile_name,
+        },
+    };
+    match client.request(&request).map_err(Failure::Daemon)? {
+        Response::Ok => {}
+        Response::Error { message, kind } => {
+            return Err(match kind {
+                Some(ErrorKind::Unavailable) => Failure::Unavailable(message),
+                Some(ErrorKind::NotFound) => Failure::NotFound(message),
+                None => Failure::Daemon(message),
+            });
+        }
+        other => return Err(Failure::Daemon(control::unexpected(other))),
+    }
+    Ok(Outcome {
+        action,
+        via: Via::Daemon,
+        profile: profile.map(Into::into),
+    })
+}
+
+fn via_backend(direct: &Direct, action: Action) -> Result<Outcome, Failure> {
+    let backend = &*direct.backend;
+    let ipad_name = direct.ipad_name.as_deref();
+    let action = match action {
+        Action::Toggle
+            if sidecar::is_connected(backend, ipad_name).map_err(Failure::Sidecar)? =>
+        {
+            Action::Disconnect
+        }
+        Action::Toggle => Action::Connect,
+        action => action,
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let done: sidecar::Completion = Box::new(move |result| {
+        let _ = tx.send(result);
+    });
+    match action {
+        Action::Disconnect => sidecar::disconnect(backend, ipad_name, done),
+        _ => sidecar::connect(backend, ipad_name, done),
+    }
+    rx.recv_timeout(sidecar::COMPLETION_TIMEOUT)
+        .unwrap_or(Err(SidecarError::Timeout))
+        .map_err(Failure::Sidecar)?;
+    Ok(Outcome {
+        action,
+        via: Via::Direct,
+        profile: None,
+    })
+}
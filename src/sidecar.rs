@@ -1,150 +1,189 @@
 //! High-level Sidecar connect / disconnect helpers.
+//!
+//! The helpers work against any [`SidecarBackend`]: [`crate::sidecar_core::SidecarCore`]
+//! drives the real framework, and `sidecar_fake::FakeSidecar`, built with the `testing`
+//! feature, stands in for it in tests.
 
-use std::thread;
+use std::fmt;
 use std::time::Duration;
 
-use objc2::rc::Retained;
-use objc2::runtime::AnyObject;
-
-use crate::sidecar_ffi;
+/// How long to wait for a backend's completion handler before reporting a timeout. The
+/// caller keeps the deadline, since it already waits for other things.
+pub const COMPLETION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Why a Sidecar request failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SidecarError {
+    /// SidecarCore or its display manager could not be reached.
+    Unavailable(String),
+    /// No Sidecar device matched the requested name.
+    DeviceNotFound {
+        wanted: Option<String>,
+        available: Vec<String>,
+    },
+    /// SidecarCore did not call the completion handler within the timeout.
+    Timeout,
+    /// SidecarCore's completion handler reported an `NSError`.
+    Framework {
+        domain: String,
+        code: isize,
+        description: String,
+    },
+}
 
-const MAX_RETRIES: u32 = 10;
-const RETRY_DELAY: Duration = Duration::from_secs(2);
+impl SidecarError {
+    /// Whether trying the same request again later could succeed.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, SidecarError::Unavailable(_))
+    }
+}
 
-/// Ensure the SidecarCore framework is loaded.
-pub fn ensure_loaded() -> bool {
-    if sidecar_ffi::load_framework().is_err() {
-        log::error!("Failed to load SidecarCore framework");
-        return false;
+impl fmt::Display for SidecarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SidecarError::Unavailable(reason) => write!(f, "Sidecar unavailable: {reason}"),
+            SidecarError::DeviceNotFound {
+                wanted: Some(name), ..
+            } => write!(f, "Sidecar device '{name}' not found"),
+            SidecarError::DeviceNotFound { wanted: None, .. } => {
+                write!(f, "No Sidecar devices available")
+            }
+            SidecarError::Timeout => write!(
+                f,
+                "SidecarCore did not respond within {}s",
+                COMPLETION_TIMEOUT.as_secs()
+            ),
+            SidecarError::Framework {
+                domain,
+                code,
+                description,
+            } => write!(f, "{description} ({domain} error {code})"),
+        }
     }
-    true
 }
 
-/// Connect to an iPad via Sidecar, retrying until the device becomes available.
-pub fn connect(ipad_name: Option<&str>) {
-    let Some(cls) = sidecar_ffi::display_manager_class() else {
-        log::error!("SidecarDisplayManager class not found");
-        return;
-    };
+impl std::error::Error for SidecarError {}
+
+/// Receives the outcome of a Sidecar request. Called at most once, possibly on another
+/// thread and possibly before the request function returns. A backend that hangs may
+/// answer after [`COMPLETION_TIMEOUT`], or never.
+pub type Completion = Box<dyn FnOnce(Result<(), SidecarError>) + Send>;
+
+/// The operations the daemon needs from Sidecar.
+///
+/// Devices are identified by their exact name as returned by [`SidecarBackend::devices`].
+pub trait SidecarBackend: Send + Sync {
+    /// Names of the devices Sidecar can currently extend to.
+    fn devices(&self) -> Result<Vec<String>, SidecarError>;
+    /// Names of the devices with an active Sidecar session.
+    fn connected_devices(&self) -> Result<Vec<String>, SidecarError>;
+    /// Start a session with `device` and report the outcome to `done`.
+    fn connect(&self, device: &str, done: Completion);
+    /// End the session with `device` and report the outcome to `done`.
+    fn disconnect(&self, device: &str, done: Completion);
+}
 
-    unsafe {
-        let Some(manager) = sidecar_ffi::shared_manager(cls) else {
-            log::error!("Could not get SidecarDisplayManager.sharedManager");
-            return;
-        };
-
-        for attempt in 1..=MAX_RETRIES {
-            if let Some(device) = find_device(&manager, ipad_name) {
-                log::info!("Connecting Sidecar...");
-                sidecar_ffi::connect_to_device(&manager, &device);
-                return;
-            }
+/// Backend for platforms without Sidecar. Every request fails with
+/// [`SidecarError::Unavailable`].
+#[derive(Debug, Default)]
+pub struct NoSidecar;
 
-            if attempt == 1 {
-                log_available_devices(&manager, ipad_name);
-            }
+impl NoSidecar {
+    fn unavailable() -> SidecarError {
+        SidecarError::Unavailable("Sidecar is only available on macOS".into())
+    }
+}
 
-            if attempt < MAX_RETRIES {
-                log::info!(
-                    "Sidecar device not available yet (attempt {attempt}/{MAX_RETRIES}), retrying in {}s...",
-                    RETRY_DELAY.as_secs()
-                );
-                thread::sleep(RETRY_DELAY);
-            }
-        }
+impl SidecarBackend for NoSidecar {
+    fn devices(&self) -> Result<Vec<String>, SidecarError> {
+        Err(Self::unavailable())
+    }
 
-        match ipad_name {
-            Some(name) => {
-                log::warn!("Sidecar device '{name}' not found after {MAX_RETRIES} attempts")
-            }
-            None => log::warn!("No Sidecar devices available after {MAX_RETRIES} attempts"),
-        }
+    fn connected_devices(&self) -> Result<Vec<String>, SidecarError> {
+        Err(Self::unavailable())
+    }
+
+    fn connect(&self, _device: &str, done: Completion) {
+        done(Err(Self::unavailable()))
+    }
+
+    fn disconnect(&self, _device: &str, done: Completion) {
+        done(Err(Self::unavailable()))
     }
 }
 
+/// Make one attempt to connect an iPad via Sidecar. Never blocks waiting for the device;
+/// callers schedule their own retries.
+pub fn connect(backend: &dyn SidecarBackend, ipad_name: Option<&str>, done: Completion) {
+    let device = match find_device(backend, ipad_name) {
+        Ok(d) => d,
+        Err(e) => return done(Err(e)),
+    };
+
+    log::info!("Connecting Sidecar to '{device}'...");
+    backend.connect(&device, done);
+}
+
 /// Disconnect a currently-connected iPad from Sidecar.
-pub fn disconnect(ipad_name: Option<&str>) {
-    let Some(cls) = sidecar_ffi::display_manager_class() else {
-        log::error!("SidecarDisplayManager class not found");
-        return;
+pub fn disconnect(backend: &dyn SidecarBackend, ipad_name: Option<&str>, done: Completion) {
+    let device = match find_device(backend, ipad_name) {
+        Ok(d) => d,
+        Err(e) => return done(Err(e)),
     };
 
-    unsafe {
-        let Some(manager) = sidecar_ffi::shared_manager(cls) else {
-            log::error!("Could not get SidecarDisplayManager.sharedManager");
-            return;
-        };
+    log::info!("Disconnecting Sidecar from '{device}'...");
+    backend.disconnect(&device, done);
+}
 
-        let Some(device) = find_device(&manager, ipad_name) else {
-            log::debug!("No matching Sidecar device found for disconnect (may already be gone)");
-            return;
-        };
+/// Whether `ipad_name` has an active Sidecar session. Without a name, whether any device
+/// has one.
+pub fn is_connected(
+    backend: &dyn SidecarBackend,
+    ipad_name: Option<&str>,
+) -> Result<bool, SidecarError> {
+    let connected = backend.connected_devices()?;
+    Ok(match ipad_name.map(normalise_quotes) {
+        Some(target) => connected
+            .iter()
+            .any(|name| normalise_quotes(name) == target),
+        None => !connected.is_empty(),
+    })
+}
 
-        log::info!("Disconnecting Sidecar...");
-        sidecar_ffi::disconnect_from_device(&manager, &device);
+/// Whether Sidecar offers `ipad_name`, connected or not. Without a name, whether it offers
+/// any device.
+pub fn is_available(
+    backend: &dyn SidecarBackend,
+    ipad_name: Option<&str>,
+) -> Result<bool, SidecarError> {
+    match find_device(backend, ipad_name) {
+        Ok(_) => Ok(true),
+        Err(SidecarError::DeviceNotFound { .. }) => Ok(false),
+        Err(e) => Err(e),
     }
 }
 
-/// Find a Sidecar device by name, normalising Unicode quotes for matching.
-fn find_device(manager: &AnyObject, target_name: Option<&str>) -> Option<Retained<AnyObject>> {
-    unsafe {
-        let array = sidecar_ffi::devices(manager)?;
-        let count = sidecar_ffi::array_count(&array);
-        let normalised_target = target_name.map(normalise_quotes);
-
-        for i in 0..count {
-            let Some(device) = sidecar_ffi::array_object_at(&array, i) else {
-                continue;
-            };
-
-            match normalised_target.as_deref() {
-                Some(target) => {
-                    if let Some(name) = sidecar_ffi::device_name(&device)
-                        && normalise_quotes(&name.to_string()) == target
-                    {
-                        return Some(device);
-                    }
-                }
-                None => return Some(device),
-            }
-        }
+/// Find a Sidecar device by name, normalising Unicode quotes for matching. Without a
+/// name, the first available device is used.
+fn find_device(
+    backend: &dyn SidecarBackend,
+    target_name: Option<&str>,
+) -> Result<String, SidecarError> {
+    let available = backend.devices()?;
+    let found = match target_name.map(normalise_quotes) {
+        Some(target) => available
+            .iter()
+            .find(|name| normalise_quotes(name) == target),
+        None => available.first(),
+    };
 
-        None
-    }
+    found.cloned().ok_or_else(|| SidecarError::DeviceNotFound {
+        wanted: target_name.map(Into::into),
+        available,
+    })
 }
 
 /// Replace common Unicode quote variants with plain ASCII apostrophe.
 pub fn normalise_quotes(s: &str) -> String {
     s.replace(['\u{2019}', '\u{2018}', '\u{02BC}'], "'")
 }
-
-unsafe fn log_available_devices(manager: &AnyObject, target_name: Option<&str>) {
-    unsafe {
-        let Some(array) = sidecar_ffi::devices(manager) else {
-            log::debug!("Could not read Sidecar devices list");
-            return;
-        };
-
-        let count = sidecar_ffi::array_count(&array);
-        if count == 0 {
-            log::info!("Sidecar devices list is currently empty");
-        } else {
-            let mut names: Vec<String> = Vec::with_capacity(count);
-            for i in 0..count {
-                if let Some(device) = sidecar_ffi::array_object_at(&array, i) {
-                    let name = sidecar_ffi::device_name(&device)
-                        .map(|s| s.to_string())
-                        .unwrap_or_else(|| "<unnamed>".into());
-                    names.push(name);
-                }
-            }
-            log::info!("Available Sidecar devices: {:?}", names);
-            if let Some(target) = target_name {
-                log::info!(
-                    "Looking for: {:?} (check config if name doesn't match)",
-                    target
-                );
-            }
-        }
-    }
-}
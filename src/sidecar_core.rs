@@ -0,0 +1,133 @@
+//! [`SidecarBackend`] backed by the private `SidecarCore.framework`.
+
+use core_foundation_sys::runloop::CFRunLoopRun;
+use objc2::rc::Retained;
+use objc2::runtime::AnyObject;
+
+use crate::sidecar::{Completion, SidecarBackend, SidecarError};
+use crate::sidecar_ffi;
+
+impl From<sidecar_ffi::NSErrorInfo> for SidecarError {
+    fn from(info: sidecar_ffi::NSErrorInfo) -> Self {
+        SidecarError::Framework {
+            domain: info.domain,
+            code: info.code,
+            description: info.description,
+        }
+    }
+}
+
+/// The system's Sidecar, reached through `SidecarDisplayManager.sharedManager`.
+#[derive(Debug)]
+pub struct SidecarCore {
+    _private: (),
+}
+
+impl SidecarCore {
+    /// Load the SidecarCore framework.
+    pub fn load() -> Result<Self, SidecarError> {
+        sidecar_ffi::load_framework().map_err(SidecarError::Unavailable)?;
+        Ok(Self { _private: () })
+    }
+}
+
+impl SidecarBackend for SidecarCore {
+    fn devices(&self) -> Result<Vec<String>, SidecarError> {
+        let manager = manager()?;
+        let array = unsafe { sidecar_ffi::devices(&manager) }
+            .ok_or_else(|| SidecarError::Unavailable("Could not read Sidecar devices".into()))?;
+        Ok(unsafe { device_names(&array) })
+    }
+
+    fn connected_devices(&self) -> Result<Vec<String>, SidecarError> {
+        let manager = manager()?;
+        let array = unsafe { sidecar_ffi::connected_devices(&manager) }.ok_or_else(|| {
+            SidecarError::Unavailable("Could not read connected Sidecar devices".into())
+        })?;
+        Ok(unsafe { device_names(&array) })
+    }
+
+    fn connect(&self, device: &str, done: Completion) {
+        let (manager, device) = match lookup(device) {
+            Ok(found) => found,
+            Err(e) => return done(Err(e)),
+        };
+        unsafe {
+            sidecar_ffi::connect_to_device(&manager, &device, move |error| {
+                done(error.map_or(Ok(()), |e| Err(e.into())))
+            });
+        }
+    }
+
+    fn disconnect(&self, device: &str, done: Completion) {
+        let (manager, device) = match lookup(device) {
+            Ok(found) => found,
+            Err(e) => return done(Err(e)),
+        };
+        unsafe {
+            sidecar_ffi::disconnect_from_device(&manager, &device, move |error| {
+                done(error.map_or(Ok(()), |e| Err(e.into())))
+            });
+        }
+    }
+}
+
+/// Run the main thread's run loop forever.
+///
+/// SidecarCore may deliver completion handlers on the main dispatch queue, which is
+/// only serviced while the main thread runs its run loop, so the monitoring loop must
+/// run on another thread.
+pub fn run_main_run_loop() -> ! {
+    loop {
+        unsafe { CFRunLoopRun() };
+    }
+}
+
+fn manager() -> Result<Retained<AnyObject>, SidecarError> {
+    let cls = sidecar_ffi::display_manager_class()
+        .ok_or_else(|| SidecarError::Unavailable("SidecarDisplayManager class not found".into()))?;
+    unsafe { sidecar_ffi::shared_manager(cls) }.ok_or_else(|| {
+        SidecarError::Unavailable("Could not get SidecarDisplayManager.sharedManager".into())
+    })
+}
+
+/// The display manager and the device object named exactly `name`.
+fn lookup(name: &str) -> Result<(Retained<AnyObject>, Retained<AnyObject>), SidecarError> {
+    let manager = manager()?;
+    unsafe {
+        let array = sidecar_ffi::devices(&manager)
+            .ok_or_else(|| SidecarError::Unavailable("Could not read Sidecar devices".into()))?;
+        for i in 0..sidecar_ffi::array_count(&array) {
+            if let Some(device) = sidecar_ffi::array_object_at(&array, i)
+                && sidecar_ffi::device_name(&device).is_some_and(|n| n.to_string() == name)
+            {
+                return Ok((manager, device));
+            }
+        }
+        let available = device_names(&array);
+        Err(SidecarError::DeviceNotFound {
+            wanted: Some(name.into()),
+            available,
+        })
+    }
+}
+
+/// Names of the `SidecarDevice`s in `array`.
+///
+/// # Safety
+/// The caller must ensure `array` is a valid `NSArray<SidecarDevice>`.
+unsafe fn device_names(array: &AnyObject) -> Vec<String> {
+    unsafe {
+        let count = sidecar_ffi::array_count(array);
+        let mut names: Vec<String> = Vec::with_capacity(count);
+        for i in 0..count {
+            if let Some(device) = sidecar_ffi::array_object_at(array, i) {
+                let name = sidecar_ffi::device_name(&device)
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|| "<unnamed>".into());
+                names.push(name);
+            }
+        }
+        names
+    }
+}
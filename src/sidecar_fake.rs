@@ -0,0 +1,195 @@
+//! Scriptable in-memory [`SidecarBackend`] for tests. Only built with the `testing`
+//! feature, which this crate's own tests turn on.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::sidecar::{Completion, SidecarBackend, SidecarError};
+
+/// A request the fake received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Connect(String),
+    Disconnect(String),
+}
+
+/// In-memory Sidecar. Clones share state, so a test can keep a handle while the code
+/// under test owns another.
+#[derive(Debug, Clone, Default)]
+pub struct FakeSidecar {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Listed devices, each with the number of `devices()` calls it stays hidden for.
+    devices: Vec<(String, usize)>,
+    connected: Vec<String>,
+    unavailable: Option<String>,
+    connect_failures: VecDeque<SidecarError>,
+    disconnect_failures: VecDeque<SidecarError>,
+    delay: Duration,
+    calls: Vec<Call>,
+}
+
+impl FakeSidecar {
+    /// A fake with no devices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A fake listing `names` from the start.
+    pub fn with_devices(names: &[&str]) -> Self {
+        let fake = Self::new();
+        for name in names {
+            fake.add_device(name);
+        }
+        fake
+    }
+
+    /// List `name` from now on.
+    pub fn add_device(&self, name: &str) {
+        self.device_appears_after(name, 0);
+    }
+
+    /// List `name` only once `devices()` has been called `polls` times, like an iPad that
+    /// shows up a few seconds after the dock.
+    pub fn device_appears_after(&self, name: &str, polls: usize) {
+        self.lock().devices.push((name.into(), polls));
+    }
+
+    /// Stop listing `name` and drop its session, if any.
+    pub fn remove_device(&self, name: &str) {
+        let mut inner = self.lock();
+        inner.devices.retain(|(n, _)| n != name);
+        inner.connected.retain(|n| n != name);
+    }
+
+    /// End `name`'s session without a disconnect request, as sleep sometimes does.
+    pub fn end_session(&self, name: &str) {
+        self.lock().connected.retain(|n| n != name);
+    }
+
+    /// Make every request fail with [`SidecarError::Unavailable`], or recover with `None`.
+    pub fn set_unavailable(&self, reason: Option<&str>) {
+        self.lock().unavailable = reason.map(Into::into);
+    }
+
+    /// Fail the next connect with `error`. Queued failures are used in order.
+    pub fn fail_next_connect(&self, error: SidecarError) {
+        self.lock().connect_failures.push_back(error);
+    }
+
+    /// Fail the next disconnect with `error`. Queued failures are used in order.
+    pub fn fail_next_disconnect(&self, error: SidecarError) {
+        self.lock().disconnect_failures.push_back(error);
+    }
+
+    /// Complete requests from a background thread after `delay`. With a zero delay (the
+    /// default), completions run before `connect` / `disconnect` return.
+    pub fn set_delay(&self, delay: Duration) {
+        self.lock().delay = delay;
+    }
+
+    /// Every connect and disconnect received so far, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        self.lock().calls.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn request(&self, call: Call, done: Completion) {
+        let delay = {
+            let mut inner = self.lock();
+            inner.calls.push(call.clone());
+            inner.delay
+        };
+        let inner = Arc::clone(&self.inner);
+        let complete = move || {
+            let result = {
+                let mut inner = inner.lock().unwrap_or_else(|e| e.into_inner());
+                inner.complete(&call)
+            };
+            done(result)
+        };
+        if delay.is_zero() {
+            complete();
+        } else {
+            thread::spawn(move || {
+                thread::sleep(delay);
+                complete();
+            });
+        }
+    }
+}
+
+impl Inner {
+    fn check_available(&self) -> Result<(), SidecarError> {
+        match &self.unavailable {
+            Some(reason) => Err(SidecarError::Unavailable(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn visible(&self) -> Vec<String> {
+        self.devices
+            .iter()
+            .filter(|(_, hidden_for)| *hidden_for == 0)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Apply `call` and decide its outcome.
+    fn complete(&mut self, call: &Call) -> Result<(), SidecarError> {
+        self.check_available()?;
+        let (name, failures) = match call {
+            Call::Connect(name) => (name, &mut self.connect_failures),
+            Call::Disconnect(name) => (name, &mut self.disconnect_failures),
+        };
+        if let Some(error) = failures.pop_front() {
+            return Err(error);
+        }
+        if !self.visible().contains(name) {
+            return Err(SidecarError::DeviceNotFound {
+                wanted: Some(name.clone()),
+                available: self.visible(),
+            });
+        }
+
+        self.connected.retain(|n| n != name);
+        if matches!(call, Call::Connect(_)) {
+            self.connected.push(name.clone());
+        }
+        Ok(())
+    }
+}
+
+impl SidecarBackend for FakeSidecar {
+    fn devices(&self) -> Result<Vec<String>, SidecarError> {
+        let mut inner = self.lock();
+        inner.check_available()?;
+        let listed = inner.visible();
+        for (_, hidden_for) in &mut inner.devices {
+            *hidden_for = hidden_for.saturating_sub(1);
+        }
+        Ok(listed)
+    }
+
+    fn connected_devices(&self) -> Result<Vec<String>, SidecarError> {
+        let inner = self.lock();
+        inner.check_available()?;
+        Ok(inner.connected.clone())
+    }
+
+    fn connect(&self, device: &str, done: Completion) {
+        self.request(Call::Connect(device.into()), done);
+    }
+
+    fn disconnect(&self, device: &str, done: Completion) {
+        self.request(Call::Disconnect(device.into()), done);
+    }
+}
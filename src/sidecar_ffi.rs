@@ -4,11 +4,12 @@
 //! All interaction happens through the Objective-C runtime using `objc2`.
 
 use std::ffi::{c_char, c_int, c_void};
+use std::sync::Mutex;
 
-use block2::StackBlock;
-use objc2::msg_send;
+use block2::RcBlock;
 use objc2::rc::Retained;
 use objc2::runtime::{AnyClass, AnyObject};
+use objc2::{msg_send, sel};
 use objc2_foundation::NSString;
 
 const SIDECAR_FRAMEWORK_PATH: &[u8] =
@@ -50,6 +51,21 @@ pub unsafe fn devices(manager: &AnyObject) -> Option<Retained<AnyObject>> {
     unsafe { msg_send![manager, devices] }
 }
 
+/// `[manager connectedDevices]` returning an `NSArray<SidecarDevice>`, or `None` if this
+/// version of SidecarCore does not provide it.
+///
+/// # Safety
+/// The caller must ensure `manager` is a valid `SidecarDisplayManager` instance.
+pub unsafe fn connected_devices(manager: &AnyObject) -> Option<Retained<AnyObject>> {
+    unsafe {
+        let supported: bool = msg_send![manager, respondsToSelector: sel!(connectedDevices)];
+        if !supported {
+            return None;
+        }
+        msg_send![manager, connectedDevices]
+    }
+}
+
 /// `[device name]` returning an `NSString`.
 ///
 /// # Safety
@@ -74,18 +90,57 @@ pub unsafe fn array_object_at(array: &AnyObject, index: usize) -> Option<Retaine
     unsafe { msg_send![array, objectAtIndex: index] }
 }
 
+/// The parts of an `NSError` worth reporting.
+#[derive(Debug, Clone)]
+pub struct NSErrorInfo {
+    pub domain: String,
+    pub code: isize,
+    pub description: String,
+}
+
+/// Read `domain`, `code` and `localizedDescription` from an `NSError`.
+///
+/// # Safety
+/// The caller must ensure `error` is a valid `NSError` instance.
+pub unsafe fn error_info(error: &AnyObject) -> NSErrorInfo {
+    unsafe {
+        let domain: Option<Retained<NSString>> = msg_send![error, domain];
+        let code: isize = msg_send![error, code];
+        let description: Option<Retained<NSString>> = msg_send![error, localizedDescription];
+        NSErrorInfo {
+            domain: domain.map(|s| s.to_string()).unwrap_or_default(),
+            code,
+            description: description.map(|s| s.to_string()).unwrap_or_default(),
+        }
+    }
+}
+
+/// Wrap a one-shot callback in a heap block suitable for a SidecarCore completion handler.
+///
+/// SidecarCore may call the handler on any thread; `completion` runs at most once.
+fn completion_block<F>(completion: F) -> RcBlock<dyn Fn(*mut AnyObject)>
+where
+    F: FnOnce(Option<NSErrorInfo>) + Send + 'static,
+{
+    let slot = Mutex::new(Some(completion));
+    RcBlock::new(move |error: *mut AnyObject| {
+        let Some(completion) = slot.lock().ok().and_then(|mut s| s.take()) else {
+            return;
+        };
+        let info = unsafe { error.as_ref().map(|e| error_info(e)) };
+        completion(info);
+    })
+}
+
 /// `[manager connectToDevice:device completion:block]`.
 ///
 /// # Safety
 /// The caller must ensure `manager` and `device` are valid ObjC instances.
-pub unsafe fn connect_to_device(manager: &AnyObject, device: &AnyObject) {
-    let block = StackBlock::new(|error: *mut AnyObject| {
-        if error.is_null() {
-            log::info!("Sidecar connected successfully");
-        } else {
-            log::error!("Sidecar connection failed (completion reported error)");
-        }
-    });
+pub unsafe fn connect_to_device<F>(manager: &AnyObject, device: &AnyObject, completion: F)
+where
+    F: FnOnce(Option<NSErrorInfo>) + Send + 'static,
+{
+    let block = completion_block(completion);
     unsafe { msg_send![manager, connectToDevice: device, completion: &*block] }
 }
 
@@ -93,13 +148,10 @@ pub unsafe fn connect_to_device(manager: &AnyObject, device: &AnyObject) {
 ///
 /// # Safety
 /// The caller must ensure `manager` and `device` are valid ObjC instances.
-pub unsafe fn disconnect_from_device(manager: &AnyObject, device: &AnyObject) {
-    let block = StackBlock::new(|error: *mut AnyObject| {
-        if error.is_null() {
-            log::info!("Sidecar disconnected successfully");
-        } else {
-            log::error!("Sidecar disconnect failed (completion reported error)");
-        }
-    });
+pub unsafe fn disconnect_from_device<F>(manager: &AnyObject, device: &AnyObject, completion: F)
+where
+    F: FnOnce(Option<NSErrorInfo>) + Send + 'static,
+{
+    let block = completion_block(completion);
     unsafe { msg_send![manager, disconnectFromDevice: device, completion: &*block] }
 }
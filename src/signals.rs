@@ -0,0 +1,60 @@
+//! Termination signals, delivered as messages.
+//!
+//! SIGTERM, which launchd sends when the agent is stopped, and SIGINT are blocked and
+//! collected by a dedicated thread with `sigwait`, so the daemon can shut down in its own
+//! time instead of being killed mid-request. A second signal ends the process at once.
+
+use std::mem;
+use std::process;
+use std::thread;
+
+/// Signals that ask the daemon to stop.
+const TERMINATION: &[(libc::c_int, &str)] = &[(libc::SIGTERM, "SIGTERM"), (libc::SIGINT, "SIGINT")];
+
+/// Block SIGTERM and SIGINT in the calling thread and in every thread it starts from now
+/// on, and call `on_signal` with the signal's name when the first one arrives.
+///
+/// Call this before starting any other thread, or those threads may still be killed by
+/// the signals.
+pub fn on_termination(on_signal: impl FnOnce(&'static str) + Send + 'static) -> Result<(), String> {
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for (signal, _) in TERMINATION {
+            libc::sigaddset(&mut set, *signal);
+        }
+        let rc = libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        if rc != 0 {
+            return Err(format!(
+                "Failed to block termination signals: {}",
+                std::io::Error::from_raw_os_error(rc)
+            ));
+        }
+        set
+    };
+
+    thread::Builder::new()
+        .name("signals".into())
+        .spawn(move || {
+            let mut on_signal = Some(on_signal);
+            loop {
+                let mut signal = 0;
+                if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                    continue;
+                }
+                let name = TERMINATION
+                    .iter()
+                    .find(|(s, _)| *s == signal)
+                    .map_or("signal", |(_, name)| name);
+                match on_signal.take() {
+                    Some(on_signal) => on_signal(name),
+                    None => {
+                        log::warn!("Received {name} again; exiting now");
+                        process::exit(128 + signal);
+                    }
+                }
+            }
+        })
+        .map_err(|e| format!("Failed to start signal thread: {e}"))?;
+    Ok(())
+}
@@ -0,0 +1,696 @@
+//! Dock-to-Sidecar state machine.
+//!
+//! [`step`] is a pure function: it never touches IOKit, SidecarCore or the system clock.
+//! Callers feed it [`Event`]s and carry out the returned [`Effect`]s, reporting their
+//! outcome back as further events. Timed transitions (settling, retries, grace periods)
+//! are expressed as deadlines ([`State::deadline`]); the caller arms a timer and sends
+//! [`Event::Tick`] when it fires.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Where a single profile's dock and Sidecar session currently stand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The dock is absent and no Sidecar session is ours.
+    Idle,
+    /// The dock is attached and Sidecar is not connected. With `next_attempt` set, a connect
+    /// is scheduled (after settling or between retries); otherwise Sidecar is deliberately
+    /// left alone.
+    DockPresent { next_attempt: Option<Attempt> },
+    /// Connect attempt number `attempt` has been requested and its outcome is pending.
+    Connecting { dock_present: bool, attempt: u32 },
+    /// Sidecar is connected. With `disconnect_at` set, the dock has been removed and the
+    /// session is kept until the grace period runs out.
+    Connected { disconnect_at: Option<Instant> },
+    /// A disconnect has been requested and its outcome is pending.
+    Disconnecting { dock_present: bool },
+    /// Every connect attempt failed while the dock was attached. Cleared by removing the
+    /// dock.
+    Failed { since: Instant },
+    /// Automation is suspended; dock and session changes are tracked but not acted on.
+    Paused {
+        dock_present: bool,
+        sidecar_active: bool,
+    },
+    /// Sidecar was connected by someone else. The daemon leaves that session alone, even
+    /// when the dock is removed.
+    External { dock_present: bool },
+    /// The system is asleep. Dock and session changes are tracked until it wakes; with
+    /// `resume` set, Sidecar is then connected if the dock is still attached.
+    Asleep {
+        dock_present: bool,
+        sidecar_active: bool,
+        resume: bool,
+    },
+}
+
+/// A scheduled connect attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempt {
+    /// When to make the attempt.
+    pub at: Instant,
+    /// 1-based attempt number within the current dock cycle.
+    pub number: u32,
+}
+
+/// Something that happened to the dock or the Sidecar session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The profile's dock appeared.
+    DockAppeared,
+    /// The profile's dock was removed.
+    DockRemoved,
+    /// A Thunderbolt switch was removed but its UID could not be read, so it may have been
+    /// the dock. Treated like [`Event::DockRemoved`].
+    UnidentifiedRemoval,
+    /// A requested connect succeeded.
+    ConnectSucceeded,
+    /// A requested connect failed. Only retryable failures lead to another attempt.
+    ConnectFailed { retryable: bool },
+    /// A requested disconnect finished (successfully or not).
+    DisconnectCompleted,
+    /// A timer armed for [`State::deadline`] fired.
+    Tick,
+    /// Stop acting on dock changes.
+    Pause,
+    /// Start acting on dock changes again.
+    Resume,
+    /// Someone asked for Sidecar to connect now. Makes a single attempt while the dock is
+    /// attached and Sidecar is not connected.
+    ConnectRequested,
+    /// Someone asked for Sidecar to disconnect now. With the dock attached, Sidecar is then
+    /// left alone until the dock is removed.
+    DisconnectRequested,
+    /// The system is about to sleep.
+    Sleep,
+    /// The system woke up. `sidecar_connected` is whether the profile's iPad still has a
+    /// session, which sleep often ends without notice.
+    Woke { sidecar_connected: bool },
+    /// A check found the dock attached but Sidecar not connected, although it should be.
+    /// Connect again `after` this long.
+    Repair { after: Duration },
+    /// Sidecar was found connected although the daemon had not connected it.
+    ConnectedExternally,
+    /// The session ended although the daemon had not disconnected it, and the iPad is still
+    /// offered by Sidecar: someone disconnected it on purpose.
+    DisconnectedExternally,
+    /// The daemon is stopping. Nothing new is started; with
+    /// [`Policy::disconnect_on_shutdown`], the session is ended first.
+    Shutdown,
+}
+
+/// An action the caller must carry out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Connect the profile's iPad, then report [`Event::ConnectSucceeded`] or
+    /// [`Event::ConnectFailed`] once the outcome is known.
+    Connect,
+    /// Disconnect the profile's iPad, then report [`Event::DisconnectCompleted`] once the
+    /// outcome is known.
+    Disconnect,
+}
+
+/// A change in the dock or Sidecar session worth reporting outside the daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Notice {
+    /// The dock was attached.
+    DockConnected,
+    /// The dock was removed.
+    DockDisconnected,
+    /// Sidecar connected.
+    SidecarConnected,
+    /// Every connect attempt failed.
+    SidecarFailed,
+}
+
+/// Delays and limits applied to dock changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    /// How long the dock must stay attached before Sidecar is connected.
+    pub settle: Duration,
+    /// How long the dock may be absent before Sidecar is disconnected.
+    pub grace: Duration,
+    /// How many connect attempts to make per dock cycle.
+    pub max_attempts: u32,
+    /// Delay between connect attempts.
+    pub retry_delay: Duration,
+    /// Whether to end the session before the system sleeps. It is started again on wake.
+    pub disconnect_on_sleep: bool,
+    /// How often to check that Sidecar really is connected while it should be. Zero turns
+    /// the checks off.
+    pub reconcile: Duration,
+    /// How long to leave Sidecar disconnected after someone else disconnected it. `None`
+    /// leaves it until the dock is next attached.
+    pub manual_cooldown: Option<Duration>,
+    /// Whether to end the daemon's own session when the daemon stops.
+    pub disconnect_on_shutdown: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            settle: Duration::ZERO,
+            grace: Duration::ZERO,
+            max_attempts: 1,
+            retry_delay: Duration::ZERO,
+            disconnect_on_sleep: false,
+            reconcile: Duration::ZERO,
+            manual_cooldown: None,
+            disconnect_on_shutdown: false,
+        }
+    }
+}
+
+/// The longest wait before a repair, however often the session has dropped.
+const MAX_REPAIR_DELAY: Duration = Duration::from_secs(300);
+
+impl Policy {
+    /// How long to wait before repairing a session that has already been repaired
+    /// `repairs` times in a row: not at all the first time, then `retry_delay` (at least
+    /// a second), doubling each time up to five minutes.
+    pub fn repair_delay(&self, repairs: u32) -> Duration {
+        let Some(doublings) = repairs.checked_sub(1) else {
+            return Duration::ZERO;
+        };
+        let base = self.retry_delay.max(Duration::from_secs(1));
+        base.checked_mul(1 << doublings.min(16))
+            .map_or(MAX_REPAIR_DELAY, |delay| delay.min(MAX_REPAIR_DELAY))
+    }
+}
+
+impl State {
+    /// [`State::name`] of a [`State::Connecting`] state.
+    pub const CONNECTING: &'static str = "connecting";
+
+    /// Whether the dock is known to be attached.
+    pub fn dock_present(&self) -> bool {
+        match *self {
+            State::Idle => false,
+            State::DockPresent { .. } | State::Failed { .. } => true,
+            State::Connected { disconnect_at } => disconnect_at.is_none(),
+            State::Connecting { dock_present, .. }
+            | State::Disconnecting { dock_present }
+            | State::External { dock_present }
+            | State::Paused { dock_present, .. }
+            | State::Asleep { dock_present, .. } => dock_present,
+        }
+    }
+
+    /// Short lowercase name of the variant, e.g. `"connected"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::DockPresent { .. } => "dock_present",
+            State::Connecting { .. } => Self::CONNECTING,
+            State::Connected { .. } => "connected",
+            State::Disconnecting { .. } => "disconnecting",
+            State::Failed { .. } => "failed",
+            State::External { .. } => "external",
+            State::Paused { .. } => "paused",
+            State::Asleep { .. } => "asleep",
+        }
+    }
+
+    /// Whether the iPad is known to have a Sidecar session, ours or one started by hand.
+    pub fn sidecar_connected(&self) -> bool {
+        matches!(self, State::External { .. }) || sidecar_active(*self)
+    }
+
+    /// When this state next needs an [`Event::Tick`], if ever.
+    pub fn deadline(&self) -> Option<Instant> {
+        match *self {
+            State::DockPresent { next_attempt } => next_attempt.map(|a| a.at),
+            State::Connected { disconnect_at } => disconnect_at,
+            _ => None,
+        }
+    }
+}
+
+/// Advance the state machine by one event.
+pub fn step(policy: &Policy, state: State, event: Event, now: Instant) -> (State, Vec<Effect>) {
+    use Event::*;
+    use State::*;
+
+    let removed = matches!(event, DockRemoved | UnidentifiedRemoval);
+    let due = |deadline: Instant| event == Tick && now >= deadline;
+
+    match (state, event) {
+        (
+            Paused {
+                dock_present,
+                sidecar_active,
+            },
+            event,
+        ) => match event {
+            DockAppeared => (
+                Paused {
+                    dock_present: true,
+                    sidecar_active,
+                },
+                vec![],
+            ),
+            DockRemoved | UnidentifiedRemoval => (
+                Paused {
+                    dock_present: false,
+                    sidecar_active,
+                },
+                vec![],
+            ),
+            ConnectSucceeded => (
+                Paused {
+                    dock_present,
+                    sidecar_active: true,
+                },
+                vec![],
+            ),
+            ConnectFailed { .. } | DisconnectCompleted => (
+                Paused {
+                    dock_present,
+                    sidecar_active: false,
+                },
+                vec![],
+            ),
+            Resume => match (dock_present, sidecar_active) {
+                (true, true) => (
+                    Connected {
+                        disconnect_at: None,
+                    },
+                    vec![],
+                ),
+                (true, false) => (DockPresent { next_attempt: None }, vec![]),
+                (false, true) => (
+                    Disconnecting {
+                        dock_present: false,
+                    },
+                    vec![Effect::Disconnect],
+                ),
+                (false, false) => (Idle, vec![]),
+            },
+            Pause
+            | Tick
+            | ConnectRequested
+            | DisconnectRequested
+            | Sleep
+            | Woke { .. }
+            | Repair { .. }
+            | ConnectedExternally
+            | DisconnectedExternally
+            | Shutdown => (state, vec![]),
+        },
+
+        (_, Pause) => (
+            Paused {
+                dock_present: state.dock_present(),
+                sidecar_active: sidecar_active(state),
+            },
+            vec![],
+        ),
+
+        (
+            Asleep {
+                dock_present,
+                sidecar_active,
+                resume,
+            },
+            event,
+        ) => {
+            let asleep = |dock_present, sidecar_active| Asleep {
+                dock_present,
+                sidecar_active,
+                resume,
+            };
+            match event {
+                DockAppeared => (asleep(true, sidecar_active), vec![]),
+                DockRemoved | UnidentifiedRemoval => (asleep(false, sidecar_active), vec![]),
+                // Connected just as the system went to sleep.
+                ConnectSucceeded if policy.disconnect_on_sleep => {
+                    (asleep(dock_present, false), vec![Effect::Disconnect])
+                }
+                ConnectSucceeded => (asleep(dock_present, true), vec![]),
+                ConnectFailed { .. } | DisconnectCompleted => (asleep(dock_present, false), vec![]),
+                Woke { sidecar_connected } => match (dock_present, sidecar_connected) {
+                    (true, true) if resume || sidecar_active => (
+                        Connected {
+                            disconnect_at: None,
+                        },
+                        vec![],
+                    ),
+                    (true, _) if resume => dock_arrived(policy, now),
+                    (true, _) => (DockPresent { next_attempt: None }, vec![]),
+                    (false, true) if sidecar_active => dock_left(policy, now),
+                    (false, _) => (Idle, vec![]),
+                },
+                _ => (state, vec![]),
+            }
+        }
+
+        (Connected { disconnect_at }, Sleep) if policy.disconnect_on_sleep => (
+            Asleep {
+                dock_present: disconnect_at.is_none(),
+                sidecar_active: false,
+                resume: true,
+            },
+            vec![Effect::Disconnect],
+        ),
+        // A profile told to leave Sidecar alone stays that way after waking.
+        (_, Sleep) => (
+            Asleep {
+                dock_present: state.dock_present(),
+                sidecar_active: sidecar_active(state),
+                resume: !matches!(state, DockPresent { next_attempt: None } | External { .. }),
+            },
+            vec![],
+        ),
+        (
+            Connected {
+                disconnect_at: None,
+            }
+            | Failed { .. },
+            Repair { after },
+        ) => (
+            DockPresent {
+                next_attempt: Some(Attempt {
+                    at: now + after,
+                    number: 1,
+                }),
+            },
+            vec![],
+        ),
+
+        (Connected { .. }, Shutdown) if policy.disconnect_on_shutdown => (
+            Disconnecting {
+                dock_present: false,
+            },
+            vec![Effect::Disconnect],
+        ),
+        // Whatever the outcome, nothing follows: a session that comes up is handled by
+        // the next `Shutdown`.
+        (Connecting { attempt, .. }, Shutdown) if policy.disconnect_on_shutdown => (
+            Connecting {
+                dock_present: false,
+                attempt,
+            },
+            vec![],
+        ),
+        (Disconnecting { .. }, Shutdown) => (
+            Disconnecting {
+                dock_present: false,
+            },
+            vec![],
+        ),
+        (DockPresent { .. }, Shutdown) => (DockPresent { next_attempt: None }, vec![]),
+
+        (Idle, ConnectedExternally) => (
+            External {
+                dock_present: false,
+            },
+            vec![],
+        ),
+        (DockPresent { .. } | Failed { .. }, ConnectedExternally) => {
+            (External { dock_present: true }, vec![])
+        }
+        (External { .. }, DockAppeared) => (External { dock_present: true }, vec![]),
+        (External { .. }, _) if removed => (
+            External {
+                dock_present: false,
+            },
+            vec![],
+        ),
+        (
+            Connected {
+                disconnect_at: None,
+            }
+            | External { dock_present: true },
+            DisconnectedExternally,
+        ) => (
+            DockPresent {
+                next_attempt: policy.manual_cooldown.map(|cooldown| Attempt {
+                    at: now + cooldown,
+                    number: 1,
+                }),
+            },
+            vec![],
+        ),
+        (
+            Connected {
+                disconnect_at: Some(_),
+            }
+            | External {
+                dock_present: false,
+            },
+            DisconnectedExternally,
+        ) => (Idle, vec![]),
+
+        // Waking without having seen the system go to sleep.
+        (
+            Connected {
+                disconnect_at: None,
+            },
+            Woke {
+                sidecar_connected: false,
+            },
+        ) => dock_arrived(policy, now),
+
+        (Idle, DockAppeared) => dock_arrived(policy, now),
+
+        (
+            DockPresent {
+                next_attempt: Some(attempt),
+            },
+            Tick,
+        ) if due(attempt.at) => (
+            Connecting {
+                dock_present: true,
+                attempt: attempt.number,
+            },
+            vec![Effect::Connect],
+        ),
+        // Removing the dock drops any scheduled attempt, cancelling pending retries.
+        (DockPresent { .. } | Failed { .. }, _) if removed => (Idle, vec![]),
+
+        (Connecting { attempt, .. }, DockAppeared) => (
+            Connecting {
+                dock_present: true,
+                attempt,
+            },
+            vec![],
+        ),
+        (Connecting { attempt, .. }, _) if removed => (
+            Connecting {
+                dock_present: false,
+                attempt,
+            },
+            vec![],
+        ),
+        (
+            Connecting {
+                dock_present: true, ..
+            },
+            ConnectSucceeded,
+        ) => (
+            Connected {
+                disconnect_at: None,
+            },
+            vec![],
+        ),
+        (
+            Connecting {
+                dock_present: false,
+                ..
+            },
+            ConnectSucceeded,
+        ) => dock_left(policy, now),
+        (
+            Connecting {
+                dock_present: true,
+                attempt,
+            },
+            ConnectFailed { retryable },
+        ) => {
+            if retryable && attempt < policy.max_attempts {
+                (
+                    DockPresent {
+                        next_attempt: Some(Attempt {
+                            at: now + policy.retry_delay,
+                            number: attempt + 1,
+                        }),
+                    },
+                    vec![],
+                )
+            } else {
+                (Failed { since: now }, vec![])
+            }
+        }
+        (
+            Connecting {
+                dock_present: false,
+                ..
+            },
+            ConnectFailed { .. },
+        ) => (Idle, vec![]),
+        // A connect given up on as timed out that succeeded after all.
+        (DockPresent { .. } | Failed { .. }, ConnectSucceeded) => (
+            Connected {
+                disconnect_at: None,
+            },
+            vec![],
+        ),
+        (Idle, ConnectSucceeded) => dock_left(policy, now),
+
+        (
+            Connected {
+                disconnect_at: None,
+            },
+            _,
+        ) if removed => dock_left(policy, now),
+        (
+            Connected {
+                disconnect_at: Some(_),
+            },
+            DockAppeared,
+        ) => (
+            Connected {
+                disconnect_at: None,
+            },
+            vec![],
+        ),
+        (
+            Connected {
+                disconnect_at: Some(at),
+            },
+            Tick,
+        ) if due(at) => (
+            Disconnecting {
+                dock_present: false,
+            },
+            vec![Effect::Disconnect],
+        ),
+
+        (Disconnecting { .. }, DockAppeared) => (Disconnecting { dock_present: true }, vec![]),
+        (Disconnecting { .. }, _) if removed => (
+            Disconnecting {
+                dock_present: false,
+            },
+            vec![],
+        ),
+        (Disconnecting { dock_present: true }, DisconnectCompleted) => dock_arrived(policy, now),
+        (
+            Disconnecting {
+                dock_present: false,
+            },
+            DisconnectCompleted,
+        ) => (Idle, vec![]),
+
+        // A requested attempt counts as the last one, so a failure is final.
+        (DockPresent { .. } | Failed { .. }, ConnectRequested) => (
+            Connecting {
+                dock_present: true,
+                attempt: policy.max_attempts,
+            },
+            vec![Effect::Connect],
+        ),
+        (
+            Connected {
+                disconnect_at: None,
+            },
+            DisconnectRequested,
+        ) => (DockPresent { next_attempt: None }, vec![Effect::Disconnect]),
+        (
+            Connected {
+                disconnect_at: Some(_),
+            },
+            DisconnectRequested,
+        ) => (
+            Disconnecting {
+                dock_present: false,
+            },
+            vec![Effect::Disconnect],
+        ),
+
+        // Repeated appear events, early ticks, stray completions, `Resume` while running and
+        // requests that don't apply are no-ops.
+        _ => (state, vec![]),
+    }
+}
+
+/// What changed between two consecutive states. Dock changes are not reported while
+/// paused.
+pub fn notices(before: State, after: State) -> Vec<Notice> {
+    let mut notices = Vec::new();
+    let paused = matches!(before, State::Paused { .. }) || matches!(after, State::Paused { .. });
+    if !paused {
+        match (before.dock_present(), after.dock_present()) {
+            (false, true) => notices.push(Notice::DockConnected),
+            (true, false) => notices.push(Notice::DockDisconnected),
+            _ => {}
+        }
+    }
+    match (before, after) {
+        (
+            State::Connecting { .. }
+            | State::DockPresent { .. }
+            | State::Failed { .. }
+            | State::Idle,
+            State::Connected { .. },
+        ) => notices.push(Notice::SidecarConnected),
+        (State::Connecting { .. }, State::Failed { .. }) => notices.push(Notice::SidecarFailed),
+        _ => {}
+    }
+    notices
+}
+
+/// Whether `state` has a session of ours, or one being torn down.
+fn sidecar_active(state: State) -> bool {
+    match state {
+        State::Connected { .. } | State::Disconnecting { .. } => true,
+        State::Paused { sidecar_active, .. } | State::Asleep { sidecar_active, .. } => {
+            sidecar_active
+        }
+        _ => false,
+    }
+}
+
+/// The dock is attached and Sidecar is not connected: connect once it has settled.
+fn dock_arrived(policy: &Policy, now: Instant) -> (State, Vec<Effect>) {
+    if policy.settle.is_zero() {
+        (
+            State::Connecting {
+                dock_present: true,
+                attempt: 1,
+            },
+            vec![Effect::Connect],
+        )
+    } else {
+        (
+            State::DockPresent {
+                next_attempt: Some(Attempt {
+                    at: now + policy.settle,
+                    number: 1,
+                }),
+            },
+            vec![],
+        )
+    }
+}
+
+/// The dock is gone while Sidecar is connected: disconnect once the grace period is over.
+fn dock_left(policy: &Policy, now: Instant) -> (State, Vec<Effect>) {
+    if policy.grace.is_zero() {
+        (
+            State::Disconnecting {
+                dock_present: false,
+            },
+            vec![Effect::Disconnect],
+        )
+    } else {
+        (
+            State::Connected {
+                disconnect_at: Some(now + policy.grace),
+            },
+            vec![],
+        )
+    }
+}
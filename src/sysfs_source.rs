@@ -0,0 +1,243 @@
+//! Thunderbolt dock events from Linux sysfs.
+//!
+//! Devices are listed from `/sys/bus/thunderbolt/devices`. Whenever the kernel reports
+//! a Thunderbolt uevent (the same hotplug notifications udev consumes), the directory is
+//! rescanned and the difference reported as [`DockEvent`]s.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use crate::dock_event::{DockEvent, DockEventSource, Properties};
+
+/// Where the kernel lists Thunderbolt devices.
+pub const SYSFS_THUNDERBOLT_DEVICES: &str = "/sys/bus/thunderbolt/devices";
+
+/// Attributes reported as event properties, besides `unique_id`.
+const STRING_ATTRIBUTES: &[&str] = &["vendor_name", "device_name"];
+
+/// Dock events from a sysfs Thunderbolt device directory.
+#[derive(Debug)]
+pub struct SysfsSource {
+    root: PathBuf,
+    changes: Receiver<()>,
+    scanned: bool,
+    /// Devices seen in the last scan, by directory name.
+    known: BTreeMap<String, (Option<u64>, Properties)>,
+    pending: VecDeque<DockEvent>,
+}
+
+impl SysfsSource {
+    /// Report the devices under `root`, rescanning each time `changes` receives. The
+    /// source ends once the sender is dropped.
+    pub fn new(root: impl Into<PathBuf>, changes: Receiver<()>) -> Self {
+        Self {
+            root: root.into(),
+            changes,
+            scanned: false,
+            known: BTreeMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Watch [`SYSFS_THUNDERBOLT_DEVICES`], rescanning on kernel Thunderbolt uevents.
+    #[cfg(target_os = "linux")]
+    pub fn start() -> Result<Self, String> {
+        let changes = uevent::watch_thunderbolt()?;
+        Ok(Self::new(SYSFS_THUNDERBOLT_DEVICES, changes))
+    }
+
+    /// Queue events for every device added or removed since the last scan.
+    fn rescan(&mut self) {
+        let current = scan(&self.root);
+
+        for (name, (uid, properties)) in &self.known {
+            if current.get(name).map(|(now, _)| now) != Some(uid) {
+                self.pending.push_back(DockEvent::Removed {
+                    uid: *uid,
+                    properties: properties.clone(),
+                });
+            }
+        }
+        for (name, (uid, properties)) in &current {
+            if self.known.get(name).map(|(known, _)| known) != Some(uid) {
+                self.pending.push_back(DockEvent::Appeared {
+                    uid: *uid,
+                    properties: properties.clone(),
+                });
+            }
+        }
+
+        self.known = current;
+    }
+}
+
+impl DockEventSource for SysfsSource {
+    fn next_event(&mut self) -> Option<DockEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if self.scanned {
+                self.changes.recv().ok()?;
+            }
+            self.scanned = true;
+            self.rescan();
+        }
+    }
+}
+
+/// Convert a sysfs `unique_id` UUID to the UID IOKit reports for the same device.
+///
+/// The kernel builds the UUID from the 64-bit UID stored little-endian in its first
+/// eight bytes, e.g. UID `0x003DA86E85A8CB00` becomes `00cba885-6ea8-3d00-…`.
+pub fn uid_from_unique_id(unique_id: &str) -> Option<u64> {
+    let hex: String = unique_id
+        .trim()
+        .chars()
+        .filter(|c| *c != '-')
+        .take(16)
+        .collect();
+    if hex.len() != 16 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(u64::from_le_bytes(bytes))
+}
+
+/// Read one device directory. Returns `None` for entries that are not Thunderbolt
+/// devices (domains, XDomain services, retimers).
+pub fn read_device(dir: &Path) -> Option<(Option<u64>, Properties)> {
+    let unique_id = read_attribute(dir, "unique_id")?;
+    let mut properties = Properties::new();
+    properties.insert("unique_id".into(), unique_id.clone());
+    for name in STRING_ATTRIBUTES {
+        if let Some(value) = read_attribute(dir, name) {
+            properties.insert((*name).into(), value);
+        }
+    }
+    Some((uid_from_unique_id(&unique_id), properties))
+}
+
+fn read_attribute(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Every device currently under `root`. A missing directory (no Thunderbolt driver
+/// loaded) lists nothing.
+fn scan(root: &Path) -> BTreeMap<String, (Option<u64>, Properties)> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::debug!("Cannot list {}: {e}", root.display());
+            return BTreeMap::new();
+        }
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Devices are named `<domain>-<route>`; `:` marks services and retimers.
+            if name.contains(':') {
+                return None;
+            }
+            read_device(&entry.path()).map(|device| (name, device))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+mod uevent {
+    use std::mem;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    /// Multicast group of uevents sent by the kernel itself.
+    const KERNEL_GROUP: u32 = 1;
+
+    /// Signal the returned channel for every kernel uevent from the Thunderbolt subsystem,
+    /// and whenever some may have been missed.
+    pub fn watch_thunderbolt() -> Result<Receiver<()>, String> {
+        let socket = open()?;
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("uevent".into())
+            .spawn(move || {
+                let mut buf = vec![0u8; 8192];
+                loop {
+                    let n = unsafe {
+                        libc::recv(socket.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0)
+                    };
+                    if n < 0 {
+                        let err = std::io::Error::last_os_error();
+                        if err.kind() == std::io::ErrorKind::Interrupted {
+                            continue;
+                        }
+                        if err.raw_os_error() == Some(libc::ENOBUFS) {
+                            // Uevents were dropped in a burst; rescan to catch up on them.
+                            log::debug!("Missed some kernel uevents: {err}");
+                            if tx.send(()).is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                        log::error!("Reading kernel uevents failed: {err}");
+                        return;
+                    }
+                    if is_thunderbolt(&buf[..n as usize]) && tx.send(()).is_err() {
+                        return;
+                    }
+                }
+            })
+            .map_err(|e| format!("Failed to start uevent thread: {e}"))?;
+        Ok(rx)
+    }
+
+    fn open() -> Result<OwnedFd, String> {
+        unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            );
+            if fd < 0 {
+                return Err(format!(
+                    "Failed to open uevent socket: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            let socket = OwnedFd::from_raw_fd(fd);
+
+            let mut addr: libc::sockaddr_nl = mem::zeroed();
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = KERNEL_GROUP;
+            let rc = libc::bind(
+                fd,
+                (&addr as *const libc::sockaddr_nl).cast(),
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            );
+            if rc < 0 {
+                return Err(format!(
+                    "Failed to bind uevent socket: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            Ok(socket)
+        }
+    }
+
+    /// Whether a raw `action@devpath\0KEY=VALUE\0…` message is about a Thunderbolt device.
+    fn is_thunderbolt(message: &[u8]) -> bool {
+        message
+            .split(|b| *b == 0)
+            .any(|field| field == b"SUBSYSTEM=thunderbolt")
+    }
+}
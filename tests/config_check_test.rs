@@ -0,0 +1,140 @@
+use sidecar_on_dock::config_check::{self, Diagnostic, Severity};
+
+fn check(text: &str) -> Vec<Diagnostic> {
+    config_check::check(text, None)
+}
+
+fn messages(found: &[Diagnostic]) -> Vec<&str> {
+    found.iter().map(|d| d.message.as_str()).collect()
+}
+
+#[test]
+fn valid_config_has_no_diagnostics() {
+    assert!(check(r#"{"dock_uid": "0x003DA86E85A8CB00", "ipad_name": "My iPad"}"#).is_empty());
+    assert!(
+        check(r#"{"profiles": [{"name": "desk", "dock_uid": "0x1", "on_connect": "true"}]}"#)
+            .is_empty()
+    );
+}
+
+#[test]
+fn syntax_error_has_a_position() {
+    let found = check("{\n  \"dock_uid\": \"0x1\",\n}");
+    assert_eq!(found.len(), 1);
+    assert_eq!((found[0].line, found[0].column), (3, 1));
+    assert_eq!(found[0].severity, Severity::Error);
+    assert!(found[0].message.contains("trailing comma"), "{found:?}");
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let text = r#"{"profiles": [
+  {"dock_uid": "0xZZ", "settle_ms": -1},
+  {"dock_uid": 7, "grace_ms": "5000"}
+]}"#;
+    let found = check(text);
+    let positions: Vec<_> = found.iter().map(|d| (d.line, d.column)).collect();
+    assert_eq!(positions, vec![(2, 16), (2, 37), (3, 16), (3, 31)]);
+    assert!(found.iter().all(|d| d.severity == Severity::Error));
+    assert!(found[0].message.contains("0xZZ"), "{found:?}");
+}
+
+#[test]
+fn unknown_fields_suggest_the_closest_name() {
+    let found =
+        check(r#"{"dock_uid": "0x1", "ipad_nmae": "My iPad", "grace": 1, "colour": "red"}"#);
+    assert_eq!(
+        messages(&found),
+        vec![
+            "Unknown field 'ipad_nmae'; did you mean 'ipad_name'?",
+            "Unknown field 'grace'; did you mean 'grace_ms'?",
+            "Unknown field 'colour'; it is ignored",
+        ]
+    );
+    assert!(found.iter().all(|d| d.severity == Severity::Warning));
+}
+
+#[test]
+fn hook_fields_are_checked() {
+    let found =
+        check(r#"{"dock_uid": "0x1", "on_connect": {"comand": "x", "on_failure": "retry"}}"#);
+    assert_eq!(
+        messages(&found),
+        vec![
+            "on_connect is missing its command",
+            "Unknown field 'comand'; did you mean 'command'?",
+            r#"on_failure must be "warn", "ignore" or {"retry": N}"#,
+        ]
+    );
+}
+
+#[test]
+fn duplicate_fields_are_errors() {
+    let found = check(r#"{"dock_uid": "0x1", "dock_uid": "0x2"}"#);
+    assert_eq!(messages(&found), vec!["'dock_uid' is set more than once"]);
+    assert_eq!(found[0].severity, Severity::Error);
+}
+
+#[test]
+fn dock_uid_and_profiles_conflict() {
+    let both = check(r#"{"dock_uid": "0x1", "profiles": [{"dock_uid": "0x2"}]}"#);
+    assert!(both[0].message.contains("use one or the other"), "{both:?}");
+    let neither = check(r#"{"ipad_name": "My iPad"}"#);
+    assert!(neither[0].message.contains("Set either"), "{neither:?}");
+}
+
+#[test]
+fn top_level_options_are_ignored_with_profiles() {
+    let found = check(r#"{"settle_ms": 0, "profiles": [{"dock_uid": "0x1"}]}"#);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].severity, Severity::Warning);
+    assert!(found[0].message.contains("settle_ms"), "{found:?}");
+}
+
+#[test]
+fn duplicate_profile_names_are_ambiguous() {
+    let found = check(
+        r#"{"profiles": [{"name": "desk", "dock_uid": "0x1"}, {"name": "desk", "dock_uid": "0x2"}]}"#,
+    );
+    assert_eq!(found.len(), 1);
+    assert!(found[0].message.contains("ambiguous"), "{found:?}");
+}
+
+#[test]
+fn ipad_names_are_checked_against_visible_devices() {
+    let text = r#"{"profiles": [
+        {"dock_uid": "0x1", "ipad_name": "Sam’s iPad"},
+        {"dock_uid": "0x2", "ipad_name": "Wrok iPad"},
+        {"dock_uid": "0x3", "ipad_name": "Kitchen"}
+    ]}"#;
+    let devices = vec!["Sam's iPad".to_string(), "Work iPad".to_string()];
+    let found = config_check::check(text, Some(&devices));
+    assert_eq!(
+        messages(&found),
+        vec![
+            "No Sidecar device is named 'Wrok iPad'; did you mean 'Work iPad'?",
+            "No Sidecar device is named 'Kitchen' (visible: Sam's iPad, Work iPad)",
+        ]
+    );
+    assert!(found.iter().all(|d| d.severity == Severity::Warning));
+    // Without a device list, names are not checked.
+    assert!(check(text).is_empty());
+}
+
+#[test]
+fn diagnostics_display_like_a_compiler() {
+    let found = check(r#"{"dock_uid": "nope"}"#);
+    assert_eq!(
+        found[0].to_string(),
+        "1:14: error: Invalid dock_uid 'nope': invalid digit found in string"
+    );
+}
+
+#[test]
+fn disconnect_on_sleep_must_be_a_boolean() {
+    assert!(check(r#"{"dock_uid": "0x1", "disconnect_on_sleep": true}"#).is_empty());
+    let found = check(r#"{"dock_uid": "0x1", "disconnect_on_sleep": "yes"}"#);
+    assert_eq!(found.len(), 1, "{found:?}");
+    assert_eq!((found[0].line, found[0].column), (1, 44));
+    assert!(found[0].message.contains("true or false"), "{found:?}");
+}
@@ -2,12 +2,13 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use sidecar_on_dock::config::Config;
+use sidecar_on_dock::config::{Config, FailurePolicy, ProfileOptions};
 
 fn cfg(dock_uid: &str, ipad_name: Option<&str>) -> Config {
     Config {
-        dock_uid: dock_uid.into(),
+        dock_uid: Some(dock_uid.into()),
         ipad_name: ipad_name.map(Into::into),
+        ..Default::default()
     }
 }
 
@@ -70,7 +71,7 @@ fn load_valid_json() {
     writeln!(f, r#"{{"dock_uid": "0xABCD", "ipad_name": "My iPad"}}"#).unwrap();
 
     let c = Config::load(&path).unwrap();
-    assert_eq!(c.dock_uid, "0xABCD");
+    assert_eq!(c.dock_uid.as_deref(), Some("0xABCD"));
     assert_eq!(c.ipad_name.as_deref(), Some("My iPad"));
 }
 
@@ -81,7 +82,7 @@ fn load_minimal_json() {
     fs::write(&path, r#"{"dock_uid": "0xFF"}"#).unwrap();
 
     let c = Config::load(&path).unwrap();
-    assert_eq!(c.dock_uid, "0xFF");
+    assert_eq!(c.dock_uid.as_deref(), Some("0xFF"));
     assert!(c.ipad_name.is_none());
 }
 
@@ -107,6 +108,129 @@ fn save_and_reload() {
     original.save(&path).unwrap();
 
     let loaded = Config::load(&path).unwrap();
-    assert_eq!(loaded.dock_uid, "0xDEAD");
+    assert_eq!(loaded.dock_uid.as_deref(), Some("0xDEAD"));
     assert_eq!(loaded.ipad_name.as_deref(), Some("Test iPad"));
 }
+
+// --- profiles ---
+
+#[test]
+fn single_dock_config_expands_to_one_profile() {
+    let profiles = cfg("0xFF", Some("My iPad")).profiles().unwrap();
+    assert_eq!(profiles.len(), 1);
+    assert_eq!(profiles[0].dock_uid_u64().unwrap(), 0xFF);
+    assert_eq!(profiles[0].ipad_name.as_deref(), Some("My iPad"));
+    assert_eq!(profiles[0].options, ProfileOptions::default());
+}
+
+#[test]
+fn load_profiles_list() {
+    let c: Config = serde_json::from_str(
+        r#"{
+            "profiles": [
+                {"name": "home", "dock_uid": "0x01", "ipad_name": "Home iPad"},
+                {"name": "office", "dock_uid": "0x02", "connect_retries": 3}
+            ]
+        }"#,
+    )
+    .unwrap();
+
+    let profiles = c.profiles().unwrap();
+    assert_eq!(profiles.len(), 2);
+    assert_eq!(profiles[0].label(), "home");
+    assert_eq!(profiles[0].options.connect_retries, 10);
+    assert_eq!(profiles[1].dock_uid_u64().unwrap(), 0x02);
+    assert!(profiles[1].ipad_name.is_none());
+    assert_eq!(profiles[1].options.connect_retries, 3);
+}
+
+#[test]
+fn profile_label_defaults_to_uid() {
+    let c: Config = serde_json::from_str(r#"{"profiles": [{"dock_uid": "0xAB"}]}"#).unwrap();
+    assert_eq!(c.profiles().unwrap()[0].label(), "0xAB");
+}
+
+#[test]
+fn profiles_rejects_both_forms() {
+    let c: Config =
+        serde_json::from_str(r#"{"dock_uid": "0x01", "profiles": [{"dock_uid": "0x02"}]}"#)
+            .unwrap();
+    assert!(c.profiles().is_err());
+}
+
+#[test]
+fn profiles_rejects_empty_config() {
+    assert!(Config::default().profiles().is_err());
+}
+
+#[test]
+fn profiles_rejects_invalid_uid() {
+    let c: Config = serde_json::from_str(r#"{"profiles": [{"dock_uid": "nope"}]}"#).unwrap();
+    assert!(c.profiles().is_err());
+}
+
+// --- hooks ---
+
+#[test]
+fn hook_as_plain_command() {
+    let cfg: Config =
+        serde_json::from_str(r#"{"dock_uid": "0x1", "on_connect": "mount-shares"}"#).unwrap();
+    let hook = cfg.options.hooks.on_connect.unwrap();
+    assert_eq!(hook.command, "mount-shares");
+    assert_eq!(hook.timeout_secs, 30);
+    assert_eq!(hook.on_failure, FailurePolicy::Warn);
+    assert!(cfg.options.hooks.on_disconnect.is_none());
+}
+
+#[test]
+fn hook_with_options() {
+    let cfg: Config = serde_json::from_str(
+        r#"{"dock_uid": "0x1",
+            "on_sidecar_failed": {"command": "notify", "timeout_secs": 5, "on_failure": "ignore"},
+            "on_disconnect": {"command": "vpn down", "on_failure": {"retry": 2}}}"#,
+    )
+    .unwrap();
+    let hooks = cfg.options.hooks;
+    let failed = hooks.on_sidecar_failed.unwrap();
+    assert_eq!(failed.timeout_secs, 5);
+    assert_eq!(failed.on_failure, FailurePolicy::Ignore);
+    assert_eq!(
+        hooks.on_disconnect.unwrap().on_failure,
+        FailurePolicy::Retry(2)
+    );
+}
+
+#[test]
+fn profile_hooks_are_per_profile() {
+    let cfg: Config = serde_json::from_str(
+        r#"{"profiles": [
+            {"dock_uid": "0x1", "on_connect": "home"},
+            {"dock_uid": "0x2"}
+        ]}"#,
+    )
+    .unwrap();
+    let profiles = cfg.profiles().unwrap();
+    assert_eq!(
+        profiles[0]
+            .options
+            .hooks
+            .on_connect
+            .as_ref()
+            .unwrap()
+            .command,
+        "home"
+    );
+    assert!(profiles[1].options.hooks.on_connect.is_none());
+}
+
+#[test]
+fn hooks_survive_save_and_load() {
+    let dir = tempdir();
+    let path = dir.join("config.json");
+    let cfg: Config =
+        serde_json::from_str(r#"{"dock_uid": "0x1", "on_sidecar_connected": "say hi"}"#).unwrap();
+    cfg.save(&path).unwrap();
+    let loaded = Config::load(&path).unwrap();
+    assert_eq!(loaded.options, cfg.options);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use sidecar_on_dock::config_watch;
+
+const CONFIG: &str = r#"{"dock_uid": "0x1"}"#;
+const EDITED: &str = r#"{"dock_uid": "0x2"}"#;
+
+fn assert_changed(changes: &Receiver<()>) {
+    changes
+        .recv_timeout(Duration::from_secs(5))
+        .expect("change was not reported");
+}
+
+fn assert_quiet(changes: &Receiver<()>) {
+    assert!(changes.recv_timeout(Duration::from_millis(600)).is_err());
+}
+
+#[test]
+fn edit_in_place_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(&path, CONFIG).unwrap();
+    let changes = config_watch::watch(&path).unwrap();
+
+    fs::write(&path, EDITED).unwrap();
+    assert_changed(&changes);
+    assert_quiet(&changes);
+}
+
+#[test]
+fn replacing_the_file_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(&path, CONFIG).unwrap();
+    let changes = config_watch::watch(&path).unwrap();
+
+    let saved = dir.path().join(".config.json.swp");
+    fs::write(&saved, EDITED).unwrap();
+    fs::rename(&saved, &path).unwrap();
+    assert_changed(&changes);
+
+    // The replacement is watched too.
+    fs::write(&path, CONFIG).unwrap();
+    assert_changed(&changes);
+}
+
+#[test]
+fn rewriting_the_same_contents_is_ignored() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(&path, CONFIG).unwrap();
+    let changes = config_watch::watch(&path).unwrap();
+
+    fs::write(&path, CONFIG).unwrap();
+    fs::write(dir.path().join("notes.txt"), "unrelated").unwrap();
+    assert_quiet(&changes);
+}
+
+#[test]
+fn polling_reports_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(&path, CONFIG).unwrap();
+    let changes = config_watch::poll(&path, Duration::from_millis(50)).unwrap();
+    assert_quiet(&changes);
+
+    fs::write(&path, EDITED).unwrap();
+    assert_changed(&changes);
+}
+
+#[test]
+fn deleted_file_is_picked_up_when_restored() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(&path, CONFIG).unwrap();
+    let changes = config_watch::watch(&path).unwrap();
+
+    fs::remove_file(&path).unwrap();
+    assert_quiet(&changes);
+    fs::write(&path, EDITED).unwrap();
+    assert_changed(&changes);
+}
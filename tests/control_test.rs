@@ -0,0 +1,475 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use sidecar_on_dock::config::Config;
+use sidecar_on_dock::control::{Client, ProfileStatus, Request, Response, Server};
+use sidecar_on_dock::dock_event::{self, DockEvent};
+use sidecar_on_dock::dock_monitor::{self, Control};
+use sidecar_on_dock::manual::{self, Action, Failure};
+use sidecar_on_dock::sidecar::SidecarBackend;
+use sidecar_on_dock::sidecar_fake::{Call, FakeSidecar};
+use sidecar_on_dock::state::Notice;
+
+const DOCK: u64 = 0x003DA86E85A8CB00;
+const OTHER_DOCK: u64 = 0x00AAAAAAAAAAAAAA;
+
+const ONE_PROFILE: &str = r#"{"profiles": [
+    {"name": "desk", "dock_uid": "0x003DA86E85A8CB00", "ipad_name": "My iPad",
+     "connect_retries": 1, "retry_delay_secs": 0, "settle_ms": 0, "grace_ms": 0}
+]}"#;
+
+const TWO_PROFILES: &str = r#"{"profiles": [
+    {"name": "desk", "dock_uid": "0x003DA86E85A8CB00", "ipad_name": "My iPad",
+     "connect_retries": 1, "retry_delay_secs": 0, "settle_ms": 0, "grace_ms": 0},
+    {"name": "studio", "dock_uid": "0x00AAAAAAAAAAAAAA", "ipad_name": "Studio iPad",
+     "connect_retries": 1, "retry_delay_secs": 0, "settle_ms": 0, "grace_ms": 0}
+]}"#;
+
+/// Checks Sidecar every second.
+const WATCHED: &str = r#"{"profiles": [
+    {"name": "desk", "dock_uid": "0x003DA86E85A8CB00", "ipad_name": "My iPad",
+     "connect_retries": 1, "retry_delay_secs": 0, "settle_ms": 0, "grace_ms": 0,
+     "reconcile_secs": 1}
+]}"#;
+
+/// A monitor driven by a channel source and a fake backend, listening on a temporary
+/// socket and reloading from a temporary config file.
+struct Daemon {
+    dir: tempfile::TempDir,
+    docks: Sender<DockEvent>,
+    fake: FakeSidecar,
+}
+
+impl Daemon {
+    fn start(config: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        fs::write(&config_path, config).unwrap();
+        let profiles = Config::load(&config_path).unwrap().profiles().unwrap();
+
+        let server = Server::bind(&dir.path().join("run").join("control.sock")).unwrap();
+        let (requests_tx, requests) = mpsc::channel();
+        server.serve(requests_tx).unwrap();
+        let control = Control {
+            requests,
+            reload: Box::new(move || Config::load(&config_path)?.profiles()),
+        };
+
+        let fake = FakeSidecar::with_devices(&["My iPad", "Studio iPad"]);
+        let backend = fake.clone();
+        let (docks, source) = dock_event::channel();
+        thread::spawn(move || {
+            dock_monitor::run_with_control(profiles, Box::new(source), Box::new(backend), control)
+        });
+        Self { dir, docks, fake }
+    }
+
+    fn socket(&self) -> PathBuf {
+        self.dir.path().join("run").join("control.sock")
+    }
+
+    fn client(&self) -> Client {
+        Client::open(&self.socket()).unwrap()
+    }
+
+    fn write_config(&self, config: &str) {
+        fs::write(self.dir.path().join("config.json"), config).unwrap();
+    }
+
+    fn attach(&self, uid: u64) {
+        self.docks
+            .send(DockEvent::Appeared {
+                uid: Some(uid),
+                properties: Default::default(),
+            })
+            .unwrap();
+    }
+
+    /// Poll `status` until `profile` reaches `state`.
+    fn wait_for(&self, profile: &str, state: &str) -> ProfileStatus {
+        let mut client = self.client();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let status = client.status().unwrap();
+            let found = status.iter().find(|p| p.name == profile);
+            if let Some(found) = found.filter(|p| p.state == state) {
+                return found.clone();
+            }
+            assert!(
+                Instant::now() < deadline,
+                "'{profile}' never became {state}: {status:?}"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+// --- protocol ---
+
+#[test]
+fn requests_are_tagged_by_command() {
+    let connect = Request::Connect {
+        profile: Some("desk".into()),
+    };
+    assert_eq!(
+        serde_json::to_value(&connect).unwrap(),
+        json!({"command": "connect", "profile": "desk"})
+    );
+    assert_eq!(
+        serde_json::from_value::<Request>(json!({"command": "pause"})).unwrap(),
+        Request::Pause { profile: None }
+    );
+    assert_eq!(
+        serde_json::from_value::<Request>(json!({"command": "status"})).unwrap(),
+        Request::Status
+    );
+}
+
+#[test]
+fn responses_are_tagged_by_type() {
+    let error = Response::error("nope");
+    assert_eq!(
+        serde_json::to_value(&error).unwrap(),
+        json!({"type": "error", "message": "nope"})
+    );
+    assert_eq!(
+        serde_json::to_value(Response::Ok).unwrap(),
+        json!({"type": "ok"})
+    );
+}
+
+// --- socket ---
+
+#[test]
+fn socket_is_private_to_the_user() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run").join("control.sock");
+    let server = Server::bind(&path).unwrap();
+    assert_eq!(server.path(), path);
+    assert_eq!(mode(&dir.path().join("run")), 0o700);
+    assert_eq!(mode(&path), 0o600);
+}
+
+#[test]
+fn stale_socket_is_replaced() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("control.sock");
+    drop(UnixListener::bind(&path).unwrap());
+    assert!(Server::bind(&path).is_ok());
+}
+
+#[test]
+fn live_socket_is_not_taken_over() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("control.sock");
+    let _first = Server::bind(&path).unwrap();
+    let err = Server::bind(&path).unwrap_err();
+    assert!(err.contains("already listening"), "{err}");
+}
+
+#[test]
+fn malformed_request_is_answered_with_an_error() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    let mut stream = UnixStream::connect(daemon.socket()).unwrap();
+    stream.write_all(b"{\"command\": \"explode\"}\n").unwrap();
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    let response: Response = serde_json::from_str(&line).unwrap();
+    assert!(matches!(response, Response::Error { .. }), "{line}");
+}
+
+// --- daemon ---
+
+#[test]
+fn status_reports_each_profile() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.attach(DOCK);
+    let desk = daemon.wait_for("desk", "connected");
+    assert_eq!(
+        desk,
+        ProfileStatus {
+            name: "desk".into(),
+            dock_uid: "0x003DA86E85A8CB00".into(),
+            ipad_name: Some("My iPad".into()),
+            state: "connected".into(),
+            dock_present: true,
+            sidecar_connected: true,
+        }
+    );
+}
+
+#[test]
+fn manual_disconnect_and_connect() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "connected");
+
+    let mut client = daemon.client();
+    client.disconnect(None).unwrap();
+    assert!(daemon.fake.connected_devices().unwrap().is_empty());
+    let desk = daemon.wait_for("desk", "dock_present");
+    assert!(desk.dock_present && !desk.sidecar_connected);
+
+    client.connect(Some("desk")).unwrap();
+    assert_eq!(
+        daemon.fake.connected_devices().unwrap(),
+        vec!["My iPad".to_string()]
+    );
+    daemon.wait_for("desk", "connected");
+}
+
+#[test]
+fn disconnect_is_answered_when_a_connect_follows_it() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "connected");
+    daemon.fake.set_delay(Duration::from_millis(200));
+
+    let mut client = daemon.client();
+    let (done, disconnected) = mpsc::channel();
+    thread::spawn(move || done.send(client.disconnect(None)));
+    daemon.wait_for("desk", "dock_present");
+    daemon.client().connect(None).unwrap();
+
+    let answer = disconnected.recv_timeout(Duration::from_secs(5));
+    assert_eq!(answer, Ok(Ok(())));
+    daemon.wait_for("desk", "connected");
+}
+
+#[test]
+fn connect_without_the_dock_is_not_tracked() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    let mut client = daemon.client();
+    client.connect(None).unwrap();
+    let desk = daemon.wait_for("desk", "idle");
+    assert!(desk.sidecar_connected && !desk.dock_present);
+
+    client.disconnect(None).unwrap();
+    assert!(daemon.fake.connected_devices().unwrap().is_empty());
+    assert!(!client.status().unwrap()[0].sidecar_connected);
+}
+
+#[test]
+fn connect_reports_sidecar_errors() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.fake.remove_device("My iPad");
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "failed");
+
+    let err = daemon.client().connect(None).unwrap_err();
+    assert!(err.contains("My iPad"), "{err}");
+    daemon.wait_for("desk", "failed");
+}
+
+#[test]
+fn sidecar_errors_keep_their_exit_code_through_the_daemon() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.fake.remove_device("My iPad");
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "failed");
+
+    let direct = || panic!("the daemon should have been used");
+    let err = manual::perform(Action::Connect, None, &daemon.socket(), direct).unwrap_err();
+    assert!(matches!(err, Failure::NotFound(_)), "{err:?}");
+    assert_eq!(err.exit_code(), 4);
+}
+
+#[test]
+fn unknown_profile_is_an_error() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    let err = daemon.client().pause(Some("kitchen")).unwrap_err();
+    assert!(err.contains("kitchen"), "{err}");
+}
+
+#[test]
+fn several_profiles_need_a_name_to_connect() {
+    let daemon = Daemon::start(TWO_PROFILES);
+    let err = daemon.client().connect(None).unwrap_err();
+    assert!(err.contains("desk") && err.contains("studio"), "{err}");
+}
+
+#[test]
+fn pause_stops_acting_on_the_dock() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    let mut client = daemon.client();
+    client.pause(None).unwrap();
+    daemon.attach(DOCK);
+    // Already paused, so wait for the dock to be noticed.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !client.status().unwrap()[0].dock_present {
+        assert!(Instant::now() < deadline, "The dock was never noticed");
+        thread::sleep(Duration::from_millis(10));
+    }
+    daemon.wait_for("desk", "paused");
+    assert!(daemon.fake.calls().is_empty());
+
+    client.resume(None).unwrap();
+    daemon.wait_for("desk", "dock_present");
+    assert!(daemon.fake.calls().is_empty());
+}
+
+#[test]
+fn subscribers_see_every_change() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    let mut events = daemon.client().subscribe().unwrap();
+    let Some(Ok(Response::Status { profiles })) = events.next() else {
+        panic!("expected a status snapshot");
+    };
+    assert_eq!(profiles[0].state, "idle");
+
+    daemon.attach(DOCK);
+    let mut seen = Vec::new();
+    for event in events.by_ref() {
+        let Response::Changed { profile, notices } = event.unwrap() else {
+            panic!("expected a change");
+        };
+        seen.push((profile.state, notices));
+        if profile.sidecar_connected {
+            break;
+        }
+    }
+    assert_eq!(
+        seen,
+        vec![
+            ("connecting".to_string(), vec![Notice::DockConnected]),
+            ("connected".to_string(), vec![Notice::SidecarConnected]),
+        ]
+    );
+}
+
+#[test]
+fn reload_picks_up_new_profiles() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.attach(DOCK);
+    daemon.attach(OTHER_DOCK);
+    daemon.wait_for("desk", "connected");
+
+    daemon.write_config(TWO_PROFILES);
+    daemon.client().reload().unwrap();
+    // The dock was already attached, so the new profile connects straight away.
+    daemon.wait_for("studio", "connected");
+    // The unchanged profile keeps its session.
+    daemon.wait_for("desk", "connected");
+    assert_eq!(daemon.fake.calls().len(), 2);
+}
+
+#[test]
+fn broken_config_keeps_the_current_profiles() {
+    let daemon = Daemon::start(TWO_PROFILES);
+    daemon.write_config(r#"{"profiles": [{"dock_uid": "not hex"}]}"#);
+    let mut client = daemon.client();
+    let err = client.reload().unwrap_err();
+    assert!(err.contains("not hex"), "{err}");
+    assert_eq!(client.status().unwrap().len(), 2);
+}
+
+#[test]
+fn dropped_session_is_reconnected_and_recorded() {
+    let daemon = Daemon::start(WATCHED);
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "connected");
+    // The iPad vanishes from Sidecar's list for a moment, as it does when the session
+    // drops on its own.
+    daemon.fake.remove_device("My iPad");
+    daemon.fake.device_appears_after("My iPad", 1);
+
+    let mut client = daemon.client();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let corrections = loop {
+        let corrections = client.corrections().unwrap();
+        if !corrections.is_empty() && !daemon.fake.connected_devices().unwrap().is_empty() {
+            break corrections;
+        }
+        assert!(Instant::now() < deadline, "session was never repaired");
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(corrections.len(), 1);
+    assert_eq!(corrections[0].profile, "desk");
+    assert_eq!(corrections[0].problem, "Sidecar session dropped");
+    assert_eq!(corrections[0].retry_in_secs, 0);
+    daemon.wait_for("desk", "connected");
+}
+
+#[test]
+fn disconnect_by_hand_is_respected() {
+    let daemon = Daemon::start(WATCHED);
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "connected");
+    // Disconnected from Control Center: the iPad is still offered.
+    daemon.fake.end_session("My iPad");
+
+    let desk = daemon.wait_for("desk", "dock_present");
+    assert!(!desk.sidecar_connected);
+    thread::sleep(Duration::from_millis(1500));
+    assert!(daemon.fake.connected_devices().unwrap().is_empty());
+    assert!(daemon.client().corrections().unwrap().is_empty());
+    assert_eq!(daemon.fake.calls().len(), 1);
+}
+
+#[test]
+fn session_started_by_hand_is_left_alone() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.client().connect(None).unwrap();
+    daemon.attach(DOCK);
+    let desk = daemon.wait_for("desk", "external");
+    assert!(desk.dock_present && desk.sidecar_connected);
+
+    daemon
+        .docks
+        .send(DockEvent::Removed {
+            uid: Some(DOCK),
+            properties: Default::default(),
+        })
+        .unwrap();
+    daemon.wait_for("desk", "external");
+    assert!(!daemon.client().status().unwrap()[0].dock_present);
+    assert_eq!(
+        daemon.fake.connected_devices().unwrap(),
+        vec!["My iPad".to_string()]
+    );
+    assert_eq!(daemon.fake.calls(), vec![Call::Connect("My iPad".into())]);
+}
+
+#[test]
+fn shutdown_disconnects_when_configured() {
+    let daemon = Daemon::start(&ONE_PROFILE.replace(
+        r#""grace_ms": 0"#,
+        r#""grace_ms": 0, "disconnect_on_shutdown": true"#,
+    ));
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "connected");
+
+    daemon.client().shutdown().unwrap();
+    assert!(daemon.fake.connected_devices().unwrap().is_empty());
+    assert_eq!(
+        daemon.fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Disconnect("My iPad".into())
+        ]
+    );
+}
+
+#[test]
+fn shutdown_leaves_the_session_by_default() {
+    let daemon = Daemon::start(ONE_PROFILE);
+    daemon.attach(DOCK);
+    daemon.wait_for("desk", "connected");
+
+    daemon.client().shutdown().unwrap();
+    assert_eq!(
+        daemon.fake.connected_devices().unwrap(),
+        vec!["My iPad".to_string()]
+    );
+}
@@ -0,0 +1,106 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use sidecar_on_dock::config::Config;
+use sidecar_on_dock::daemon::Daemon;
+use sidecar_on_dock::dock_event::{self, DockEvent};
+use sidecar_on_dock::sidecar_fake::{Call, FakeSidecar};
+use sidecar_on_dock::state::Notice;
+
+const DOCK: u64 = 0x003DA86E85A8CB00;
+
+fn config() -> Config {
+    serde_json::from_str(
+        r#"{"profiles": [
+            {"name": "desk", "dock_uid": "0x003DA86E85A8CB00", "ipad_name": "My iPad",
+             "connect_retries": 1, "retry_delay_secs": 0, "settle_ms": 0, "grace_ms": 0}
+        ]}"#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn runs_until_stopped() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let (docks, source) = dock_event::channel();
+    let (changes_tx, changes) = mpsc::channel();
+    let daemon = Daemon::builder()
+        .config(config())
+        .source(source)
+        .backend(fake.clone())
+        .on_event(move |profile, notices| {
+            let _ = changes_tx.send((profile.state.clone(), notices.to_vec()));
+        })
+        .build()
+        .unwrap();
+    let handle = daemon.handle();
+    let running = thread::spawn(move || daemon.run());
+
+    docks
+        .send(DockEvent::Appeared {
+            uid: Some(DOCK),
+            properties: Default::default(),
+        })
+        .unwrap();
+    let (state, notices) = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(state, "connecting");
+    assert_eq!(notices, vec![Notice::DockConnected]);
+    let (state, notices) = changes.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(state, "connected");
+    assert_eq!(notices, vec![Notice::SidecarConnected]);
+
+    let status = handle.status().unwrap();
+    assert_eq!(status[0].state, "connected");
+    handle.disconnect(None).unwrap();
+    assert_eq!(
+        fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Disconnect("My iPad".into())
+        ]
+    );
+
+    handle.stop();
+    running.join().unwrap().unwrap();
+    assert!(handle.status().is_err());
+}
+
+#[test]
+fn stop_before_run_returns_at_once() {
+    let (_docks, source) = dock_event::channel();
+    let daemon = Daemon::builder()
+        .config(config())
+        .source(source)
+        .backend(FakeSidecar::new())
+        .build()
+        .unwrap();
+    daemon.handle().stop();
+    daemon.run().unwrap();
+}
+
+#[test]
+fn reload_needs_a_loader() {
+    let (_docks, source) = dock_event::channel();
+    let builder = Daemon::builder()
+        .config(config())
+        .source(source)
+        .backend(FakeSidecar::new());
+    let handle = builder.handle();
+    let daemon = builder.build().unwrap();
+    let running = thread::spawn(move || daemon.run());
+
+    assert_eq!(handle.reload().unwrap_err(), "Reloading is not supported");
+    handle.stop();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn config_is_required() {
+    let err = Daemon::builder()
+        .backend(FakeSidecar::new())
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err, "No config given");
+}
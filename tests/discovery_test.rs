@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::json;
+use sidecar_on_dock::discovery::{
+    self, Layout, Report, SCHEMA_VERSION, SidecarDevice, ThunderboltDevice,
+};
+
+fn dock() -> ThunderboltDevice {
+    ThunderboltDevice {
+        name: "TS4".into(),
+        uid: "0x003DA86E85A8CB00".into(),
+        vendor: "CalDigit, Inc.".into(),
+        route_string: Some("1".into()),
+        depth: Some(1),
+        port: Some(1),
+        link_speed: None,
+        link_width: None,
+        firmware: None,
+        apple_internal: false,
+        children: Vec::new(),
+    }
+}
+
+/// The Mac's own controller, with `dock()` plugged into it.
+fn topology() -> Vec<ThunderboltDevice> {
+    vec![ThunderboltDevice {
+        name: "MacBook Pro".into(),
+        uid: "0x05AC8A2B1C3D4E00".into(),
+        vendor: "Apple Inc.".into(),
+        route_string: Some("0".into()),
+        depth: Some(0),
+        port: None,
+        link_speed: None,
+        link_width: None,
+        firmware: None,
+        apple_internal: true,
+        children: vec![dock()],
+    }]
+}
+
+fn ipad() -> SidecarDevice {
+    SidecarDevice {
+        name: "My iPad".into(),
+    }
+}
+
+#[test]
+fn json_layout_is_stable() {
+    let report = Report::new(Some(Ok(vec![dock()])), Some(Ok(vec![ipad()])));
+    assert_eq!(
+        serde_json::to_value(&report).unwrap(),
+        json!({
+            "schema_version": SCHEMA_VERSION,
+            "thunderbolt": {"devices": [
+                {"name": "TS4", "uid": "0x003DA86E85A8CB00", "vendor": "CalDigit, Inc.",
+                 "route_string": "1", "depth": 1, "port": 1, "apple_internal": false}
+            ]},
+            "sidecar": {"devices": [{"name": "My iPad"}]}
+        })
+    );
+    assert!(!report.failed());
+}
+
+#[test]
+fn failed_section_carries_its_error() {
+    let report = Report::new(Some(Ok(vec![dock()])), Some(Err("no SidecarCore".into())));
+    assert!(report.failed());
+    assert_eq!(
+        serde_json::to_value(&report).unwrap()["sidecar"],
+        json!({"devices": [], "error": "no SidecarCore"})
+    );
+}
+
+#[test]
+fn sections_not_asked_for_are_left_out() {
+    let report = Report::new(None, Some(Ok(Vec::new())));
+    let value = serde_json::to_value(&report).unwrap();
+    assert!(value.get("thunderbolt").is_none());
+    assert_eq!(value["sidecar"], json!({"devices": []}));
+    assert!(!report.failed());
+}
+
+#[test]
+fn yaml_has_the_same_fields() {
+    let report = Report::new(Some(Ok(vec![dock()])), None);
+    let yaml = serde_norway::to_string(&report).unwrap();
+    assert!(yaml.starts_with("schema_version: 1\n"), "{yaml}");
+    assert!(yaml.contains("uid: '0x003DA86E85A8CB00'"), "{yaml}");
+}
+
+#[test]
+fn flat_layout_drops_the_mac_unless_asked() {
+    let flat = discovery::arrange(topology(), Layout::default());
+    assert_eq!(flat, vec![dock()]);
+
+    let all = discovery::arrange(
+        topology(),
+        Layout {
+            tree: false,
+            all: true,
+        },
+    );
+    let names: Vec<_> = all.iter().map(|d| d.name.as_str()).collect();
+    assert_eq!(names, ["MacBook Pro", "TS4"]);
+    assert!(all.iter().all(|d| d.children.is_empty()));
+}
+
+#[test]
+fn tree_layout_promotes_devices_behind_the_mac() {
+    let tree = Layout {
+        tree: true,
+        all: false,
+    };
+    assert_eq!(discovery::arrange(topology(), tree), vec![dock()]);
+
+    let all = Layout {
+        tree: true,
+        all: true,
+    };
+    assert_eq!(discovery::arrange(topology(), all), topology());
+}
+
+#[test]
+fn table_indents_the_daisy_chain() {
+    let mut mac = topology();
+    mac[0].children[0].link_speed = Some("Up to 40 Gb/s".into());
+    mac[0].children[0].link_width = Some(2);
+    mac[0].children[0].firmware = Some("44.1".into());
+    let table = Report::new(Some(Ok(mac)), None).to_string();
+    assert!(
+        table.contains("  Name:     MacBook Pro (this Mac)\n"),
+        "{table}"
+    );
+    assert!(table.contains("      Name:     TS4\n"), "{table}");
+    assert!(
+        table.contains("      Link:     Up to 40 Gb/s, x2\n"),
+        "{table}"
+    );
+    assert!(table.contains("      Firmware: 44.1\n"), "{table}");
+}
+
+#[test]
+fn table_lists_devices_and_failures() {
+    let report = Report::new(Some(Ok(vec![dock()])), Some(Err("no SidecarCore".into())));
+    let table = report.to_string();
+    assert!(
+        table.contains("  UID:      0x003DA86E85A8CB00\n"),
+        "{table}"
+    );
+    assert!(
+        table.contains("  Route:    1 (depth 1, port 1)\n"),
+        "{table}"
+    );
+    assert!(table.contains("  (failed: no SidecarCore)"), "{table}");
+    assert!(table.contains("sidecar-on-dock init"), "{table}");
+}
+
+// --- captured dumps ---
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/discovery")
+}
+
+#[test]
+fn every_fixture_gives_its_expected_devices() {
+    let mut checked = 0;
+    for entry in fs::read_dir(fixtures()).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "xml") {
+            continue;
+        }
+        let devices = discovery::thunderbolt_devices_from_bytes(&fs::read(&path).unwrap())
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+        let expected: serde_json::Value = serde_json::from_str(
+            &fs::read_to_string(path.with_extension("expected.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&devices).unwrap(),
+            expected,
+            "{}",
+            path.display()
+        );
+
+        let tree_path = path.with_extension("tree.json");
+        if tree_path.exists() {
+            let tree = discovery::thunderbolt_tree_from_bytes(&fs::read(&path).unwrap()).unwrap();
+            let expected: serde_json::Value =
+                serde_json::from_str(&fs::read_to_string(&tree_path).unwrap()).unwrap();
+            assert_eq!(
+                serde_json::to_value(&tree).unwrap(),
+                expected,
+                "{}",
+                tree_path.display()
+            );
+        }
+        checked += 1;
+    }
+    assert!(checked >= 5, "only {checked} fixtures found");
+}
+
+#[test]
+fn ioreg_and_system_profiler_agree() {
+    let read = |name: &str| {
+        let bytes = fs::read(fixtures().join(name)).unwrap();
+        discovery::thunderbolt_devices_from_bytes(&bytes).unwrap()
+    };
+    assert_eq!(
+        read("ioreg_tree.xml"),
+        read("system_profiler_single_dock.xml")
+    );
+}
+
+#[test]
+fn binary_plist_dumps_are_read() {
+    let xml = fs::read(fixtures().join("system_profiler_single_dock.xml")).unwrap();
+    let value = plist::Value::from_reader_xml(&xml[..]).unwrap();
+    let mut binary = Vec::new();
+    value.to_writer_binary(&mut binary).unwrap();
+    let devices = discovery::thunderbolt_devices_from_bytes(&binary).unwrap();
+    let expected = ThunderboltDevice {
+        link_speed: Some("Up to 40 Gb/s".into()),
+        link_width: Some(2),
+        firmware: Some("44.1".into()),
+        ..dock()
+    };
+    assert_eq!(devices, vec![expected]);
+}
+
+#[test]
+fn garbage_is_an_error() {
+    let err = discovery::thunderbolt_devices_from_bytes(b"Thunderbolt/USB4:\n  TS4").unwrap_err();
+    assert!(err.contains("plist"), "{err}");
+}
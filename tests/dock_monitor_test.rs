@@ -0,0 +1,351 @@
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use sidecar_on_dock::config::{Profile, ProfileOptions};
+use sidecar_on_dock::dock_event::{self, DockEvent, DockEventSource, ReplaySource, SleepGuard};
+use sidecar_on_dock::dock_monitor;
+use sidecar_on_dock::sidecar::SidecarBackend;
+use sidecar_on_dock::sidecar_fake::{Call, FakeSidecar};
+
+const DOCK: &str = "0x003DA86E85A8CB00";
+const OTHER_DOCK: &str = "0x00AAAAAAAAAAAAAA";
+
+/// A profile that acts immediately and makes a single connect attempt.
+fn profile(dock_uid: &str, ipad_name: &str) -> Profile {
+    Profile {
+        name: None,
+        dock_uid: dock_uid.into(),
+        ipad_name: Some(ipad_name.into()),
+        options: ProfileOptions {
+            connect_retries: 1,
+            retry_delay_secs: 0,
+            settle_ms: 0,
+            grace_ms: 0,
+            ..Default::default()
+        },
+    }
+}
+
+/// Run the monitor until `source` is exhausted and every profile has settled.
+fn run(profiles: Vec<Profile>, source: impl DockEventSource + 'static, fake: &FakeSidecar) {
+    let (tx, rx) = mpsc::channel();
+    let backend = fake.clone();
+    thread::spawn(move || {
+        tx.send(dock_monitor::run(
+            profiles,
+            Box::new(source),
+            Box::new(backend),
+        ))
+        .unwrap()
+    });
+    rx.recv_timeout(Duration::from_secs(10))
+        .expect("monitor did not finish")
+        .unwrap();
+}
+
+fn replay(script: &str) -> ReplaySource {
+    ReplaySource::parse(script).unwrap()
+}
+
+#[test]
+fn dock_cycle_connects_then_disconnects() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let script = format!(
+        r#"{{"appeared": {{"uid": "{DOCK}"}}}}
+           {{"wait": {{"ms": 50}}}}
+           {{"removed": {{"uid": "{DOCK}"}}}}"#
+    );
+    run(vec![profile(DOCK, "My iPad")], replay(&script), &fake);
+    assert_eq!(
+        fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Disconnect("My iPad".into())
+        ]
+    );
+    assert!(fake.connected_devices().unwrap().is_empty());
+}
+
+#[test]
+fn other_docks_are_ignored() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let script = format!(r#"{{"appeared": {{"uid": "{OTHER_DOCK}"}}}}"#);
+    run(vec![profile(DOCK, "My iPad")], replay(&script), &fake);
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn unidentified_removal_disconnects() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let script = format!(
+        r#"{{"appeared": {{"uid": "{DOCK}"}}}}
+           {{"wait": {{"ms": 50}}}}
+           {{"removed": {{}}}}"#
+    );
+    run(vec![profile(DOCK, "My iPad")], replay(&script), &fake);
+    assert_eq!(
+        fake.calls().last(),
+        Some(&Call::Disconnect("My iPad".into()))
+    );
+}
+
+#[test]
+fn late_ipad_is_connected_by_a_retry() {
+    let fake = FakeSidecar::new();
+    fake.device_appears_after("My iPad", 3);
+    let script = format!(r#"{{"appeared": {{"uid": "{DOCK}"}}}}"#);
+    let mut profile = profile(DOCK, "My iPad");
+    profile.options.connect_retries = 5;
+    run(vec![profile], replay(&script), &fake);
+    assert_eq!(
+        fake.connected_devices().unwrap(),
+        vec!["My iPad".to_string()]
+    );
+}
+
+#[test]
+fn gives_up_once_retries_are_exhausted() {
+    let fake = FakeSidecar::new();
+    fake.device_appears_after("My iPad", 5);
+    let script = format!(r#"{{"appeared": {{"uid": "{DOCK}"}}}}"#);
+    let mut profile = profile(DOCK, "My iPad");
+    profile.options.connect_retries = 3;
+    run(vec![profile], replay(&script), &fake);
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn replug_within_grace_keeps_the_session() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let script = format!(
+        r#"{{"appeared": {{"uid": "{DOCK}"}}}}
+           {{"wait": {{"ms": 50}}}}
+           {{"removed": {{"uid": "{DOCK}"}}}}
+           {{"wait": {{"ms": 20}}}}
+           {{"appeared": {{"uid": "{DOCK}"}}}}"#
+    );
+    let mut profile = profile(DOCK, "My iPad");
+    profile.options.grace_ms = 500;
+    run(vec![profile], replay(&script), &fake);
+    assert_eq!(fake.calls(), vec![Call::Connect("My iPad".into())]);
+}
+
+#[test]
+fn each_profile_follows_its_own_dock() {
+    let fake = FakeSidecar::with_devices(&["Desk iPad", "Studio iPad"]);
+    let script = format!(r#"{{"appeared": {{"uid": "{OTHER_DOCK}"}}}}"#);
+    let profiles = vec![
+        profile(DOCK, "Desk iPad"),
+        profile(OTHER_DOCK, "Studio iPad"),
+    ];
+    run(profiles, replay(&script), &fake);
+    assert_eq!(fake.calls(), vec![Call::Connect("Studio iPad".into())]);
+}
+
+#[test]
+fn channel_source_ends_when_sender_is_dropped() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let (tx, source) = dock_event::channel();
+    tx.send(DockEvent::Appeared {
+        uid: Some(0x003DA86E85A8CB00),
+        properties: Default::default(),
+    })
+    .unwrap();
+    drop(tx);
+    run(vec![profile(DOCK, "My iPad")], source, &fake);
+    assert_eq!(
+        fake.connected_devices().unwrap(),
+        vec!["My iPad".to_string()]
+    );
+}
+
+#[test]
+fn replay_reports_uid_and_properties() {
+    let script = format!(
+        r#"# recorded session
+           {{"appeared": {{"uid": "{DOCK}", "properties": {{"device_name": "TS4"}}}}}}
+
+           {{"removed": {{}}}}"#
+    );
+    let mut source = replay(&script);
+    assert_eq!(
+        source.next_event(),
+        Some(DockEvent::Appeared {
+            uid: Some(0x003DA86E85A8CB00),
+            properties: [("device_name".to_string(), "TS4".to_string())].into(),
+        })
+    );
+    assert_eq!(
+        source.next_event(),
+        Some(DockEvent::Removed {
+            uid: None,
+            properties: Default::default(),
+        })
+    );
+    assert_eq!(source.next_event(), None);
+}
+
+#[test]
+fn replay_rejects_invalid_uid_with_line_number() {
+    let err = ReplaySource::parse("\n{\"appeared\": {\"uid\": \"nothex\"}}").unwrap_err();
+    assert!(err.starts_with("line 2:"), "{err}");
+}
+
+#[test]
+fn replay_rejects_unknown_steps() {
+    assert!(ReplaySource::parse(r#"{"plugged": {}}"#).is_err());
+}
+
+#[test]
+fn hooks_run_for_dock_and_sidecar_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log");
+    let record = |event: &str| {
+        let command = format!(
+            r#"echo "{event} $PROFILE $DOCK_UID $IPAD_NAME" >> "{}""#,
+            log.display()
+        );
+        Some(serde_json::from_value(serde_json::Value::String(command)).unwrap())
+    };
+    let mut desk = profile(DOCK, "My iPad");
+    desk.name = Some("desk".into());
+    desk.options.hooks.on_connect = record("connect");
+    desk.options.hooks.on_sidecar_connected = record("sidecar");
+    desk.options.hooks.on_disconnect = record("disconnect");
+
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let script = format!(
+        r#"{{"appeared": {{"uid": "{DOCK}"}}}}
+           {{"wait": {{"ms": 50}}}}
+           {{"removed": {{"uid": "{DOCK}"}}}}"#
+    );
+    run(vec![desk], replay(&script), &fake);
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        format!(
+            "connect desk {DOCK} My iPad\nsidecar desk {DOCK} My iPad\ndisconnect desk {DOCK} My iPad\n"
+        )
+    );
+}
+
+#[test]
+fn failed_connect_runs_the_failure_hook() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("failed");
+    let mut desk = profile(DOCK, "My iPad");
+    desk.options.hooks.on_sidecar_failed = Some(
+        serde_json::from_value(serde_json::json!(format!(
+            r#"touch "{}""#,
+            marker.display()
+        )))
+        .unwrap(),
+    );
+
+    let fake = FakeSidecar::with_devices(&[]);
+    let script = format!(r#"{{"appeared": {{"uid": "{DOCK}"}}}}"#);
+    run(vec![desk], replay(&script), &fake);
+    assert!(marker.exists());
+}
+
+/// Wait until `fake` has received `count` requests.
+fn wait_for_calls(fake: &FakeSidecar, count: usize) {
+    for _ in 0..500 {
+        if fake.calls().len() >= count {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("expected {count} calls, got {:?}", fake.calls());
+}
+
+/// Dock, let the session connect, then sleep and wake with the dock still attached.
+/// `asleep` runs once the monitor is ready for sleep.
+fn sleep_and_wake(desk: Profile, fake: &FakeSidecar, asleep: impl FnOnce() + Send + 'static) {
+    let (tx, source) = dock_event::channel();
+    let driver = {
+        let fake = fake.clone();
+        thread::spawn(move || {
+            tx.send(DockEvent::Appeared {
+                uid: Some(0x003DA86E85A8CB00),
+                properties: Default::default(),
+            })
+            .unwrap();
+            wait_for_calls(&fake, 1);
+            while fake.connected_devices().unwrap().is_empty() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            // Let the monitor hear that Sidecar connected.
+            thread::sleep(Duration::from_millis(50));
+
+            let (guard, released) = SleepGuard::new();
+            tx.send(DockEvent::Sleeping { guard }).unwrap();
+            assert_eq!(
+                released.recv_timeout(Duration::from_secs(5)),
+                Err(mpsc::RecvTimeoutError::Disconnected)
+            );
+            asleep();
+            tx.send(DockEvent::Woke {
+                attached: Some(vec![0x003DA86E85A8CB00]),
+            })
+            .unwrap();
+        })
+    };
+    run(vec![desk], source, fake);
+    driver.join().unwrap();
+}
+
+#[test]
+fn wake_reconnects_a_session_lost_in_sleep() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let lost = fake.clone();
+    sleep_and_wake(profile(DOCK, "My iPad"), &fake, move || {
+        lost.end_session("My iPad")
+    });
+    assert_eq!(
+        fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Connect("My iPad".into())
+        ]
+    );
+}
+
+#[test]
+fn disconnect_on_sleep_holds_sleep_until_sidecar_answers() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    fake.set_delay(Duration::from_millis(100));
+    let mut desk = profile(DOCK, "My iPad");
+    desk.options.disconnect_on_sleep = true;
+    let asleep = fake.clone();
+    sleep_and_wake(desk, &fake, move || {
+        assert_eq!(asleep.connected_devices().unwrap(), Vec::<String>::new())
+    });
+    assert_eq!(
+        fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Disconnect("My iPad".into()),
+            Call::Connect("My iPad".into())
+        ]
+    );
+}
+
+#[test]
+fn dock_removed_during_sleep_disconnects_on_wake() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let script = format!(
+        r#"{{"appeared": {{"uid": "{DOCK}"}}}}
+           {{"wait": {{"ms": 50}}}}
+           {{"sleeping": {{}}}}
+           {{"woke": {{"attached": []}}}}"#
+    );
+    run(vec![profile(DOCK, "My iPad")], replay(&script), &fake);
+    assert_eq!(
+        fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Disconnect("My iPad".into())
+        ]
+    );
+}
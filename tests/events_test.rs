@@ -0,0 +1,190 @@
+#![cfg(feature = "stream")]
+
+use std::future::{Future, poll_fn};
+use std::path::Path;
+use std::pin::{Pin, pin};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use futures_core::Stream;
+use sidecar_on_dock::config::Config;
+use sidecar_on_dock::control::Client;
+use sidecar_on_dock::daemon::{Daemon, Handle};
+use sidecar_on_dock::dock_event::{self, DockEvent};
+use sidecar_on_dock::events::{Event, Events};
+use sidecar_on_dock::sidecar_fake::FakeSidecar;
+
+const DOCK: u64 = 0x003DA86E85A8CB00;
+
+fn config() -> Config {
+    serde_json::from_str(
+        r#"{"profiles": [
+            {"name": "desk", "dock_uid": "0x003DA86E85A8CB00", "ipad_name": "My iPad",
+             "connect_retries": 1, "retry_delay_secs": 0, "settle_ms": 0, "grace_ms": 0}
+        ]}"#,
+    )
+    .unwrap()
+}
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// The next event, polled the way an executor would, or `None` once the stream ends.
+fn next(events: &mut Events) -> Option<Event> {
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut next = pin!(poll_fn(|cx| Pin::new(&mut *events).poll_next(cx)));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Poll::Ready(event) = next.as_mut().poll(&mut cx) {
+            return event;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        assert!(!left.is_zero(), "no event within 5s");
+        thread::park_timeout(left);
+    }
+}
+
+/// Run a daemon listening on `socket`, as the launchd agent does.
+fn start_daemon(socket: &Path) -> (mpsc::Sender<DockEvent>, Handle) {
+    let (docks, source) = dock_event::channel();
+    let builder = Daemon::builder()
+        .config(config())
+        .source(source)
+        .backend(FakeSidecar::with_devices(&["My iPad"]))
+        .reload(|| config().profiles())
+        .control_socket(socket);
+    let handle = builder.handle();
+    let daemon = builder.build().unwrap();
+    thread::spawn(move || daemon.run());
+    (docks, handle)
+}
+
+#[test]
+fn streams_the_running_daemons_events() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("control.sock");
+    let (docks, handle) = start_daemon(&socket);
+    let mut events = Events::connect(&socket).unwrap();
+    assert!(events.handle().is_none());
+
+    docks
+        .send(DockEvent::Appeared {
+            uid: Some(DOCK),
+            properties: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(
+        next(&mut events),
+        Some(Event::DockAppeared {
+            profile: "desk".into()
+        })
+    );
+    assert_eq!(
+        next(&mut events),
+        Some(Event::SidecarConnecting {
+            profile: "desk".into()
+        })
+    );
+    assert_eq!(
+        next(&mut events),
+        Some(Event::SidecarConnected {
+            profile: "desk".into()
+        })
+    );
+
+    docks
+        .send(DockEvent::Removed {
+            uid: Some(DOCK),
+            properties: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(
+        next(&mut events),
+        Some(Event::DockRemoved {
+            profile: "desk".into()
+        })
+    );
+    assert_eq!(
+        next(&mut events),
+        Some(Event::SidecarDisconnected {
+            profile: "desk".into()
+        })
+    );
+
+    Client::open(&socket).unwrap().reload().unwrap();
+    assert_eq!(
+        next(&mut events),
+        Some(Event::ConfigReloaded { profiles: 1 })
+    );
+
+    handle.stop();
+    assert_eq!(next(&mut events), None);
+}
+
+#[test]
+fn dropping_the_stream_leaves_the_daemon_running() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("control.sock");
+    let (_docks, handle) = start_daemon(&socket);
+    drop(Events::connect(&socket).unwrap());
+    assert_eq!(handle.status().unwrap().len(), 1);
+    handle.stop();
+}
+
+#[test]
+fn no_daemon_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = Events::connect(&dir.path().join("control.sock"))
+        .err()
+        .unwrap();
+    assert!(err.contains("Cannot reach the daemon"), "{err}");
+}
+
+#[test]
+fn streams_a_daemon_of_its_own() {
+    let (docks, source) = dock_event::channel();
+    let mut events = Daemon::builder()
+        .config(config())
+        .source(source)
+        .backend(FakeSidecar::new())
+        .events()
+        .unwrap();
+
+    docks
+        .send(DockEvent::Appeared {
+            uid: Some(DOCK),
+            properties: Default::default(),
+        })
+        .unwrap();
+    assert_eq!(
+        next(&mut events),
+        Some(Event::DockAppeared {
+            profile: "desk".into()
+        })
+    );
+    assert_eq!(
+        next(&mut events),
+        Some(Event::SidecarConnecting {
+            profile: "desk".into()
+        })
+    );
+    assert_eq!(
+        next(&mut events),
+        Some(Event::SidecarFailed {
+            profile: "desk".into()
+        })
+    );
+
+    let handle = events.handle().unwrap();
+    drop(events);
+    assert!(handle.status().is_err());
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use sidecar_on_dock::config::{FailurePolicy, Hook};
+use sidecar_on_dock::hooks::{self, HookJob, HookRunner};
+
+fn job(command: &str) -> HookJob {
+    HookJob {
+        label: "desk".into(),
+        event: "on_connect",
+        hook: Hook {
+            command: command.into(),
+            timeout_secs: 5,
+            on_failure: FailurePolicy::Warn,
+        },
+        env: vec![
+            ("DOCK_UID".into(), "0x003DA86E85A8CB00".into()),
+            ("IPAD_NAME".into(), "My iPad".into()),
+        ],
+    }
+}
+
+#[test]
+fn hook_output_is_captured() {
+    let output = hooks::run(&job("echo one; echo two")).unwrap();
+    assert_eq!(output, vec!["one", "two"]);
+}
+
+#[test]
+fn hook_sees_event_details_in_env() {
+    let output = hooks::run(&job(r#"echo "$EVENT $DOCK_UID $IPAD_NAME""#)).unwrap();
+    assert_eq!(output, vec!["on_connect 0x003DA86E85A8CB00 My iPad"]);
+}
+
+#[test]
+fn non_zero_exit_is_a_failure() {
+    let err = hooks::run(&job("echo oops >&2; exit 3")).unwrap_err();
+    assert!(err.contains('3'), "{err}");
+}
+
+#[test]
+fn slow_hook_is_killed_at_timeout() {
+    let mut job = job("sleep 10; echo done");
+    job.hook.timeout_secs = 1;
+    let started = Instant::now();
+    let err = hooks::run(&job).unwrap_err();
+    assert!(err.contains("timed out"), "{err}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn background_process_does_not_hold_up_the_hook() {
+    // The background `sleep` keeps the output pipes open after the shell exits.
+    let mut job = job("echo started; sleep 5 &");
+    job.hook.timeout_secs = 1;
+    let started = Instant::now();
+    assert_eq!(hooks::run(&job).unwrap(), vec!["started"]);
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn retry_policy_runs_the_hook_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let count = dir.path().join("count");
+    let mut job = job(&format!(
+        r#"echo x >> "{0}"; [ "$(wc -l < "{0}")" -ge 3 ]"#,
+        count.display()
+    ));
+
+    job.hook.on_failure = FailurePolicy::Retry(1);
+    assert!(hooks::run(&job).is_err());
+    assert_eq!(fs::read_to_string(&count).unwrap().lines().count(), 2);
+
+    job.hook.on_failure = FailurePolicy::Retry(5);
+    assert!(hooks::run(&job).is_ok());
+    assert_eq!(fs::read_to_string(&count).unwrap().lines().count(), 3);
+}
+
+#[test]
+fn runner_finishes_queued_hooks_in_order_on_drop() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log");
+    let runner = HookRunner::start().unwrap();
+    for word in ["first", "second", "third"] {
+        runner.fire(job(&format!(
+            r#"sleep 0.05; echo {word} >> "{}""#,
+            log.display()
+        )));
+    }
+    drop(runner);
+    assert_eq!(fs::read_to_string(&log).unwrap(), "first\nsecond\nthird\n");
+}
@@ -0,0 +1,223 @@
+use std::fs;
+use std::io::Cursor;
+
+use sidecar_on_dock::config::Config;
+use sidecar_on_dock::init::{self, Answers, Dock};
+
+fn docks() -> Vec<Dock> {
+    vec![
+        Dock {
+            name: "TS4".into(),
+            vendor: "CalDigit, Inc.".into(),
+            uid: "0x003DA86E85A8CB00".into(),
+        },
+        Dock {
+            name: "Hub".into(),
+            vendor: "OWC".into(),
+            uid: "0xAA".into(),
+        },
+    ]
+}
+
+fn ipads() -> Vec<String> {
+    vec!["Sam's iPad".into(), "Work iPad".into()]
+}
+
+/// Run the wizard with `typed` as the user's input, returning the config and what was
+/// printed.
+fn run(
+    docks: &[Dock],
+    ipads: &[String],
+    answers: Answers,
+    typed: &str,
+) -> (Result<Config, String>, String) {
+    let mut output = Vec::new();
+    let result = init::build(docks, ipads, answers, &mut Cursor::new(typed), &mut output);
+    (result, String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn picks_dock_and_ipad_from_lists() {
+    let (cfg, printed) = run(&docks(), &ipads(), Answers::default(), "2\n2\n");
+    let cfg = cfg.unwrap();
+    assert_eq!(cfg.dock_uid.as_deref(), Some("0x00000000000000AA"));
+    assert_eq!(cfg.ipad_name.as_deref(), Some("Work iPad"));
+    assert!(
+        printed.contains("1) TS4 (CalDigit, Inc.) 0x003DA86E85A8CB00"),
+        "{printed}"
+    );
+    assert!(printed.contains("0) Whichever is available"), "{printed}");
+}
+
+#[test]
+fn zero_means_any_ipad() {
+    let (cfg, _) = run(&docks(), &ipads(), Answers::default(), "1\n0\n");
+    assert_eq!(cfg.unwrap().ipad_name, None);
+}
+
+#[test]
+fn enter_accepts_the_only_option() {
+    let (cfg, printed) = run(&docks()[..1], &ipads()[..1], Answers::default(), "\n\n");
+    let cfg = cfg.unwrap();
+    assert_eq!(cfg.dock_uid.as_deref(), Some("0x003DA86E85A8CB00"));
+    assert_eq!(cfg.ipad_name.as_deref(), Some("Sam's iPad"));
+    assert!(printed.contains("Which dock? [1]:"), "{printed}");
+}
+
+#[test]
+fn invalid_answers_are_asked_again() {
+    let (cfg, printed) = run(&docks(), &ipads(), Answers::default(), "3\ntwo\n1\n1\n");
+    assert_eq!(cfg.unwrap().dock_uid.as_deref(), Some("0x003DA86E85A8CB00"));
+    assert_eq!(printed.matches("Enter a number from 1 to 2").count(), 2);
+}
+
+#[test]
+fn flags_skip_the_prompts() {
+    let answers = Answers {
+        dock: Some("3da86e85a8cb00".into()),
+        ipad: Some("Sam’s iPad".into()),
+    };
+    let (cfg, printed) = run(&[], &ipads(), answers, "");
+    let cfg = cfg.unwrap();
+    assert_eq!(cfg.dock_uid.as_deref(), Some("0x003DA86E85A8CB00"));
+    // Spelled the way Sidecar reports it.
+    assert_eq!(cfg.ipad_name.as_deref(), Some("Sam's iPad"));
+    assert!(printed.is_empty(), "{printed}");
+}
+
+#[test]
+fn invalid_dock_flag_is_rejected() {
+    let answers = Answers {
+        dock: Some("not hex".into()),
+        ipad: None,
+    };
+    let (cfg, _) = run(&docks(), &ipads(), answers, "");
+    assert!(cfg.unwrap_err().contains("not hex"));
+}
+
+#[test]
+fn no_docks_and_no_answers_fail() {
+    let (cfg, _) = run(&[], &ipads(), Answers::default(), "");
+    assert!(cfg.unwrap_err().contains("--dock"));
+
+    let (cfg, _) = run(&docks(), &ipads(), Answers::default(), "");
+    assert!(cfg.unwrap_err().contains("No answer"));
+}
+
+#[test]
+fn existing_config_needs_force() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("sidecar-on-dock").join("config.json");
+    let answers = Answers {
+        dock: Some("0x1".into()),
+        ipad: None,
+    };
+    let (cfg, _) = run(&[], &[], answers, "");
+    let cfg = cfg.unwrap();
+
+    init::write(&cfg, &path, false).unwrap();
+    let written = Config::load(&path).unwrap();
+    assert_eq!(written.dock_uid.as_deref(), Some("0x0000000000000001"));
+    assert_eq!(written.profiles().unwrap().len(), 1);
+
+    fs::write(&path, "{}").unwrap();
+    let err = init::write(&cfg, &path, false).unwrap_err();
+    assert!(err.contains("--force"), "{err}");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+    init::write(&cfg, &path, true).unwrap();
+    assert!(Config::load(&path).is_ok());
+}
+
+// --- discover --identify ---
+
+fn dock(name: &str, uid: &str) -> Dock {
+    Dock {
+        name: name.into(),
+        vendor: "CalDigit, Inc.".into(),
+        uid: uid.into(),
+    }
+}
+
+#[test]
+fn changes_list_plugged_then_unplugged() {
+    let before = vec![dock("Hub", "0x1"), dock("Display", "0x2")];
+    let after = vec![
+        dock("Display", "0x2"),
+        dock("TS4", "0x3"),
+        dock("TS4", "0x4"),
+    ];
+    let changes = init::changed_docks(&before, &after);
+    let summary: Vec<_> = changes
+        .iter()
+        .map(|c| (c.dock.uid.as_str(), c.attached))
+        .collect();
+    assert_eq!(summary, vec![("0x3", true), ("0x4", true), ("0x1", false)]);
+}
+
+#[test]
+fn identify_waits_until_something_changes() {
+    let mut snapshots = vec![
+        vec![dock("Hub", "0x1")],
+        vec![dock("Hub", "0x1")],
+        vec![dock("Hub", "0x1"), dock("TS4", "0x3")],
+    ]
+    .into_iter();
+    let mut output = Vec::new();
+    let changes = init::identify(
+        || Ok(snapshots.next().unwrap()),
+        &mut Cursor::new("\n\n"),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(changes.len(), 1);
+    assert!(changes[0].attached);
+    let printed = String::from_utf8(output).unwrap();
+    assert!(printed.contains("Nothing changed yet"), "{printed}");
+    assert!(
+        printed.contains("Plugged in:\n  TS4 (CalDigit, Inc.) 0x3"),
+        "{printed}"
+    );
+}
+
+#[test]
+fn identified_uid_is_saved_to_a_new_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    let changes = init::changed_docks(&[], &[dock("TS4", "0x3DA8"), dock("TS4", "0x3DA9")]);
+    let saved =
+        init::offer_to_save(&changes, &path, &mut Cursor::new("2\n"), &mut Vec::new()).unwrap();
+    assert!(saved);
+    let cfg = Config::load(&path).unwrap();
+    assert_eq!(cfg.dock_uid.as_deref(), Some("0x0000000000003DA9"));
+}
+
+#[test]
+fn identified_uid_updates_the_chosen_profile() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(
+        &path,
+        r#"{"profiles": [
+            {"name": "home", "dock_uid": "0x1", "ipad_name": "Home iPad"},
+            {"name": "office", "dock_uid": "0x2"}
+        ]}"#,
+    )
+    .unwrap();
+    let changes = init::changed_docks(&[dock("TS4", "0xAB")], &[]);
+    init::offer_to_save(&changes, &path, &mut Cursor::new("\n2\n"), &mut Vec::new()).unwrap();
+    let profiles = Config::load(&path).unwrap().profiles().unwrap();
+    assert_eq!(profiles[0].dock_uid, "0x1");
+    assert_eq!(profiles[0].ipad_name.as_deref(), Some("Home iPad"));
+    assert_eq!(profiles[1].dock_uid, "0x00000000000000AB");
+}
+
+#[test]
+fn declining_to_save_leaves_the_config_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    let changes = init::changed_docks(&[], &[dock("TS4", "0x3")]);
+    let saved =
+        init::offer_to_save(&changes, &path, &mut Cursor::new("0\n"), &mut Vec::new()).unwrap();
+    assert!(!saved);
+    assert!(!path.exists());
+}
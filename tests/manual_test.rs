@@ -0,0 +1,220 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use serde_json::json;
+use sidecar_on_dock::control::{ProfileStatus, Request, Response, Server};
+use sidecar_on_dock::manual::{self, Action, Direct, Failure, Outcome, Status, Via};
+use sidecar_on_dock::sidecar::{NoSidecar, SidecarBackend, SidecarError};
+use sidecar_on_dock::sidecar_fake::{Call, FakeSidecar};
+
+fn direct(fake: &FakeSidecar, ipad: Option<&str>) -> impl FnOnce() -> Result<Direct, Failure> {
+    let backend = fake.clone();
+    let ipad_name = ipad.map(Into::into);
+    move || {
+        Ok(Direct {
+            backend: Box::new(backend),
+            ipad_name,
+        })
+    }
+}
+
+fn no_daemon() -> PathBuf {
+    PathBuf::from("/nonexistent/sidecar-on-dock/control.sock")
+}
+
+fn unused() -> Result<Direct, Failure> {
+    panic!("the daemon should have been used")
+}
+
+/// A daemon stand-in whose only profile has Sidecar `connected`. Records every request.
+fn fake_daemon(dir: &Path, connected: bool) -> (PathBuf, mpsc::Receiver<Request>) {
+    let path = dir.join("control.sock");
+    let (tx, requests) = mpsc::channel();
+    Server::bind(&path).unwrap().serve(tx).unwrap();
+    let (seen_tx, seen) = mpsc::channel();
+    thread::spawn(move || {
+        for incoming in requests {
+            let response = match incoming.request {
+                Request::Status => Response::Status {
+                    profiles: vec![ProfileStatus {
+                        name: "desk".into(),
+                        dock_uid: "0x003DA86E85A8CB00".into(),
+                        ipad_name: Some("My iPad".into()),
+                        state: "connected".into(),
+                        dock_present: true,
+                        sidecar_connected: connected,
+                    }],
+                },
+                Request::Connect { .. } if connected => Response::error("already"),
+                _ => Response::Ok,
+            };
+            seen_tx.send(incoming.request).unwrap();
+            incoming.reply.send(response).unwrap();
+        }
+    });
+    (path, seen)
+}
+
+// --- without a daemon ---
+
+#[test]
+fn connect_directly_without_daemon() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let outcome =
+        manual::perform(Action::Connect, None, &no_daemon(), direct(&fake, None)).unwrap();
+    assert_eq!(
+        outcome,
+        Outcome {
+            action: Action::Connect,
+            via: Via::Direct,
+            profile: None,
+        }
+    );
+    assert_eq!(fake.calls(), vec![Call::Connect("My iPad".into())]);
+}
+
+#[test]
+fn toggle_directly_flips_the_session() {
+    let fake = FakeSidecar::with_devices(&["Other iPad", "My iPad"]);
+    let toggle = || {
+        manual::perform(
+            Action::Toggle,
+            None,
+            &no_daemon(),
+            direct(&fake, Some("My iPad")),
+        )
+        .unwrap()
+        .action
+    };
+    assert_eq!(toggle(), Action::Connect);
+    assert_eq!(toggle(), Action::Disconnect);
+    assert!(fake.connected_devices().unwrap().is_empty());
+}
+
+#[test]
+fn failures_have_distinct_exit_codes() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let missing = manual::perform(
+        Action::Connect,
+        None,
+        &no_daemon(),
+        direct(&fake, Some("Work iPad")),
+    )
+    .unwrap_err();
+    assert_eq!(missing.exit_code(), 4);
+
+    let no_sidecar = || {
+        Ok(Direct {
+            backend: Box::new(NoSidecar),
+            ipad_name: None,
+        })
+    };
+    let unavailable =
+        manual::perform(Action::Disconnect, None, &no_daemon(), no_sidecar).unwrap_err();
+    assert_eq!(unavailable.exit_code(), 3);
+
+    fake.fail_next_connect(SidecarError::Timeout);
+    let failed =
+        manual::perform(Action::Connect, None, &no_daemon(), direct(&fake, None)).unwrap_err();
+    assert_eq!(failed.exit_code(), 1);
+}
+
+#[test]
+fn status_without_daemon_lists_devices() {
+    let fake = FakeSidecar::with_devices(&["My iPad", "Work iPad"]);
+    fake.connect("Work iPad", Box::new(|_| {}));
+    let status = manual::status(&no_daemon(), direct(&fake, None)).unwrap();
+    assert_eq!(
+        serde_json::to_value(&status).unwrap(),
+        json!({"via": "direct", "devices": ["My iPad", "Work iPad"], "connected": ["Work iPad"]})
+    );
+}
+
+// --- with a daemon ---
+
+#[test]
+fn daemon_is_preferred_when_running() {
+    let dir = tempfile::tempdir().unwrap();
+    let (socket, seen) = fake_daemon(dir.path(), false);
+    let outcome = manual::perform(Action::Disconnect, Some("desk"), &socket, unused).unwrap();
+    assert_eq!(outcome.via, Via::Daemon);
+    assert_eq!(
+        seen.recv().unwrap(),
+        Request::Disconnect {
+            profile: Some("desk".into())
+        }
+    );
+}
+
+#[test]
+fn toggle_asks_the_daemon_what_to_do() {
+    let dir = tempfile::tempdir().unwrap();
+    let (socket, seen) = fake_daemon(dir.path(), true);
+    let outcome = manual::perform(Action::Toggle, None, &socket, unused).unwrap();
+    assert_eq!(outcome.action, Action::Disconnect);
+    assert_eq!(seen.recv().unwrap(), Request::Status);
+    assert_eq!(seen.recv().unwrap(), Request::Disconnect { profile: None });
+}
+
+#[test]
+fn daemon_errors_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let (socket, _seen) = fake_daemon(dir.path(), true);
+    let err = manual::perform(Action::Connect, None, &socket, unused).unwrap_err();
+    assert_eq!(err, Failure::Daemon("already".into()));
+    assert_eq!(err.exit_code(), 1);
+}
+
+#[test]
+fn status_from_daemon() {
+    let dir = tempfile::tempdir().unwrap();
+    let (socket, _seen) = fake_daemon(dir.path(), true);
+    let Status::Daemon { profiles } = manual::status(&socket, unused).unwrap() else {
+        panic!("expected the daemon's status");
+    };
+    assert_eq!(profiles[0].name, "desk");
+    assert!(profiles[0].sidecar_connected);
+}
+
+// --- config lookup ---
+
+#[test]
+fn ipad_comes_from_the_named_profile() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    fs::write(
+        &path,
+        r#"{"profiles": [
+            {"name": "home", "dock_uid": "0x1", "ipad_name": "Home iPad"},
+            {"name": "office", "dock_uid": "0x2"}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        manual::ipad_for(&path, Some("home")).unwrap(),
+        Some("Home iPad".into())
+    );
+    assert_eq!(manual::ipad_for(&path, Some("office")).unwrap(), None);
+    assert_eq!(manual::ipad_for(&path, None).unwrap_err().exit_code(), 2);
+    assert!(
+        manual::ipad_for(&path, Some("kitchen"))
+            .unwrap_err()
+            .to_string()
+            .contains("kitchen")
+    );
+}
+
+#[test]
+fn missing_config_means_first_available_ipad() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.json");
+    assert_eq!(manual::ipad_for(&path, None).unwrap(), None);
+    assert_eq!(
+        manual::ipad_for(&path, Some("home"))
+            .unwrap_err()
+            .exit_code(),
+        2
+    );
+}
@@ -1,4 +1,8 @@
-use sidecar_on_dock::sidecar::normalise_quotes;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use sidecar_on_dock::sidecar::{self, SidecarBackend, SidecarError, normalise_quotes};
+use sidecar_on_dock::sidecar_fake::{Call, FakeSidecar};
 
 #[test]
 fn normalise_right_single_quote() {
@@ -29,3 +33,112 @@ fn normalise_no_quotes() {
 fn normalise_empty() {
     assert_eq!(normalise_quotes(""), "");
 }
+
+/// Run `request` and wait for its completion.
+fn outcome(request: impl FnOnce(sidecar::Completion)) -> Result<(), SidecarError> {
+    let (tx, rx) = mpsc::channel();
+    request(Box::new(move |r| tx.send(r).unwrap()));
+    rx.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn connect_matches_name_with_curly_apostrophe() {
+    let fake = FakeSidecar::with_devices(&["Other iPad", "Dominic\u{2019}s iPad"]);
+    let result = outcome(|done| sidecar::connect(&fake, Some("Dominic's iPad"), done));
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        fake.connected_devices().unwrap(),
+        vec!["Dominic\u{2019}s iPad".to_string()]
+    );
+}
+
+#[test]
+fn connect_without_name_uses_first_device() {
+    let fake = FakeSidecar::with_devices(&["First", "Second"]);
+    outcome(|done| sidecar::connect(&fake, None, done)).unwrap();
+    assert_eq!(fake.calls(), vec![Call::Connect("First".into())]);
+}
+
+#[test]
+fn connect_reports_available_devices_when_not_found() {
+    let fake = FakeSidecar::with_devices(&["Other iPad"]);
+    let result = outcome(|done| sidecar::connect(&fake, Some("My iPad"), done));
+    assert_eq!(
+        result,
+        Err(SidecarError::DeviceNotFound {
+            wanted: Some("My iPad".into()),
+            available: vec!["Other iPad".into()],
+        })
+    );
+    assert!(fake.calls().is_empty());
+}
+
+#[test]
+fn late_device_is_found_on_a_later_attempt() {
+    let fake = FakeSidecar::new();
+    fake.device_appears_after("My iPad", 2);
+    for _ in 0..2 {
+        let result = outcome(|done| sidecar::connect(&fake, Some("My iPad"), done));
+        assert!(matches!(result, Err(SidecarError::DeviceNotFound { .. })));
+    }
+    assert_eq!(
+        outcome(|done| sidecar::connect(&fake, Some("My iPad"), done)),
+        Ok(())
+    );
+}
+
+#[test]
+fn injected_failures_are_used_once_in_order() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    let framework = SidecarError::Framework {
+        domain: "SidecarErrorDomain".into(),
+        code: -1,
+        description: "Connection refused".into(),
+    };
+    fake.fail_next_connect(framework.clone());
+    fake.fail_next_connect(SidecarError::Timeout);
+
+    let connect = || outcome(|done| sidecar::connect(&fake, Some("My iPad"), done));
+    assert_eq!(connect(), Err(framework));
+    assert_eq!(connect(), Err(SidecarError::Timeout));
+    assert_eq!(connect(), Ok(()));
+}
+
+#[test]
+fn unavailable_sidecar_is_not_retryable() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    fake.set_unavailable(Some("framework missing"));
+    let error = outcome(|done| sidecar::connect(&fake, None, done)).unwrap_err();
+    assert_eq!(error, SidecarError::Unavailable("framework missing".into()));
+    assert!(!error.is_retryable());
+}
+
+#[test]
+fn delayed_completion_arrives_after_connect_returns() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    fake.set_delay(Duration::from_millis(50));
+    let (tx, rx) = mpsc::channel();
+    sidecar::connect(&fake, None, Box::new(move |r| tx.send(r).unwrap()));
+    assert!(rx.try_recv().is_err());
+    assert!(fake.connected_devices().unwrap().is_empty());
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Ok(()));
+    assert_eq!(
+        fake.connected_devices().unwrap(),
+        vec!["My iPad".to_string()]
+    );
+}
+
+#[test]
+fn disconnect_ends_the_session() {
+    let fake = FakeSidecar::with_devices(&["My iPad"]);
+    outcome(|done| sidecar::connect(&fake, None, done)).unwrap();
+    outcome(|done| sidecar::disconnect(&fake, Some("My iPad"), done)).unwrap();
+    assert!(fake.connected_devices().unwrap().is_empty());
+    assert_eq!(
+        fake.calls(),
+        vec![
+            Call::Connect("My iPad".into()),
+            Call::Disconnect("My iPad".into())
+        ]
+    );
+}
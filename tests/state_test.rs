@@ -0,0 +1,858 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use sidecar_on_dock::state::{Effect, Event, Notice, Policy, State, notices, step};
+
+const NO_DELAY: Policy = Policy {
+    settle: Duration::ZERO,
+    grace: Duration::ZERO,
+    max_attempts: 1,
+    retry_delay: Duration::ZERO,
+    disconnect_on_sleep: false,
+    reconcile: Duration::ZERO,
+    manual_cooldown: None,
+    disconnect_on_shutdown: false,
+};
+
+const CONNECTING: State = State::Connecting {
+    dock_present: true,
+    attempt: 1,
+};
+
+const CONNECTED: State = State::Connected {
+    disconnect_at: None,
+};
+
+/// Feed a sequence of events from `start` with no settle or grace period, returning the
+/// final state and every effect.
+fn run(start: State, events: &[Event]) -> (State, Vec<Effect>) {
+    let now = Instant::now();
+    let mut state = start;
+    let mut all = Vec::new();
+    for &event in events {
+        let (next, effects) = step(&NO_DELAY, state, event, now);
+        state = next;
+        all.extend(effects);
+    }
+    (state, all)
+}
+
+/// A manually advanced clock driving a single state machine.
+struct FakeClock {
+    policy: Policy,
+    now: Cell<Instant>,
+    state: Cell<State>,
+}
+
+impl FakeClock {
+    fn new(settle_ms: u64, grace_ms: u64) -> Self {
+        Self::with_policy(Policy {
+            settle: Duration::from_millis(settle_ms),
+            grace: Duration::from_millis(grace_ms),
+            ..Policy::default()
+        })
+    }
+
+    fn with_policy(policy: Policy) -> Self {
+        Self {
+            policy,
+            now: Cell::new(Instant::now()),
+            state: Cell::new(State::Idle),
+        }
+    }
+
+    fn send(&self, event: Event) -> Vec<Effect> {
+        let (next, effects) = step(&self.policy, self.state.get(), event, self.now.get());
+        self.state.set(next);
+        effects
+    }
+
+    /// Advance time, delivering a tick if a deadline was passed, like the run-loop timer.
+    fn advance(&self, ms: u64) -> Vec<Effect> {
+        self.now.set(self.now.get() + Duration::from_millis(ms));
+        match self.state.get().deadline() {
+            Some(deadline) if deadline <= self.now.get() => self.send(Event::Tick),
+            _ => vec![],
+        }
+    }
+}
+
+// --- happy path ---
+
+#[test]
+fn dock_appeared_connects() {
+    let (state, effects) = run(State::Idle, &[Event::DockAppeared]);
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn connect_success_then_removal_disconnects() {
+    let (state, effects) = run(
+        State::Idle,
+        &[
+            Event::DockAppeared,
+            Event::ConnectSucceeded,
+            Event::DockRemoved,
+            Event::DisconnectCompleted,
+        ],
+    );
+    assert_eq!(state, State::Idle);
+    assert_eq!(effects, vec![Effect::Connect, Effect::Disconnect]);
+}
+
+// --- repeated events ---
+
+#[test]
+fn repeated_appear_while_connecting_is_ignored() {
+    let (state, effects) = run(State::Idle, &[Event::DockAppeared, Event::DockAppeared]);
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn repeated_appear_while_connected_is_ignored() {
+    let (state, effects) = run(CONNECTED, &[Event::DockAppeared]);
+    assert_eq!(state, CONNECTED);
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn removal_while_idle_is_ignored() {
+    let (state, effects) = run(State::Idle, &[Event::DockRemoved]);
+    assert_eq!(state, State::Idle);
+    assert!(effects.is_empty());
+}
+
+// --- re-plug during connect / disconnect ---
+
+#[test]
+fn removal_during_connect_disconnects_on_success() {
+    let (state, effects) = run(CONNECTING, &[Event::DockRemoved, Event::ConnectSucceeded]);
+    assert_eq!(
+        state,
+        State::Disconnecting {
+            dock_present: false
+        }
+    );
+    assert_eq!(effects, vec![Effect::Disconnect]);
+}
+
+#[test]
+fn replug_during_connect_keeps_session() {
+    let (state, effects) = run(
+        CONNECTING,
+        &[
+            Event::DockRemoved,
+            Event::DockAppeared,
+            Event::ConnectSucceeded,
+        ],
+    );
+    assert_eq!(state, CONNECTED);
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn removal_during_connect_then_failure_is_idle() {
+    let (state, _) = run(
+        CONNECTING,
+        &[Event::DockRemoved, Event::ConnectFailed { retryable: true }],
+    );
+    assert_eq!(state, State::Idle);
+}
+
+#[test]
+fn replug_during_disconnect_reconnects() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[
+            Event::DockRemoved,
+            Event::DockAppeared,
+            Event::DisconnectCompleted,
+        ],
+    );
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Disconnect, Effect::Connect]);
+}
+
+// --- unreadable UID on removal ---
+
+#[test]
+fn unidentified_removal_disconnects_active_session() {
+    let (state, effects) = run(CONNECTED, &[Event::UnidentifiedRemoval]);
+    assert_eq!(
+        state,
+        State::Disconnecting {
+            dock_present: false
+        }
+    );
+    assert_eq!(effects, vec![Effect::Disconnect]);
+}
+
+#[test]
+fn unidentified_removal_while_idle_is_ignored() {
+    let (state, effects) = run(State::Idle, &[Event::UnidentifiedRemoval]);
+    assert_eq!(state, State::Idle);
+    assert!(effects.is_empty());
+}
+
+// --- failure ---
+
+#[test]
+fn failed_connect_waits_for_next_dock_cycle() {
+    let now = Instant::now();
+    let (state, _) = step(
+        &NO_DELAY,
+        CONNECTING,
+        Event::ConnectFailed { retryable: true },
+        now,
+    );
+    assert_eq!(state, State::Failed { since: now });
+
+    let (state, effects) = step(&NO_DELAY, state, Event::DockAppeared, now);
+    assert!(matches!(state, State::Failed { .. }));
+    assert!(effects.is_empty());
+
+    let (state, _) = step(&NO_DELAY, state, Event::DockRemoved, now);
+    let (state, effects) = step(&NO_DELAY, state, Event::DockAppeared, now);
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn success_after_a_timeout_is_connected() {
+    let (state, effects) = run(
+        CONNECTING,
+        &[
+            Event::ConnectFailed { retryable: false },
+            Event::ConnectSucceeded,
+        ],
+    );
+    assert_eq!(state, CONNECTED);
+    assert!(effects.is_empty());
+    assert_eq!(
+        notices(
+            State::Failed {
+                since: Instant::now()
+            },
+            state
+        ),
+        vec![Notice::SidecarConnected]
+    );
+}
+
+#[test]
+fn success_after_a_timeout_without_the_dock_disconnects() {
+    let (state, effects) = run(
+        CONNECTING,
+        &[
+            Event::ConnectFailed { retryable: true },
+            Event::DockRemoved,
+            Event::ConnectSucceeded,
+        ],
+    );
+    assert_eq!(
+        state,
+        State::Disconnecting {
+            dock_present: false
+        }
+    );
+    assert_eq!(effects, vec![Effect::Disconnect]);
+}
+
+// --- pause / resume ---
+
+#[test]
+fn paused_ignores_dock_changes() {
+    let (state, effects) = run(State::Idle, &[Event::Pause, Event::DockAppeared]);
+    assert_eq!(
+        state,
+        State::Paused {
+            dock_present: true,
+            sidecar_active: false
+        }
+    );
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn resume_while_docked_leaves_sidecar_alone() {
+    let (state, effects) = run(
+        State::Idle,
+        &[Event::Pause, Event::DockAppeared, Event::Resume],
+    );
+    assert_eq!(state, State::DockPresent { next_attempt: None });
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn resume_after_undock_disconnects_session() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[Event::Pause, Event::DockRemoved, Event::Resume],
+    );
+    assert_eq!(
+        state,
+        State::Disconnecting {
+            dock_present: false
+        }
+    );
+    assert_eq!(effects, vec![Effect::Disconnect]);
+}
+
+#[test]
+fn pause_during_connect_tracks_completion() {
+    let (state, _) = run(
+        CONNECTING,
+        &[Event::Pause, Event::ConnectSucceeded, Event::Resume],
+    );
+    assert_eq!(state, CONNECTED);
+}
+
+#[test]
+fn pause_twice_keeps_session_flag() {
+    let (state, _) = run(CONNECTED, &[Event::Pause, Event::Pause]);
+    assert_eq!(
+        state,
+        State::Paused {
+            dock_present: true,
+            sidecar_active: true
+        }
+    );
+}
+
+// --- settle / grace ---
+
+#[test]
+fn connect_waits_for_settle() {
+    let clock = FakeClock::new(1000, 0);
+    assert!(clock.send(Event::DockAppeared).is_empty());
+    assert!(clock.advance(999).is_empty());
+    assert_eq!(clock.advance(1), vec![Effect::Connect]);
+}
+
+#[test]
+fn flapping_dock_never_connects() {
+    let clock = FakeClock::new(1000, 0);
+    for _ in 0..5 {
+        clock.send(Event::DockAppeared);
+        assert!(clock.advance(200).is_empty());
+        clock.send(Event::DockRemoved);
+        assert!(clock.advance(200).is_empty());
+    }
+    assert_eq!(clock.state.get(), State::Idle);
+    assert_eq!(clock.state.get().deadline(), None);
+}
+
+#[test]
+fn repeated_appear_does_not_restart_settle() {
+    let clock = FakeClock::new(1000, 0);
+    clock.send(Event::DockAppeared);
+    clock.advance(600);
+    clock.send(Event::DockAppeared);
+    assert_eq!(clock.advance(400), vec![Effect::Connect]);
+}
+
+#[test]
+fn disconnect_waits_for_grace() {
+    let clock = FakeClock::new(0, 3000);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectSucceeded);
+
+    assert!(clock.send(Event::DockRemoved).is_empty());
+    assert!(clock.advance(2999).is_empty());
+    assert_eq!(clock.advance(1), vec![Effect::Disconnect]);
+}
+
+#[test]
+fn replug_within_grace_keeps_session() {
+    let clock = FakeClock::new(0, 3000);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectSucceeded);
+
+    clock.send(Event::DockRemoved);
+    clock.advance(500);
+    assert!(clock.send(Event::DockAppeared).is_empty());
+    assert!(clock.advance(10_000).is_empty());
+    assert_eq!(clock.state.get(), CONNECTED);
+}
+
+#[test]
+fn early_tick_is_ignored() {
+    let clock = FakeClock::new(1000, 0);
+    clock.send(Event::DockAppeared);
+    assert!(clock.send(Event::Tick).is_empty());
+    assert!(matches!(
+        clock.state.get(),
+        State::DockPresent {
+            next_attempt: Some(_)
+        }
+    ));
+}
+
+#[test]
+fn removal_during_connect_uses_grace() {
+    let clock = FakeClock::new(0, 3000);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::DockRemoved);
+    assert!(clock.send(Event::ConnectSucceeded).is_empty());
+    assert_eq!(clock.advance(3000), vec![Effect::Disconnect]);
+}
+
+// --- retries ---
+
+fn retrying(max_attempts: u32) -> FakeClock {
+    FakeClock::with_policy(Policy {
+        max_attempts,
+        retry_delay: Duration::from_secs(2),
+        ..Policy::default()
+    })
+}
+
+#[test]
+fn failed_attempt_schedules_retry() {
+    let clock = retrying(3);
+    assert_eq!(clock.send(Event::DockAppeared), vec![Effect::Connect]);
+    assert!(
+        clock
+            .send(Event::ConnectFailed { retryable: true })
+            .is_empty()
+    );
+
+    assert!(clock.advance(1999).is_empty());
+    assert_eq!(clock.advance(1), vec![Effect::Connect]);
+    assert_eq!(
+        clock.state.get(),
+        State::Connecting {
+            dock_present: true,
+            attempt: 2
+        }
+    );
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    let clock = retrying(3);
+    clock.send(Event::DockAppeared);
+    for _ in 0..2 {
+        clock.send(Event::ConnectFailed { retryable: true });
+        assert_eq!(clock.advance(2000), vec![Effect::Connect]);
+    }
+    clock.send(Event::ConnectFailed { retryable: true });
+    assert!(matches!(clock.state.get(), State::Failed { .. }));
+    assert!(clock.advance(60_000).is_empty());
+}
+
+#[test]
+fn removal_mid_retry_cancels_connect() {
+    let clock = retrying(3);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectFailed { retryable: true });
+    clock.advance(1000);
+
+    assert!(clock.send(Event::DockRemoved).is_empty());
+    assert_eq!(clock.state.get(), State::Idle);
+    assert!(clock.advance(60_000).is_empty());
+}
+
+#[test]
+fn removal_during_attempt_stops_retrying() {
+    let clock = retrying(3);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::DockRemoved);
+    assert!(
+        clock
+            .send(Event::ConnectFailed { retryable: true })
+            .is_empty()
+    );
+    assert_eq!(clock.state.get(), State::Idle);
+}
+
+#[test]
+fn new_dock_cycle_restarts_attempt_count() {
+    let clock = retrying(2);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectFailed { retryable: true });
+    clock.send(Event::DockRemoved);
+    clock.send(Event::DockAppeared);
+    assert_eq!(clock.state.get(), CONNECTING);
+}
+
+#[test]
+fn non_retryable_failure_gives_up_immediately() {
+    let clock = retrying(3);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectFailed { retryable: false });
+    assert!(matches!(clock.state.get(), State::Failed { .. }));
+}
+
+// --- notices ---
+
+#[test]
+fn dock_arrival_and_connect_are_noticed() {
+    assert_eq!(
+        notices(State::Idle, CONNECTING),
+        vec![Notice::DockConnected]
+    );
+    assert_eq!(
+        notices(CONNECTING, CONNECTED),
+        vec![Notice::SidecarConnected]
+    );
+}
+
+#[test]
+fn removal_is_noticed_when_grace_starts() {
+    let clock = FakeClock::new(0, 1000);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectSucceeded);
+    let before = clock.state.get();
+    clock.send(Event::DockRemoved);
+    assert_eq!(
+        notices(before, clock.state.get()),
+        vec![Notice::DockDisconnected]
+    );
+}
+
+#[test]
+fn giving_up_is_noticed() {
+    let failed = State::Failed {
+        since: Instant::now(),
+    };
+    assert_eq!(notices(CONNECTING, failed), vec![Notice::SidecarFailed]);
+}
+
+#[test]
+fn dock_changes_while_paused_are_not_noticed() {
+    let paused = |dock_present| State::Paused {
+        dock_present,
+        sidecar_active: false,
+    };
+    assert!(notices(paused(false), paused(true)).is_empty());
+    assert!(notices(State::Idle, paused(false)).is_empty());
+}
+
+// --- requests ---
+
+#[test]
+fn connect_request_retries_after_giving_up() {
+    let clock = FakeClock::with_policy(Policy {
+        max_attempts: 3,
+        ..Policy::default()
+    });
+    clock.send(Event::DockAppeared);
+    clock.state.set(State::Failed {
+        since: clock.now.get(),
+    });
+    assert_eq!(clock.send(Event::ConnectRequested), vec![Effect::Connect]);
+    assert_eq!(
+        clock.state.get(),
+        State::Connecting {
+            dock_present: true,
+            attempt: 3
+        }
+    );
+
+    // A requested attempt is not retried.
+    clock.send(Event::ConnectFailed { retryable: true });
+    assert!(matches!(clock.state.get(), State::Failed { .. }));
+}
+
+#[test]
+fn connect_request_without_dock_is_ignored() {
+    let (state, effects) = run(State::Idle, &[Event::ConnectRequested]);
+    assert_eq!(state, State::Idle);
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn disconnect_request_leaves_sidecar_alone_while_docked() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[Event::DisconnectRequested, Event::DisconnectCompleted],
+    );
+    assert_eq!(state, State::DockPresent { next_attempt: None });
+    assert_eq!(effects, vec![Effect::Disconnect]);
+
+    // Only the next dock cycle connects again.
+    let (state, effects) = run(state, &[Event::DockRemoved, Event::DockAppeared]);
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn disconnect_request_cuts_grace_period_short() {
+    let clock = FakeClock::new(0, 5000);
+    clock.send(Event::DockAppeared);
+    clock.send(Event::ConnectSucceeded);
+    clock.send(Event::DockRemoved);
+    assert_eq!(
+        clock.send(Event::DisconnectRequested),
+        vec![Effect::Disconnect]
+    );
+    clock.send(Event::DisconnectCompleted);
+    assert_eq!(clock.state.get(), State::Idle);
+}
+
+#[test]
+fn requests_are_ignored_while_paused() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[
+            Event::Pause,
+            Event::DisconnectRequested,
+            Event::ConnectRequested,
+        ],
+    );
+    assert!(matches!(state, State::Paused { .. }));
+    assert!(effects.is_empty());
+}
+
+// --- sleep and wake ---
+
+#[test]
+fn session_lost_in_sleep_is_reconnected_on_wake() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[
+            Event::Sleep,
+            Event::Woke {
+                sidecar_connected: false,
+            },
+        ],
+    );
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn session_that_survives_sleep_is_kept() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[
+            Event::Sleep,
+            Event::Woke {
+                sidecar_connected: true,
+            },
+        ],
+    );
+    assert_eq!(state, CONNECTED);
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn disconnect_on_sleep_reconnects_on_wake() {
+    let clock = FakeClock::with_policy(Policy {
+        disconnect_on_sleep: true,
+        ..Policy::default()
+    });
+    clock.state.set(CONNECTED);
+    assert_eq!(clock.send(Event::Sleep), vec![Effect::Disconnect]);
+    assert!(clock.send(Event::DisconnectCompleted).is_empty());
+    assert_eq!(clock.state.get().name(), "asleep");
+    let effects = clock.send(Event::Woke {
+        sidecar_connected: false,
+    });
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn dock_removed_during_sleep_disconnects_on_wake() {
+    let (state, effects) = run(
+        CONNECTED,
+        &[
+            Event::Sleep,
+            Event::DockRemoved,
+            Event::Woke {
+                sidecar_connected: true,
+            },
+        ],
+    );
+    assert_eq!(
+        state,
+        State::Disconnecting {
+            dock_present: false
+        }
+    );
+    assert_eq!(effects, vec![Effect::Disconnect]);
+}
+
+#[test]
+fn wake_leaves_a_disconnected_profile_alone() {
+    let left_alone = State::DockPresent { next_attempt: None };
+    let (state, effects) = run(
+        left_alone,
+        &[
+            Event::Sleep,
+            Event::Woke {
+                sidecar_connected: false,
+            },
+        ],
+    );
+    assert_eq!(state, left_alone);
+    assert!(effects.is_empty());
+}
+
+// --- repairs ---
+
+#[test]
+fn repair_reconnects_after_the_delay() {
+    let clock = FakeClock::new(0, 0);
+    clock.state.set(CONNECTED);
+    let after = Duration::from_millis(500);
+    assert!(clock.send(Event::Repair { after }).is_empty());
+    assert!(clock.advance(499).is_empty());
+    assert_eq!(clock.advance(1), vec![Effect::Connect]);
+    assert_eq!(clock.state.get(), CONNECTING);
+}
+
+#[test]
+fn repair_retries_after_giving_up() {
+    let (state, effects) = run(
+        State::Failed {
+            since: Instant::now(),
+        },
+        &[Event::Repair {
+            after: Duration::ZERO,
+        }],
+    );
+    assert!(matches!(
+        state,
+        State::DockPresent {
+            next_attempt: Some(_)
+        }
+    ));
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn repair_is_ignored_unless_sidecar_should_be_connected() {
+    let repair = Event::Repair {
+        after: Duration::ZERO,
+    };
+    for state in [
+        State::Idle,
+        CONNECTING,
+        State::Paused {
+            dock_present: true,
+            sidecar_active: true,
+        },
+    ] {
+        assert_eq!(run(state, &[repair]), (state, vec![]));
+    }
+}
+
+#[test]
+fn repair_delay_backs_off() {
+    let policy = Policy {
+        retry_delay: Duration::from_secs(2),
+        ..Policy::default()
+    };
+    let delays: Vec<_> = (0..4).map(|n| policy.repair_delay(n).as_secs()).collect();
+    assert_eq!(delays, [0, 2, 4, 8]);
+    assert_eq!(policy.repair_delay(30), Duration::from_secs(300));
+    assert_eq!(NO_DELAY.repair_delay(1), Duration::from_secs(1));
+}
+
+// --- changes made by hand ---
+
+#[test]
+fn session_started_by_hand_survives_the_dock() {
+    let (state, effects) = run(
+        State::Idle,
+        &[
+            Event::ConnectedExternally,
+            Event::DockAppeared,
+            Event::DockRemoved,
+        ],
+    );
+    assert_eq!(
+        state,
+        State::External {
+            dock_present: false
+        }
+    );
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn disconnect_by_hand_lasts_until_the_dock_returns() {
+    let (state, effects) = run(CONNECTED, &[Event::DisconnectedExternally]);
+    assert_eq!(state, State::DockPresent { next_attempt: None });
+    assert!(effects.is_empty());
+
+    let (state, effects) = run(state, &[Event::DockRemoved, Event::DockAppeared]);
+    assert_eq!(state, CONNECTING);
+    assert_eq!(effects, vec![Effect::Connect]);
+}
+
+#[test]
+fn disconnect_by_hand_lasts_for_the_cooldown() {
+    let clock = FakeClock::with_policy(Policy {
+        manual_cooldown: Some(Duration::from_secs(60)),
+        ..Policy::default()
+    });
+    clock.state.set(CONNECTED);
+    assert!(clock.send(Event::DisconnectedExternally).is_empty());
+    assert!(clock.advance(59_999).is_empty());
+    assert_eq!(clock.advance(1), vec![Effect::Connect]);
+}
+
+#[test]
+fn session_ended_by_hand_is_not_restarted() {
+    let external = State::External { dock_present: true };
+    let (state, effects) = run(external, &[Event::DisconnectedExternally]);
+    assert_eq!(state, State::DockPresent { next_attempt: None });
+    assert!(effects.is_empty());
+}
+
+// --- shutdown ---
+
+#[test]
+fn shutdown_leaves_the_session_by_default() {
+    let (state, effects) = run(CONNECTED, &[Event::Shutdown]);
+    assert_eq!(state, CONNECTED);
+    assert!(effects.is_empty());
+}
+
+#[test]
+fn disconnect_on_shutdown_ends_the_session() {
+    let clock = FakeClock::with_policy(Policy {
+        disconnect_on_shutdown: true,
+        ..Policy::default()
+    });
+    clock.state.set(CONNECTED);
+    assert_eq!(clock.send(Event::Shutdown), vec![Effect::Disconnect]);
+    assert!(clock.send(Event::DisconnectCompleted).is_empty());
+    assert_eq!(clock.state.get(), State::Idle);
+}
+
+#[test]
+fn disconnect_on_shutdown_ends_a_session_still_connecting() {
+    let clock = FakeClock::with_policy(Policy {
+        disconnect_on_shutdown: true,
+        grace: Duration::from_secs(5),
+        ..Policy::default()
+    });
+    clock.state.set(CONNECTING);
+    assert!(clock.send(Event::Shutdown).is_empty());
+    assert!(clock.send(Event::ConnectSucceeded).is_empty());
+    assert_eq!(clock.send(Event::Shutdown), vec![Effect::Disconnect]);
+}
+
+#[test]
+fn shutdown_cancels_scheduled_attempts() {
+    let clock = FakeClock::new(1000, 0);
+    assert!(clock.send(Event::DockAppeared).is_empty());
+    assert!(clock.send(Event::Shutdown).is_empty());
+    assert_eq!(clock.state.get().deadline(), None);
+}
+
+#[test]
+fn shutdown_does_not_reconnect_after_disconnecting() {
+    let (state, effects) = run(
+        State::Disconnecting { dock_present: true },
+        &[Event::Shutdown, Event::DisconnectCompleted],
+    );
+    assert_eq!(state, State::Idle);
+    assert!(effects.is_empty());
+}
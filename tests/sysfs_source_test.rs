@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+
+use sidecar_on_dock::config::Config;
+use sidecar_on_dock::dock_event::{DockEvent, DockEventSource};
+use sidecar_on_dock::sysfs_source::{SysfsSource, read_device, uid_from_unique_id};
+
+/// `unique_id` of a dock whose IOKit UID is 0x003DA86E85A8CB00.
+const DOCK_UNIQUE_ID: &str = "00cba885-6ea8-3d00-ffff-ffffffffffff";
+const DOCK_UID: u64 = 0x003DA86E85A8CB00;
+
+fn add_device(root: &Path, name: &str, unique_id: &str, vendor: &str, device: &str) {
+    let dir = root.join(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("unique_id"), format!("{unique_id}\n")).unwrap();
+    fs::write(dir.join("vendor_name"), format!("{vendor}\n")).unwrap();
+    fs::write(dir.join("device_name"), format!("{device}\n")).unwrap();
+}
+
+/// A fake `/sys/bus/thunderbolt/devices` with a host router, a domain and a service.
+fn fake_sysfs() -> tempfile::TempDir {
+    let root = tempfile::tempdir().unwrap();
+    add_device(
+        root.path(),
+        "0-0",
+        "d1030000-0080-7708-2301-cb85a86e3d00",
+        "Apple Inc.",
+        "Macbook",
+    );
+    fs::create_dir(root.path().join("domain0")).unwrap();
+    fs::create_dir(root.path().join("0-1:1.1")).unwrap();
+    root
+}
+
+fn uids(events: &[DockEvent]) -> Vec<(bool, Option<u64>)> {
+    events
+        .iter()
+        .map(|e| (matches!(e, DockEvent::Appeared { .. }), e.uid()))
+        .collect()
+}
+
+#[test]
+fn unique_id_converts_to_iokit_uid() {
+    assert_eq!(uid_from_unique_id(DOCK_UNIQUE_ID), Some(DOCK_UID));
+    assert_eq!(uid_from_unique_id(" 00cba885-6ea8-3d00 \n"), Some(DOCK_UID));
+}
+
+#[test]
+fn unique_id_rejects_malformed_values() {
+    assert_eq!(uid_from_unique_id("00cba885"), None);
+    assert_eq!(
+        uid_from_unique_id("zzcba885-6ea8-3d00-ffff-ffffffffffff"),
+        None
+    );
+}
+
+#[test]
+fn converted_uid_matches_config() {
+    let cfg: Config = serde_json::from_str(r#"{"dock_uid": "0x003DA86E85A8CB00"}"#).unwrap();
+    assert_eq!(uid_from_unique_id(DOCK_UNIQUE_ID), cfg.dock_uid_u64().ok());
+}
+
+#[test]
+fn read_device_reports_names() {
+    let root = fake_sysfs();
+    add_device(root.path(), "0-1", DOCK_UNIQUE_ID, "CalDigit, Inc.", "TS4");
+    let (uid, properties) = read_device(&root.path().join("0-1")).unwrap();
+    assert_eq!(uid, Some(DOCK_UID));
+    assert_eq!(properties["vendor_name"], "CalDigit, Inc.");
+    assert_eq!(properties["device_name"], "TS4");
+    assert_eq!(properties["unique_id"], DOCK_UNIQUE_ID);
+}
+
+#[test]
+fn read_device_skips_entries_without_unique_id() {
+    let root = fake_sysfs();
+    assert!(read_device(&root.path().join("domain0")).is_none());
+}
+
+#[test]
+fn attached_devices_are_reported_first() {
+    let root = fake_sysfs();
+    add_device(root.path(), "0-1", DOCK_UNIQUE_ID, "CalDigit, Inc.", "TS4");
+    let (tx, rx) = mpsc::channel();
+    let mut source = SysfsSource::new(root.path(), rx);
+
+    let events = vec![source.next_event().unwrap(), source.next_event().unwrap()];
+    assert!(uids(&events).contains(&(true, Some(DOCK_UID))));
+    assert_eq!(events.len(), 2);
+
+    drop(tx);
+    assert_eq!(source.next_event(), None);
+}
+
+#[test]
+fn hotplug_is_reported_after_a_change() {
+    let root = fake_sysfs();
+    let (tx, rx) = mpsc::channel();
+    let mut source = SysfsSource::new(root.path(), rx);
+    assert!(matches!(
+        source.next_event(),
+        Some(DockEvent::Appeared { .. })
+    ));
+
+    add_device(root.path(), "0-1", DOCK_UNIQUE_ID, "CalDigit, Inc.", "TS4");
+    tx.send(()).unwrap();
+    assert_eq!(
+        uids(&[source.next_event().unwrap()]),
+        vec![(true, Some(DOCK_UID))]
+    );
+
+    fs::remove_dir_all(root.path().join("0-1")).unwrap();
+    tx.send(()).unwrap();
+    let Some(DockEvent::Removed { uid, properties }) = source.next_event() else {
+        panic!("expected a removal");
+    };
+    assert_eq!(uid, Some(DOCK_UID));
+    assert_eq!(properties["device_name"], "TS4");
+}
+
+#[test]
+fn device_replaced_at_same_route_is_removed_then_added() {
+    let root = fake_sysfs();
+    add_device(root.path(), "0-1", DOCK_UNIQUE_ID, "CalDigit, Inc.", "TS4");
+    let (tx, rx) = mpsc::channel();
+    let mut source = SysfsSource::new(root.path(), rx);
+    source.next_event();
+    source.next_event();
+
+    add_device(
+        root.path(),
+        "0-1",
+        "01000000-0000-0000-ffff-ffffffffffff",
+        "OWC",
+        "Dock",
+    );
+    tx.send(()).unwrap();
+    let events = vec![source.next_event().unwrap(), source.next_event().unwrap()];
+    assert_eq!(
+        uids(&events),
+        vec![(false, Some(DOCK_UID)), (true, Some(1))]
+    );
+}
+
+#[test]
+fn missing_directory_lists_nothing() {
+    let root = tempfile::tempdir().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let mut source = SysfsSource::new(root.path().join("absent"), rx);
+    drop(tx);
+    assert_eq!(source.next_event(), None);
+}